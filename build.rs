@@ -1,4 +1,6 @@
 extern crate autotools;
+#[cfg(feature = "bindgen")]
+extern crate bindgen;
 extern crate cc;
 extern crate libtor_src;
 
@@ -306,9 +308,52 @@ fn build_tor(libevent: Artifacts) {
     .unwrap();
     println!("cargo:include={}/include", tor.to_str().unwrap());
 
+    #[cfg(feature = "bindgen")]
+    generate_bindings(tor.join("include/tor_api.h"), &target);
+
     println!("cargo:rerun-if-changed=build.rs");
 }
 
+#[cfg(feature = "bindgen")]
+fn generate_bindings(header: PathBuf, target: &str) {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("missing OUT_DIR"));
+
+    let mut builder = bindgen::Builder::default()
+        .header(header.to_str().unwrap())
+        .allowlist_function("tor_.*")
+        .allowlist_type("tor_.*")
+        .allowlist_var("INVALID_TOR_CONTROL_SOCKET")
+        // The opaque configuration type is defined on the Rust side, so that the generated
+        // functions can be compared with the hand-written ones
+        .blocklist_type("tor_main_configuration_t")
+        .layout_tests(true);
+    if target.contains("windows") {
+        // `tor_api.h` expects `SOCKET` to be already defined
+        builder = builder.clang_args(&["-include", "winsock2.h"]);
+    }
+
+    let bindings = builder
+        .generate()
+        .expect("Unable to generate bindings for `tor_api.h`");
+    bindings
+        .write_to_file(out_dir.join("tor_api.rs"))
+        .expect("Cannot write to `OUT_DIR`");
+
+    // List every generated function so that the tests can check that all of them have a
+    // hand-written counterpart
+    let functions = bindings
+        .to_string()
+        .split("pub fn ")
+        .skip(1)
+        .map(|f| f.split('(').next().unwrap().trim().to_string())
+        .collect::<Vec<_>>();
+    fs::write(
+        out_dir.join("tor_api_functions.rs"),
+        format!("check_functions!({})\n", functions.join(", ")),
+    )
+    .expect("Cannot write to `OUT_DIR`");
+}
+
 fn main() {
     let libevent = build_libevent();
     build_tor(libevent);
@@ -0,0 +1,1118 @@
+//! Typed builder for Tor's command line options
+
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Shortest `DormantClientTimeout` accepted by Tor
+const MIN_DORMANT_CLIENT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Listener configuration for options like `SocksPort` and `ControlPort`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Port {
+    /// Disable the listener (`0`)
+    Disabled,
+    /// Let Tor pick a free port (`auto`)
+    Auto,
+    /// Listen on the given port on localhost
+    Local(u16),
+    /// Listen on a specific address
+    Addr(SocketAddr),
+    /// Listen on a unix domain socket
+    Unix(PathBuf),
+}
+
+impl Port {
+    fn render(&self) -> Result<String, Error> {
+        Ok(match self {
+            Port::Disabled => "0".to_string(),
+            Port::Auto => "auto".to_string(),
+            Port::Local(0) => return Err(invalid("Use `Port::Disabled` to disable a listener")),
+            Port::Local(port) => port.to_string(),
+            Port::Addr(addr) if addr.port() == 0 => {
+                return Err(invalid("Use `Port::Auto` to let Tor pick a port"))
+            }
+            Port::Addr(addr) => addr.to_string(),
+            Port::Unix(path) => format!("unix:{}", unix_path(path)?),
+        })
+    }
+}
+
+/// Flag of the `SocksPort` listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SocksPortFlag {
+    /// Report onion service failures with the extended SOCKS5 reply codes `0xF0`-`0xF7`,
+    /// decoded by [`SocksConnector`](crate::socks::SocksConnector).
+    ///
+    /// [`TorConfigBuilder`] sets it by default, see
+    /// [`TorConfigBuilder::socks_extended_errors`].
+    ExtendedErrors,
+    /// Isolate streams opened with different SOCKS credentials, which Tor does by default
+    IsolateSOCKSAuth,
+    /// Isolate streams to different destination addresses
+    IsolateDestAddr,
+    /// Isolate streams to different destination ports
+    IsolateDestPort,
+    /// Keep circuits used with SOCKS credentials alive, since they are likely to be reused
+    KeepAliveIsolateSOCKSAuth,
+}
+
+impl fmt::Display for SocksPortFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SocksPortFlag::ExtendedErrors => "ExtendedErrors",
+            SocksPortFlag::IsolateSOCKSAuth => "IsolateSOCKSAuth",
+            SocksPortFlag::IsolateDestAddr => "IsolateDestAddr",
+            SocksPortFlag::IsolateDestPort => "IsolateDestPort",
+            SocksPortFlag::KeepAliveIsolateSOCKSAuth => "KeepAliveIsolateSOCKSAuth",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Flag of a listener on a unix domain socket, set with [`TorConfigBuilder::socks_socket_flag`]
+/// or [`TorConfigBuilder::control_socket_flag`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnixSocketFlag {
+    /// Make the socket writable by the group of the process
+    GroupWritable,
+    /// Make the socket writable by every user
+    WorldWritable,
+    /// Accept a directory readable by other users, as long as they can't write to it
+    RelaxDirModeCheck,
+}
+
+impl fmt::Display for UnixSocketFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            UnixSocketFlag::GroupWritable => "GroupWritable",
+            UnixSocketFlag::WorldWritable => "WorldWritable",
+            UnixSocketFlag::RelaxDirModeCheck => "RelaxDirModeCheck",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Severity of a `Log` line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogSeverity {
+    Debug,
+    Info,
+    Notice,
+    Warn,
+    Err,
+}
+
+impl fmt::Display for LogSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            LogSeverity::Debug => "debug",
+            LogSeverity::Info => "info",
+            LogSeverity::Notice => "notice",
+            LogSeverity::Warn => "warn",
+            LogSeverity::Err => "err",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Destination of a `Log` line
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LogTarget {
+    Stdout,
+    Stderr,
+    Syslog,
+    File(PathBuf),
+}
+
+/// Log messages of at least `severity` to `target`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Log {
+    pub severity: LogSeverity,
+    pub target: LogTarget,
+}
+
+impl Log {
+    pub fn new(severity: LogSeverity, target: LogTarget) -> Self {
+        Log { severity, target }
+    }
+
+    fn render(&self) -> Result<String, Error> {
+        let target = match &self.target {
+            LogTarget::Stdout => "stdout".to_string(),
+            LogTarget::Stderr => "stderr".to_string(),
+            LogTarget::Syslog => "syslog".to_string(),
+            LogTarget::File(path) => format!("file {}", path_str(path)?),
+        };
+
+        Ok(format!("{} {}", self.severity, target))
+    }
+}
+
+/// Where a hidden service forwards the connections received on a virtual port
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HiddenServiceTarget {
+    /// A port on localhost
+    Port(u16),
+    /// A TCP address
+    Addr(SocketAddr),
+    /// A unix domain socket
+    Unix(PathBuf),
+}
+
+/// Hidden service configured with `HiddenServiceDir` and `HiddenServicePort`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HiddenService {
+    dir: PathBuf,
+    ports: Vec<(u16, HiddenServiceTarget)>,
+}
+
+impl HiddenService {
+    /// Create a hidden service storing its keys and hostname in `dir`
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        HiddenService {
+            dir: dir.into(),
+            ports: Vec::new(),
+        }
+    }
+
+    /// Forward connections to `virtual_port` to `target`
+    pub fn port(mut self, virtual_port: u16, target: HiddenServiceTarget) -> Self {
+        self.ports.push((virtual_port, target));
+        self
+    }
+
+    fn render(&self, args: &mut Vec<String>) -> Result<(), Error> {
+        if self.ports.is_empty() {
+            return Err(invalid(format!(
+                "Hidden service in `{}` has no ports",
+                self.dir.display()
+            )));
+        }
+        push_option(args, "HiddenServiceDir", path_str(&self.dir)?)?;
+
+        for (virtual_port, target) in &self.ports {
+            if *virtual_port == 0 {
+                return Err(invalid("Hidden service virtual ports can't be 0"));
+            }
+            let target = match target {
+                HiddenServiceTarget::Port(0) => {
+                    return Err(invalid("Hidden service target ports can't be 0"))
+                }
+                HiddenServiceTarget::Port(port) => port.to_string(),
+                HiddenServiceTarget::Addr(addr) => addr.to_string(),
+                HiddenServiceTarget::Unix(path) => format!("unix:{}", unix_path(path)?),
+            };
+            push_option(
+                args,
+                "HiddenServicePort",
+                format!("{} {}", virtual_port, target),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builder for the command line arguments passed to Tor.
+///
+/// The values are validated when calling [`build`](TorConfigBuilder::build), which renders them
+/// as a list of `--Option value` arguments (without the program name) that can be passed to
+/// [`TorRunner`](crate::TorRunner).
+///
+/// # Example
+///
+/// ```no_run
+/// # use tor_sys::{Port, TorConfigBuilder, TorRunner};
+/// let args = TorConfigBuilder::new()
+///     .data_directory("/tmp/tor-data")
+///     .socks_port(Port::Local(19050))
+///     .client_only(true)
+///     .build()?;
+/// let handle = TorRunner::spawn(args)?;
+/// # Ok::<(), tor_sys::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TorConfigBuilder {
+    data_directory: Option<PathBuf>,
+    socks_port: Option<Port>,
+    socks_port_flags: Vec<SocksPortFlag>,
+    socks_extended_errors: Option<bool>,
+    socks_socket_flags: Vec<UnixSocketFlag>,
+    control_port: Option<Port>,
+    control_socket_flags: Vec<UnixSocketFlag>,
+    control_port_file: Option<PathBuf>,
+    http_tunnel_port: Option<Port>,
+    dns_port: Option<Port>,
+    cookie_authentication: Option<bool>,
+    logs: Vec<Log>,
+    client_only: Option<bool>,
+    disable_network: Option<bool>,
+    dormant_client_timeout: Option<Duration>,
+    dormant_on_first_startup: Option<bool>,
+    geoip_file: Option<PathBuf>,
+    bridges: Vec<String>,
+    transport_plugins: Vec<(String, String)>,
+    hidden_services: Vec<HiddenService>,
+    options: Vec<(String, String)>,
+}
+
+impl TorConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `DataDirectory`
+    pub fn data_directory<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.data_directory = Some(path.into());
+        self
+    }
+
+    /// Set `SocksPort`
+    pub fn socks_port(mut self, port: Port) -> Self {
+        self.socks_port = Some(port);
+        self
+    }
+
+    /// Add a flag to the `SocksPort`, which must be set and enabled
+    pub fn socks_port_flag(mut self, flag: SocksPortFlag) -> Self {
+        if !self.socks_port_flags.contains(&flag) {
+            self.socks_port_flags.push(flag);
+        }
+        self
+    }
+
+    /// Whether to set the [`SocksPortFlag::ExtendedErrors`] flag on the `SocksPort`, which is
+    /// done by default so that [`ConnectError`](crate::socks::ConnectError) can tell why a
+    /// connection to an onion service failed
+    pub fn socks_extended_errors(mut self, enabled: bool) -> Self {
+        self.socks_extended_errors = Some(enabled);
+        self
+    }
+
+    /// Add a flag to the `SocksPort`, which must be a [`Port::Unix`]
+    pub fn socks_socket_flag(mut self, flag: UnixSocketFlag) -> Self {
+        if !self.socks_socket_flags.contains(&flag) {
+            self.socks_socket_flags.push(flag);
+        }
+        self
+    }
+
+    /// Set `ControlPort`.
+    ///
+    /// A [`Port::Unix`] is equivalent to the `ControlSocket` option.
+    pub fn control_port(mut self, port: Port) -> Self {
+        self.control_port = Some(port);
+        self
+    }
+
+    /// Add a flag to the `ControlPort`, which must be a [`Port::Unix`]
+    pub fn control_socket_flag(mut self, flag: UnixSocketFlag) -> Self {
+        if !self.control_socket_flags.contains(&flag) {
+            self.control_socket_flags.push(flag);
+        }
+        self
+    }
+
+    /// Set `ControlPortWriteToFile`, which makes Tor write the addresses of its control
+    /// listeners to `path`, see [`read_control_port_file`](crate::control::read_control_port_file)
+    pub fn control_port_write_to_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.control_port_file = Some(path.into());
+        self
+    }
+
+    /// Set `HTTPTunnelPort`
+    pub fn http_tunnel_port(mut self, port: Port) -> Self {
+        self.http_tunnel_port = Some(port);
+        self
+    }
+
+    /// Set `DNSPort`
+    pub fn dns_port(mut self, port: Port) -> Self {
+        self.dns_port = Some(port);
+        self
+    }
+
+    /// Set `CookieAuthentication`
+    pub fn cookie_authentication(mut self, enabled: bool) -> Self {
+        self.cookie_authentication = Some(enabled);
+        self
+    }
+
+    /// Add a `Log` line
+    pub fn log(mut self, log: Log) -> Self {
+        self.logs.push(log);
+        self
+    }
+
+    /// Set `ClientOnly`
+    pub fn client_only(mut self, enabled: bool) -> Self {
+        self.client_only = Some(enabled);
+        self
+    }
+
+    /// Set `DisableNetwork`
+    pub fn disable_network(mut self, disabled: bool) -> Self {
+        self.disable_network = Some(disabled);
+        self
+    }
+
+    /// Set `DormantClientTimeout`, after which Tor becomes dormant if it wasn't used by any
+    /// client. Tor requires at least 10 minutes.
+    pub fn dormant_client_timeout(mut self, timeout: Duration) -> Self {
+        self.dormant_client_timeout = Some(timeout);
+        self
+    }
+
+    /// Set `DormantOnFirstStartup`, which makes Tor start dormant the first time it runs with a
+    /// new data directory
+    pub fn dormant_on_first_startup(mut self, enabled: bool) -> Self {
+        self.dormant_on_first_startup = Some(enabled);
+        self
+    }
+
+    /// Set `GeoIPFile`
+    pub fn geoip_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.geoip_file = Some(path.into());
+        self
+    }
+
+    /// Add a `Bridge` line, like `obfs4 192.0.2.1:443 <fingerprint> cert=... iat-mode=0`.
+    ///
+    /// `UseBridges` is enabled automatically when at least one bridge is configured.
+    pub fn bridge<S: Into<String>>(mut self, line: S) -> Self {
+        self.bridges.push(line.into());
+        self
+    }
+
+    /// Add a `ClientTransportPlugin` line running `command` for the comma-separated list of
+    /// `transports`
+    pub fn transport_plugin<T: Into<String>, C: Into<String>>(
+        mut self,
+        transports: T,
+        command: C,
+    ) -> Self {
+        self.transport_plugins
+            .push((transports.into(), command.into()));
+        self
+    }
+
+    /// Add a hidden service
+    pub fn hidden_service(mut self, service: HiddenService) -> Self {
+        self.hidden_services.push(service);
+        self
+    }
+
+    /// Add an arbitrary option not covered by the other methods
+    pub fn option<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.options.push((key.into(), value.into()));
+        self
+    }
+
+    /// Create the directories of the unix socket listeners if they are missing, and check that
+    /// Tor will accept the permissions of the existing ones.
+    ///
+    /// Tor refuses to start if a socket directory isn't owned by the user running it or can be
+    /// accessed by other users, which by default means that it must have mode `0700`:
+    /// [`UnixSocketFlag::GroupWritable`] allows the group to read it, as long as the directory
+    /// belongs to the group of the user or to root, and [`UnixSocketFlag::RelaxDirModeCheck`]
+    /// allows everyone to read it. Directories of [`UnixSocketFlag::WorldWritable`] sockets
+    /// aren't checked at all. The checks use the real user and group of the process, while Tor
+    /// uses those of the `User` option when it's set.
+    /// [`TorRunner::from_config`](crate::TorRunner::from_config) calls this before starting Tor.
+    pub fn prepare_socket_dirs(&self) -> Result<(), Error> {
+        let listeners = [
+            (&self.socks_port, &self.socks_socket_flags),
+            (&self.control_port, &self.control_socket_flags),
+        ];
+        for (port, flags) in listeners.iter() {
+            if let Some(Port::Unix(path)) = port {
+                let dir = path
+                    .parent()
+                    .ok_or_else(|| invalid(format!("Invalid socket path `{}`", path.display())))?;
+                prepare_socket_dir(dir, flags)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the configuration and render it as a list of command line arguments
+    pub fn build(&self) -> Result<Vec<String>, Error> {
+        let mut args = Vec::new();
+
+        if let Some(path) = &self.data_directory {
+            push_option(&mut args, "DataDirectory", path_str(path)?)?;
+        }
+        match &self.socks_port {
+            Some(Port::Disabled) | None if !self.socks_port_flags.is_empty() => {
+                return Err(invalid("SocksPort flags require an enabled SocksPort"))
+            }
+            Some(port) => {
+                let mut flags = self.socks_port_flags.clone();
+                let extended_errors = flags.contains(&SocksPortFlag::ExtendedErrors);
+                match self.socks_extended_errors {
+                    Some(false) if extended_errors => {
+                        return Err(invalid(
+                            "The ExtendedErrors flag conflicts with socks_extended_errors(false)",
+                        ))
+                    }
+                    Some(true) | None if !extended_errors && *port != Port::Disabled => {
+                        flags.insert(0, SocksPortFlag::ExtendedErrors)
+                    }
+                    _ => {}
+                }
+
+                let mut value = port.render()?;
+                for flag in &flags {
+                    value.push_str(&format!(" {}", flag));
+                }
+                value.push_str(&socket_flags("SocksPort", port, &self.socks_socket_flags)?);
+                push_option(&mut args, "SocksPort", value)?;
+            }
+            None if !self.socks_socket_flags.is_empty() => {
+                return Err(invalid("SocksPort socket flags require a unix socket"))
+            }
+            None => {}
+        }
+        match &self.control_port {
+            Some(port) => {
+                let flags = socket_flags("ControlPort", port, &self.control_socket_flags)?;
+                push_option(&mut args, "ControlPort", port.render()? + &flags)?;
+            }
+            None if !self.control_socket_flags.is_empty() => {
+                return Err(invalid("ControlPort socket flags require a unix socket"))
+            }
+            None => {}
+        }
+        if let Some(path) = &self.control_port_file {
+            push_option(&mut args, "ControlPortWriteToFile", path_str(path)?)?;
+        }
+        if let Some(port) = &self.http_tunnel_port {
+            push_option(&mut args, "HTTPTunnelPort", port.render()?)?;
+        }
+        if let Some(port) = &self.dns_port {
+            push_option(&mut args, "DNSPort", port.render()?)?;
+        }
+        if let Some(enabled) = self.cookie_authentication {
+            push_option(&mut args, "CookieAuthentication", bool_str(enabled))?;
+        }
+        for log in &self.logs {
+            push_option(&mut args, "Log", log.render()?)?;
+        }
+        if let Some(enabled) = self.client_only {
+            push_option(&mut args, "ClientOnly", bool_str(enabled))?;
+        }
+        if let Some(disabled) = self.disable_network {
+            push_option(&mut args, "DisableNetwork", bool_str(disabled))?;
+        }
+        if let Some(timeout) = self.dormant_client_timeout {
+            if timeout < MIN_DORMANT_CLIENT_TIMEOUT {
+                return Err(invalid("DormantClientTimeout must be at least 10 minutes"));
+            }
+            push_option(
+                &mut args,
+                "DormantClientTimeout",
+                format!("{} seconds", timeout.as_secs()),
+            )?;
+        }
+        if let Some(enabled) = self.dormant_on_first_startup {
+            push_option(&mut args, "DormantOnFirstStartup", bool_str(enabled))?;
+        }
+        if let Some(path) = &self.geoip_file {
+            push_option(&mut args, "GeoIPFile", path_str(path)?)?;
+        }
+        if !self.bridges.is_empty() {
+            push_option(&mut args, "UseBridges", "1")?;
+        }
+        for bridge in &self.bridges {
+            validate_bridge(bridge)?;
+            push_option(&mut args, "Bridge", bridge.as_str())?;
+        }
+        for (transports, command) in &self.transport_plugins {
+            if transports.is_empty() || !transports.split(',').all(is_transport_name) {
+                return Err(invalid(format!("Invalid transport list `{}`", transports)));
+            }
+            if command.trim().is_empty() {
+                return Err(invalid("Empty transport plugin command"));
+            }
+            push_option(
+                &mut args,
+                "ClientTransportPlugin",
+                format!("{} exec {}", transports, command),
+            )?;
+        }
+        for service in &self.hidden_services {
+            service.render(&mut args)?;
+        }
+        for (key, value) in &self.options {
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(invalid(format!("Invalid option name `{}`", key)));
+            }
+            push_option(&mut args, key, value.as_str())?;
+        }
+
+        Ok(args)
+    }
+}
+
+fn invalid<S: Into<String>>(msg: S) -> Error {
+    Error::InvalidConfig(msg.into())
+}
+
+fn push_option<V: Into<String>>(args: &mut Vec<String>, key: &str, value: V) -> Result<(), Error> {
+    let value = value.into();
+    if value.contains(['\n', '\r', '\0']) {
+        return Err(invalid(format!(
+            "The value of `{}` contains invalid characters",
+            key
+        )));
+    }
+
+    args.push(format!("--{}", key));
+    args.push(value);
+
+    Ok(())
+}
+
+fn bool_str(value: bool) -> &'static str {
+    if value {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str, Error> {
+    match path.to_str() {
+        Some("") => Err(invalid("Empty path")),
+        Some(s) => Ok(s),
+        None => Err(invalid(format!(
+            "Path `{}` is not valid UTF-8",
+            path.display()
+        ))),
+    }
+}
+
+fn unix_path(path: &Path) -> Result<String, Error> {
+    let s = path_str(path)?;
+    if !path.is_absolute() {
+        return Err(invalid(format!("Unix socket path `{}` is not absolute", s)));
+    }
+    if s.contains(['"', '\\']) {
+        return Err(invalid(format!(
+            "Unix socket path `{}` contains invalid characters",
+            s
+        )));
+    }
+
+    // Paths with spaces must be quoted
+    if s.contains(' ') {
+        Ok(format!("\"{}\"", s))
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+#[cfg(unix)]
+fn prepare_socket_dir(dir: &Path, flags: &[UnixSocketFlag]) -> Result<(), Error> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        return Ok(());
+    }
+
+    let metadata = fs::metadata(dir)?;
+    if !metadata.is_dir() {
+        return Err(invalid(format!("`{}` is not a directory", dir.display())));
+    }
+    // Same rules as Tor's `check_location_for_unix_socket()` and `check_private_dir()` in
+    // `lib/fs/dir.c`, which skip the checks entirely for world writable sockets
+    if flags.contains(&UnixSocketFlag::WorldWritable) {
+        return Ok(());
+    }
+    if metadata.uid() != current_uid() {
+        return Err(invalid(format!(
+            "The socket directory `{}` isn't owned by the current user",
+            dir.display()
+        )));
+    }
+    let group_writable = flags.contains(&UnixSocketFlag::GroupWritable);
+    if group_writable && metadata.gid() != current_gid() && metadata.gid() != 0 {
+        return Err(invalid(format!(
+            "The socket directory `{}` isn't owned by the group of the current user or by root",
+            dir.display()
+        )));
+    }
+    let mut forbidden = if group_writable { 0o027 } else { 0o077 };
+    if flags.contains(&UnixSocketFlag::RelaxDirModeCheck) {
+        forbidden &= 0o022;
+    }
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & forbidden != 0 {
+        return Err(invalid(format!(
+            "The socket directory `{}` has mode {:04o}, which Tor considers too permissive",
+            dir.display(),
+            mode
+        )));
+    }
+
+    Ok(())
+}
+
+/// Real user id of the process, which Tor uses instead of the effective one
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    extern "C" {
+        fn getuid() -> u32;
+    }
+
+    // SAFETY: `getuid` has no preconditions and can't fail
+    unsafe { getuid() }
+}
+
+/// Real group id of the process
+#[cfg(unix)]
+fn current_gid() -> u32 {
+    extern "C" {
+        fn getgid() -> u32;
+    }
+
+    // SAFETY: `getgid` has no preconditions and can't fail
+    unsafe { getgid() }
+}
+
+#[cfg(not(unix))]
+fn prepare_socket_dir(dir: &Path, _: &[UnixSocketFlag]) -> Result<(), Error> {
+    Ok(fs::create_dir_all(dir)?)
+}
+
+/// Render the flags of a unix socket listener, which are only accepted for [`Port::Unix`]
+fn socket_flags(key: &str, port: &Port, flags: &[UnixSocketFlag]) -> Result<String, Error> {
+    match port {
+        Port::Unix(_) => Ok(flags.iter().map(|flag| format!(" {}", flag)).collect()),
+        _ if flags.is_empty() => Ok(String::new()),
+        _ => Err(invalid(format!(
+            "{} socket flags require a unix socket",
+            key
+        ))),
+    }
+}
+
+fn is_transport_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check the structure of a `Bridge` line: `[transport] IP:ORPort [fingerprint] [k=v ...]`
+fn validate_bridge(line: &str) -> Result<(), Error> {
+    let err = || invalid(format!("Invalid bridge line `{}`", line));
+
+    let mut parts = line.split_whitespace().peekable();
+    let first = parts.next().ok_or_else(err)?;
+    let addr = if first.parse::<SocketAddr>().is_ok() {
+        first
+    } else if is_transport_name(first) {
+        parts.next().ok_or_else(err)?
+    } else {
+        return Err(err());
+    };
+    if addr.parse::<SocketAddr>().is_err() {
+        return Err(err());
+    }
+
+    if let Some(fingerprint) = parts.peek() {
+        if !fingerprint.contains('=') {
+            let fingerprint = fingerprint.trim_start_matches('$');
+            if fingerprint.len() != 40 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(err());
+            }
+            parts.next();
+        }
+    }
+    if parts.any(|arg| !arg.contains('=')) {
+        return Err(err());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::guard::test_lock;
+    use crate::runner::{TorExit, TorRunner};
+    use crate::test_utils::test_dir;
+    use std::fs;
+
+    const FINGERPRINT: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    /// Run `tor --verify-config` with an empty torrc and the given arguments
+    fn verify_config(dir: &Path, args: Vec<String>) -> TorExit {
+        let torrc = dir.join("torrc");
+        fs::write(&torrc, "").unwrap();
+
+        let mut args = args;
+        args.extend(vec![
+            "-f".to_string(),
+            torrc.to_str().unwrap().to_string(),
+            "--verify-config".to_string(),
+        ]);
+
+        TorRunner::spawn(args).unwrap().join().unwrap()
+    }
+
+    #[test]
+    fn test_render() {
+        let args = TorConfigBuilder::new()
+            .data_directory("/var/lib/tor")
+            .socks_port(Port::Local(9050))
+            .socks_port_flag(SocksPortFlag::ExtendedErrors)
+            .socks_port_flag(SocksPortFlag::IsolateDestAddr)
+            .socks_port_flag(SocksPortFlag::ExtendedErrors)
+            .control_port(Port::Addr("[::1]:9051".parse().unwrap()))
+            .control_port_write_to_file("/var/lib/tor/control-port")
+            .http_tunnel_port(Port::Auto)
+            .dns_port(Port::Local(5353))
+            .cookie_authentication(true)
+            .log(Log::new(LogSeverity::Notice, LogTarget::Stdout))
+            .log(Log::new(
+                LogSeverity::Info,
+                LogTarget::File("/var/log/tor.log".into()),
+            ))
+            .disable_network(false)
+            .dormant_client_timeout(Duration::from_secs(3600))
+            .dormant_on_first_startup(true)
+            .bridge(format!("192.0.2.1:443 {}", FINGERPRINT))
+            .hidden_service(
+                HiddenService::new("/var/lib/tor/hs")
+                    .port(80, HiddenServiceTarget::Port(8080))
+                    .port(
+                        81,
+                        HiddenServiceTarget::Unix("/run/my app/http.sock".into()),
+                    ),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            args,
+            vec![
+                "--DataDirectory",
+                "/var/lib/tor",
+                "--SocksPort",
+                "9050 ExtendedErrors IsolateDestAddr",
+                "--ControlPort",
+                "[::1]:9051",
+                "--ControlPortWriteToFile",
+                "/var/lib/tor/control-port",
+                "--HTTPTunnelPort",
+                "auto",
+                "--DNSPort",
+                "5353",
+                "--CookieAuthentication",
+                "1",
+                "--Log",
+                "notice stdout",
+                "--Log",
+                "info file /var/log/tor.log",
+                "--DisableNetwork",
+                "0",
+                "--DormantClientTimeout",
+                "3600 seconds",
+                "--DormantOnFirstStartup",
+                "1",
+                "--UseBridges",
+                "1",
+                "--Bridge",
+                &format!("192.0.2.1:443 {}", FINGERPRINT),
+                "--HiddenServiceDir",
+                "/var/lib/tor/hs",
+                "--HiddenServicePort",
+                "80 8080",
+                "--HiddenServicePort",
+                "81 unix:\"/run/my app/http.sock\"",
+            ]
+        );
+        assert!(TorConfigBuilder::new().build().unwrap().is_empty());
+
+        // ExtendedErrors is set by default, unless disabled
+        let socks_port = |builder: TorConfigBuilder| builder.build().unwrap()[1].clone();
+        let builder = TorConfigBuilder::new().socks_port(Port::Local(9050));
+        assert_eq!(socks_port(builder.clone()), "9050 ExtendedErrors");
+        assert_eq!(
+            socks_port(builder.clone().socks_extended_errors(true)),
+            "9050 ExtendedErrors"
+        );
+        assert_eq!(socks_port(builder.socks_extended_errors(false)), "9050");
+        assert_eq!(
+            socks_port(
+                TorConfigBuilder::new()
+                    .socks_port(Port::Auto)
+                    .socks_port_flag(SocksPortFlag::IsolateDestPort)
+                    .socks_port_flag(SocksPortFlag::ExtendedErrors)
+            ),
+            "auto IsolateDestPort ExtendedErrors"
+        );
+        assert_eq!(
+            socks_port(TorConfigBuilder::new().socks_port(Port::Disabled)),
+            "0"
+        );
+
+        let args = TorConfigBuilder::new()
+            .socks_port(Port::Unix("/run/app/tor/socks".into()))
+            .socks_socket_flag(UnixSocketFlag::GroupWritable)
+            .control_port(Port::Unix("/run/app/tor/control".into()))
+            .control_socket_flag(UnixSocketFlag::RelaxDirModeCheck)
+            .control_socket_flag(UnixSocketFlag::WorldWritable)
+            .build()
+            .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--SocksPort",
+                "unix:/run/app/tor/socks ExtendedErrors GroupWritable",
+                "--ControlPort",
+                "unix:/run/app/tor/control RelaxDirModeCheck WorldWritable",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let invalid = vec![
+            TorConfigBuilder::new().data_directory(""),
+            TorConfigBuilder::new().socks_port(Port::Local(0)),
+            TorConfigBuilder::new().socks_port_flag(SocksPortFlag::ExtendedErrors),
+            TorConfigBuilder::new()
+                .socks_port(Port::Disabled)
+                .socks_port_flag(SocksPortFlag::ExtendedErrors),
+            TorConfigBuilder::new()
+                .socks_port(Port::Local(9050))
+                .socks_port_flag(SocksPortFlag::ExtendedErrors)
+                .socks_extended_errors(false),
+            TorConfigBuilder::new().control_port(Port::Unix("relative/path".into())),
+            TorConfigBuilder::new().socks_socket_flag(UnixSocketFlag::GroupWritable),
+            TorConfigBuilder::new().dormant_client_timeout(Duration::from_secs(60)),
+            TorConfigBuilder::new()
+                .socks_port(Port::Local(9050))
+                .socks_socket_flag(UnixSocketFlag::GroupWritable),
+            TorConfigBuilder::new()
+                .control_port(Port::Auto)
+                .control_socket_flag(UnixSocketFlag::RelaxDirModeCheck),
+            TorConfigBuilder::new().control_socket_flag(UnixSocketFlag::WorldWritable),
+            TorConfigBuilder::new().dns_port(Port::Addr("127.0.0.1:0".parse().unwrap())),
+            TorConfigBuilder::new().bridge("not a bridge"),
+            TorConfigBuilder::new().bridge("192.0.2.1:443 1234"),
+            TorConfigBuilder::new().bridge("obfs4 192.0.2.1 cert=abc"),
+            TorConfigBuilder::new().transport_plugin("obfs4,", "/usr/bin/obfs4proxy"),
+            TorConfigBuilder::new().hidden_service(HiddenService::new("/var/lib/tor/hs")),
+            TorConfigBuilder::new().hidden_service(
+                HiddenService::new("/var/lib/tor/hs").port(0, HiddenServiceTarget::Port(80)),
+            ),
+            TorConfigBuilder::new().option("Bad Option", "1"),
+            TorConfigBuilder::new().option("Nickname", "a\nSocksPort 1234"),
+        ];
+        for builder in invalid {
+            match builder.build() {
+                Err(Error::InvalidConfig(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", builder, r),
+            }
+        }
+
+        assert!(TorConfigBuilder::new()
+            .bridge(format!(
+                "obfs4 192.0.2.1:443 ${} cert=abc iat-mode=0",
+                FINGERPRINT
+            ))
+            .transport_plugin("obfs4,meek_lite", "/usr/bin/obfs4proxy")
+            .build()
+            .is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_prepare_socket_dirs() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = test_dir("builder-socket-dirs");
+        let set_mode = |mode| fs::set_permissions(&dir, fs::Permissions::from_mode(mode)).unwrap();
+        let socks_dir = dir.join("missing").join("tor");
+        let builder = TorConfigBuilder::new()
+            .socks_port(Port::Unix(socks_dir.join("socks")))
+            .control_port(Port::Unix(dir.join("control")));
+
+        set_mode(0o700);
+        builder.prepare_socket_dirs().unwrap();
+        assert_eq!(
+            fs::metadata(&socks_dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+
+        set_mode(0o750);
+        match builder.prepare_socket_dirs() {
+            Err(Error::InvalidConfig(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        let group = builder
+            .clone()
+            .control_socket_flag(UnixSocketFlag::GroupWritable);
+        group.prepare_socket_dirs().unwrap();
+
+        set_mode(0o755);
+        assert!(group.prepare_socket_dirs().is_err());
+        let relaxed = builder
+            .clone()
+            .control_socket_flag(UnixSocketFlag::RelaxDirModeCheck);
+        relaxed.prepare_socket_dirs().unwrap();
+        assert!(TorRunner::from_config(&relaxed).is_ok());
+        assert!(TorRunner::from_config(&builder).is_err());
+
+        set_mode(0o775);
+        assert!(relaxed.prepare_socket_dirs().is_err());
+        let world = builder
+            .clone()
+            .control_socket_flag(UnixSocketFlag::WorldWritable);
+        world.prepare_socket_dirs().unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_dir_owner() {
+        use std::os::unix::fs::{chown, PermissionsExt};
+
+        let dir = test_dir("builder-socket-owner");
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        // Only root can give a directory away, other users can rely on `/` belonging to root
+        let other = if current_uid() == 0 {
+            chown(&dir, Some(1), None).unwrap();
+            dir.clone()
+        } else {
+            PathBuf::from("/")
+        };
+        let builder = TorConfigBuilder::new().control_port(Port::Unix(other.join("control")));
+        match builder.prepare_socket_dirs() {
+            Err(Error::InvalidConfig(message)) => assert!(message.contains("owned")),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        builder
+            .control_socket_flag(UnixSocketFlag::WorldWritable)
+            .prepare_socket_dirs()
+            .unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_dir_group() {
+        use std::os::unix::fs::{chown, PermissionsExt};
+
+        let dir = test_dir("builder-socket-group");
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o750)).unwrap();
+        let builder = TorConfigBuilder::new()
+            .control_port(Port::Unix(dir.join("control")))
+            .control_socket_flag(UnixSocketFlag::GroupWritable);
+        builder.prepare_socket_dirs().unwrap();
+
+        // Only root can give a directory to a group it isn't a member of
+        if current_uid() == 0 {
+            chown(&dir, None, Some(1)).unwrap();
+            match builder.prepare_socket_dirs() {
+                Err(Error::InvalidConfig(message)) => assert!(message.contains("group")),
+                r => panic!("Unexpected result: {:?}", r),
+            }
+            // The group only matters for group writable sockets
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+            TorConfigBuilder::new()
+                .control_port(Port::Unix(dir.join("control")))
+                .prepare_socket_dirs()
+                .unwrap();
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_config() {
+        let _lock = test_lock();
+
+        let dir = test_dir("builder-verify");
+        let data_dir = test_dir("builder-verify-data");
+
+        // Check every option on its own first, so that a failure points to the culprit
+        let builders = vec![
+            TorConfigBuilder::new().data_directory(&data_dir),
+            TorConfigBuilder::new().socks_port(Port::Auto),
+            TorConfigBuilder::new().socks_port(Port::Addr("127.0.0.1:19050".parse().unwrap())),
+            TorConfigBuilder::new()
+                .socks_port(Port::Local(19052))
+                .socks_port_flag(SocksPortFlag::ExtendedErrors)
+                .socks_port_flag(SocksPortFlag::IsolateSOCKSAuth)
+                .socks_port_flag(SocksPortFlag::IsolateDestAddr)
+                .socks_port_flag(SocksPortFlag::IsolateDestPort)
+                .socks_port_flag(SocksPortFlag::KeepAliveIsolateSOCKSAuth),
+            TorConfigBuilder::new().control_port(Port::Local(19051)),
+            TorConfigBuilder::new()
+                .socks_port(Port::Unix(dir.join("socks")))
+                .socks_socket_flag(UnixSocketFlag::GroupWritable)
+                .control_port(Port::Unix(dir.join("control")))
+                .control_socket_flag(UnixSocketFlag::RelaxDirModeCheck),
+            TorConfigBuilder::new()
+                .control_port(Port::Auto)
+                .control_port_write_to_file(dir.join("control-port")),
+            TorConfigBuilder::new().http_tunnel_port(Port::Auto),
+            TorConfigBuilder::new().dns_port(Port::Auto),
+            TorConfigBuilder::new().cookie_authentication(true),
+            TorConfigBuilder::new().log(Log::new(LogSeverity::Notice, LogTarget::Stdout)),
+            TorConfigBuilder::new().log(Log::new(
+                LogSeverity::Warn,
+                LogTarget::File(dir.join("tor.log")),
+            )),
+            TorConfigBuilder::new().client_only(true),
+            TorConfigBuilder::new().disable_network(true),
+            TorConfigBuilder::new()
+                .dormant_client_timeout(Duration::from_secs(600))
+                .dormant_on_first_startup(false),
+            TorConfigBuilder::new().geoip_file(dir.join("geoip")),
+            TorConfigBuilder::new().bridge(format!("192.0.2.1:443 {}", FINGERPRINT)),
+            TorConfigBuilder::new().hidden_service(
+                HiddenService::new(dir.join("hs"))
+                    .port(80, HiddenServiceTarget::Port(8080))
+                    .port(
+                        443,
+                        HiddenServiceTarget::Addr("127.0.0.1:8443".parse().unwrap()),
+                    ),
+            ),
+        ];
+        let mut all = TorConfigBuilder::new();
+        for builder in builders {
+            let args = builder.build().unwrap();
+            assert_eq!(
+                verify_config(&dir, args.clone()),
+                TorExit::Success,
+                "{:?}",
+                args
+            );
+
+            for pair in args.chunks(2) {
+                all = all.option(pair[0].trim_start_matches("--"), pair[1].as_str());
+            }
+        }
+
+        // And then all of them together
+        assert_eq!(verify_config(&dir, all.build().unwrap()), TorExit::Success);
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! Safe owner of a [`tor_main_configuration_t`]
+
+use std::ffi::CString;
+use std::io;
+use std::os::raw::{c_char, c_int};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::control_socket::{setup_control_socket, ControlStream};
+use crate::error::Error;
+use crate::guard::RunGuard;
+use crate::runner::TorExit;
+use crate::{
+    tor_main_configuration_free, tor_main_configuration_new,
+    tor_main_configuration_set_command_line, tor_main_configuration_t, tor_run_main,
+};
+
+/// Owned Tor configuration, freed with [`tor_main_configuration_free`] when dropped.
+///
+/// The configuration also keeps the storage for the command line alive, since Tor requires the
+/// `argv` pointers to stay valid until the configuration is freed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tor_sys::TorConfiguration;
+/// let mut config = TorConfiguration::new()?;
+/// config.set_command_line(vec!["tor", "--version"])?;
+/// assert!(config.run()?.success());
+/// # Ok::<(), tor_sys::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct TorConfiguration {
+    ptr: NonNull<tor_main_configuration_t>,
+    args: Vec<CString>,
+    argv: Vec<*mut c_char>,
+    running: AtomicBool,
+}
+
+// The configuration is only mutated through `&mut self`, while `tor_run_main` only reads from it
+unsafe impl Send for TorConfiguration {}
+unsafe impl Sync for TorConfiguration {}
+
+impl TorConfiguration {
+    /// Allocate a new configuration with [`tor_main_configuration_new`]
+    pub fn new() -> Result<Self, Error> {
+        let ptr = NonNull::new(unsafe { tor_main_configuration_new() }).ok_or(Error::Allocation)?;
+
+        Ok(TorConfiguration {
+            ptr,
+            args: Vec::new(),
+            argv: Vec::new(),
+            running: AtomicBool::new(false),
+        })
+    }
+
+    /// Set the command line arguments, including the program name in the first position
+    pub fn set_command_line<I, S>(&mut self, args: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Vec<u8>>,
+    {
+        let args = args
+            .into_iter()
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut argv = args
+            .iter()
+            .map(|arg| arg.as_ptr() as *mut c_char)
+            .collect::<Vec<_>>();
+        let argc = argv.len() as c_int;
+        // Like for `main()`, `argv[argc]` is a null pointer
+        argv.push(ptr::null_mut());
+
+        let result = unsafe {
+            tor_main_configuration_set_command_line(self.ptr.as_ptr(), argc, argv.as_mut_ptr())
+        };
+        if result != 0 {
+            return Err(Error::CommandLine);
+        }
+
+        // The old arguments are only released once Tor points to the new ones
+        self.args = args;
+        self.argv = argv;
+
+        Ok(())
+    }
+
+    /// Create the owning control socket for this configuration, see
+    /// [`setup_control_socket`](crate::setup_control_socket)
+    pub fn setup_control_socket(&mut self) -> io::Result<ControlStream> {
+        unsafe { setup_control_socket(self.ptr.as_ptr()) }
+    }
+
+    /// Run Tor with this configuration, blocking until it exits.
+    ///
+    /// Fails with [`Error::AlreadyRunning`] if Tor is already running in this process.
+    pub fn run(&self) -> Result<TorExit, Error> {
+        let guard = RunGuard::acquire()?;
+        Ok(self.run_with_guard(guard))
+    }
+
+    pub(crate) fn run_with_guard(&self, guard: RunGuard) -> TorExit {
+        self.running.store(true, Ordering::SeqCst);
+        let exit = TorExit::from(unsafe { tor_run_main(self.ptr.as_ptr()) });
+        self.running.store(false, Ordering::SeqCst);
+
+        guard.finish(exit);
+        exit
+    }
+
+    /// Returns `true` while Tor is running with this configuration
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Raw pointer to the configuration, valid as long as `self` is alive
+    pub fn as_ptr(&self) -> *const tor_main_configuration_t {
+        self.ptr.as_ptr()
+    }
+
+    /// Mutable raw pointer to the configuration, valid as long as `self` is alive
+    pub fn as_mut_ptr(&mut self) -> *mut tor_main_configuration_t {
+        self.ptr.as_ptr()
+    }
+}
+
+impl Drop for TorConfiguration {
+    fn drop(&mut self) {
+        unsafe { tor_main_configuration_free(self.ptr.as_ptr()) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_command_line() {
+        let mut config = TorConfiguration::new().unwrap();
+        config.set_command_line(vec!["tor", "--version"]).unwrap();
+        assert_eq!(config.args.len(), 2);
+        assert_eq!(config.argv.len(), 3);
+        assert!(config.argv[2].is_null());
+
+        match config.set_command_line(vec!["tor", "--vers\0ion"]) {
+            Err(Error::Nul(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        // The previous command line is left untouched on failure
+        assert_eq!(config.args.len(), 2);
+    }
+
+    #[test]
+    fn test_run() {
+        let _lock = crate::guard::test_lock();
+
+        let mut config = TorConfiguration::new().unwrap();
+        config.set_command_line(vec!["tor", "--version"]).unwrap();
+        assert!(!config.is_running());
+        assert_eq!(config.run().unwrap(), TorExit::Success);
+        assert!(!config.is_running());
+    }
+}
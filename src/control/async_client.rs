@@ -0,0 +1,592 @@
+//! Async version of [`ControlConnection`], available with the `tokio` feature
+
+use std::collections::VecDeque;
+use std::io;
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::auth::{
+    auth_challenge_command, authentication_step, cookie_command, new_client_nonce,
+    password_command, safecookie_command, AuthStep,
+};
+use super::bootstrap::{BootstrapWait, WaitStep, PHASE_KEY};
+use super::conf::{conf_error, get_conf_command, parse_conf_values};
+use super::events::{number, set_events_command, StatusEvent};
+use super::info::{
+    bound_listeners_command, listeners_key, parse_bool, parse_bound_listeners, parse_entry_guard,
+    parse_lines, parse_listeners, parse_microdescriptor, parse_router_status, parse_version,
+};
+use super::network::{dormant_signal, network_changes, signal_command};
+use super::{
+    command_line, get_info_command, info_value, Auth, BootstrapStatus, BoundListeners, CircEvent,
+    ConfChanges, ConfValues, ControlConnection, ControlError, EntryGuard, Event, EventKind,
+    Listener, ListenerKind, Microdescriptor, OrConnEvent, ProtocolInfo, Reply, ReplyParser,
+    RouterStatus, Signal, StreamEvent,
+};
+use crate::control_socket::{AsyncOwningController, OwningController};
+use crate::version::TorVersion;
+
+/// Size of the chunks read from the stream
+const READ_SIZE: usize = 4096;
+
+/// Connection to Tor's control port running on a Tokio stream
+///
+/// It offers the same framing as [`ControlConnection`], along with authentication, events and
+/// bootstrap monitoring. Reading replies and events is cancel safe: a partially received reply
+/// is kept and completed by the next call.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tor_sys::{TorConfigBuilder, TorRunner};
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut handle = TorRunner::new(TorConfigBuilder::new().build()?)
+///     .owning_controller(true)
+///     .start()?;
+/// let mut conn = handle.take_controller().unwrap().into_async()?;
+/// conn.wait_until_bootstrapped(Duration::from_secs(60)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AsyncControlConnection<S: AsyncRead + AsyncWrite + Unpin> {
+    stream: S,
+    buf: Vec<u8>,
+    parser: ReplyParser,
+    events: VecDeque<Reply>,
+}
+
+impl AsyncControlConnection<TcpStream> {
+    /// Connect to a TCP `ControlPort`
+    pub async fn connect_tcp<A: ToSocketAddrs>(addr: A) -> Result<Self, ControlError> {
+        Ok(AsyncControlConnection::new(TcpStream::connect(addr).await?))
+    }
+}
+
+#[cfg(unix)]
+impl AsyncControlConnection<UnixStream> {
+    /// Connect to a `ControlSocket` or a `ControlPort unix:` listener
+    pub async fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self, ControlError> {
+        Ok(AsyncControlConnection::new(
+            UnixStream::connect(path).await?,
+        ))
+    }
+}
+
+impl ControlConnection<OwningController> {
+    /// Move the owning controller to Tokio, keeping the data and the events already received.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn into_async(self) -> io::Result<AsyncControlConnection<AsyncOwningController>> {
+        let mut buf = self.line;
+        buf.extend_from_slice(self.stream.buffer());
+        let stream = self.stream.into_inner().into_async()?;
+
+        Ok(AsyncControlConnection {
+            stream,
+            buf,
+            parser: self.parser,
+            events: self.events,
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncControlConnection<S> {
+    /// Wrap an already connected stream
+    pub fn new(stream: S) -> Self {
+        AsyncControlConnection {
+            stream,
+            buf: Vec::new(),
+            parser: ReplyParser::default(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns the underlying stream, discarding any buffered data
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Send a command without waiting for the reply
+    pub async fn send(&mut self, command: &str) -> Result<(), ControlError> {
+        let line = command_line(command)?;
+        self.stream.write_all(line.as_bytes()).await?;
+        self.stream.flush().await?;
+
+        Ok(())
+    }
+
+    /// Read the next reply to a command, queueing any asynchronous event received in the
+    /// meantime
+    pub async fn recv_reply(&mut self) -> Result<Reply, ControlError> {
+        loop {
+            let reply = self.read_reply().await?;
+            if reply.is_async() {
+                self.events.push_back(reply);
+            } else {
+                return Ok(reply);
+            }
+        }
+    }
+
+    /// Send a command and wait for its reply, failing if the status is not `2xx`
+    pub async fn command(&mut self, command: &str) -> Result<Reply, ControlError> {
+        self.send(command).await?;
+        self.recv_reply().await?.into_result()
+    }
+
+    /// Query a single `GETINFO` key, returning its value or the content of the data reply
+    pub async fn get_info(&mut self, key: &str) -> Result<String, ControlError> {
+        let reply = self.command(&get_info_command(key)?).await?;
+        info_value(&reply, key)
+    }
+
+    /// Wait for the next asynchronous event
+    pub async fn next_event(&mut self) -> Result<Reply, ControlError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        let reply = self.read_reply().await?;
+        if reply.is_async() {
+            Ok(reply)
+        } else {
+            Err(ControlError::Protocol(format!(
+                "Unexpected reply `{} {}`",
+                reply.code(),
+                reply.message()
+            )))
+        }
+    }
+
+    /// Return the asynchronous events already received while waiting for replies
+    pub fn pending_events(&mut self) -> Vec<Reply> {
+        self.events.drain(..).collect()
+    }
+
+    /// Ask Tor which authentication methods it supports
+    pub async fn protocol_info(&mut self) -> Result<ProtocolInfo, ControlError> {
+        ProtocolInfo::parse(&self.command("PROTOCOLINFO 1").await?)
+    }
+
+    /// Query `PROTOCOLINFO` and authenticate with the best method allowed by both Tor and
+    /// `auth`, like [`ControlConnection::authenticate`]
+    pub async fn authenticate(&mut self, auth: &Auth) -> Result<(), ControlError> {
+        let info = self.protocol_info().await?;
+        let command = match authentication_step(&info, auth)? {
+            AuthStep::Null => "AUTHENTICATE".to_string(),
+            AuthStep::Password(password) => password_command(&password),
+            AuthStep::Cookie(cookie) => cookie_command(&cookie),
+            AuthStep::SafeCookie(cookie) => {
+                let client_nonce = new_client_nonce()?;
+                let reply = self.command(&auth_challenge_command(&client_nonce)).await?;
+                safecookie_command(&reply, &cookie, &client_nonce)?
+            }
+        };
+
+        self.command(&command).await.map(|_| ())
+    }
+
+    /// Subscribe to `events`, replacing the previous subscriptions
+    pub async fn set_events(&mut self, events: &[EventKind]) -> Result<(), ControlError> {
+        self.command(&set_events_command(events)?).await.map(|_| ())
+    }
+
+    /// Wait for the next event and parse it
+    pub async fn recv_event(&mut self) -> Result<Event, ControlError> {
+        Event::parse(&self.next_event().await?)
+    }
+
+    /// Query the current bootstrap progress
+    pub async fn bootstrap_status(&mut self) -> Result<BootstrapStatus, ControlError> {
+        let phase = self.get_info(PHASE_KEY).await?;
+        BootstrapStatus::from_event(&StatusEvent::parse(&phase)?)
+    }
+
+    /// Wait until Tor has finished bootstrapping, without blocking the runtime.
+    ///
+    /// Fails like [`ControlConnection::wait_until_bootstrapped`], including when the timeout
+    /// expires while waiting for a reply.
+    pub async fn wait_until_bootstrapped(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<BootstrapStatus, ControlError> {
+        self.wait_until_bootstrapped_with(timeout, |_| {}).await
+    }
+
+    /// Like [`wait_until_bootstrapped`](AsyncControlConnection::wait_until_bootstrapped),
+    /// calling `on_progress` every time the bootstrap status changes
+    pub async fn wait_until_bootstrapped_with<F>(
+        &mut self,
+        timeout: Duration,
+        mut on_progress: F,
+    ) -> Result<BootstrapStatus, ControlError>
+    where
+        F: FnMut(&BootstrapStatus),
+    {
+        let mut wait = BootstrapWait::new(timeout);
+        loop {
+            let phase = tokio::time::timeout(wait.remaining()?, self.get_info(PHASE_KEY))
+                .await
+                .map_err(|_| ControlError::Timeout)??;
+            match wait.update(&phase, &mut on_progress)? {
+                WaitStep::Done(status) => return Ok(status),
+                WaitStep::Sleep(duration) => tokio::time::sleep(duration).await,
+            }
+        }
+    }
+
+    /// Query the version of Tor
+    pub async fn version(&mut self) -> Result<TorVersion, ControlError> {
+        parse_version(&self.get_info("version").await?)
+    }
+
+    /// Query the circuits currently open or being built
+    pub async fn circuit_status(&mut self) -> Result<Vec<CircEvent>, ControlError> {
+        parse_lines(&self.get_info("circuit-status").await?, CircEvent::parse)
+    }
+
+    /// Query the open streams
+    pub async fn stream_status(&mut self) -> Result<Vec<StreamEvent>, ControlError> {
+        parse_lines(&self.get_info("stream-status").await?, StreamEvent::parse)
+    }
+
+    /// Query the connections to relays
+    pub async fn orconn_status(&mut self) -> Result<Vec<OrConnEvent>, ControlError> {
+        parse_lines(&self.get_info("orconn-status").await?, OrConnEvent::parse)
+    }
+
+    /// Query the guards, in order of preference
+    pub async fn entry_guards(&mut self) -> Result<Vec<EntryGuard>, ControlError> {
+        parse_lines(&self.get_info("entry-guards").await?, parse_entry_guard)
+    }
+
+    /// Query the addresses of the listeners of type `kind`
+    pub async fn listeners(&mut self, kind: &ListenerKind) -> Result<Vec<Listener>, ControlError> {
+        parse_listeners(&self.get_info(&listeners_key(kind)).await?)
+    }
+
+    /// Query the addresses of the `SocksPort`, `ControlPort`, `HTTPTunnelPort` and `DNSPort`
+    /// listeners with a single command
+    pub async fn bound_listeners(&mut self) -> Result<BoundListeners, ControlError> {
+        parse_bound_listeners(&self.command(&bound_listeners_command()).await?)
+    }
+
+    /// Returns `true` if Tor has managed to build a circuit since it started or woke up
+    pub async fn circuit_established(&mut self) -> Result<bool, ControlError> {
+        parse_bool(&self.get_info("status/circuit-established").await?)
+    }
+
+    /// Query the total number of bytes read since Tor started
+    pub async fn traffic_read(&mut self) -> Result<u64, ControlError> {
+        number(&self.get_info("traffic/read").await?, "traffic/read")
+    }
+
+    /// Query the total number of bytes written since Tor started
+    pub async fn traffic_written(&mut self) -> Result<u64, ControlError> {
+        number(&self.get_info("traffic/written").await?, "traffic/written")
+    }
+
+    /// Query the location of the `torrc` file
+    pub async fn config_file(&mut self) -> Result<PathBuf, ControlError> {
+        self.get_info("config-file").await.map(PathBuf::from)
+    }
+
+    /// Query the consensus entry of the relay with the hex fingerprint `relay`
+    pub async fn router_status(&mut self, relay: &str) -> Result<RouterStatus, ControlError> {
+        parse_router_status(&self.get_info(&format!("ns/id/{}", relay)).await?)
+    }
+
+    /// Query the microdescriptor of the relay with the hex fingerprint `relay`
+    pub async fn microdescriptor(&mut self, relay: &str) -> Result<Microdescriptor, ControlError> {
+        parse_microdescriptor(&self.get_info(&format!("md/id/{}", relay)).await?)
+    }
+
+    /// Query the current values of `keys` with `GETCONF`
+    pub async fn get_conf(&mut self, keys: &[&str]) -> Result<ConfValues, ControlError> {
+        let reply = self
+            .command(&get_conf_command(keys)?)
+            .await
+            .map_err(conf_error)?;
+        parse_conf_values(&reply)
+    }
+
+    /// Query all the values of a single option, which is empty if the option isn't set
+    pub async fn get_conf_list(&mut self, key: &str) -> Result<Vec<String>, ControlError> {
+        Ok(self
+            .get_conf(&[key])
+            .await?
+            .into_iter()
+            .filter_map(|(_, value)| value)
+            .collect())
+    }
+
+    /// Apply `changes` atomically, like [`ControlConnection::set_conf`]
+    pub async fn set_conf(&mut self, changes: &ConfChanges) -> Result<(), ControlError> {
+        self.command(&changes.command()?)
+            .await
+            .map(|_| ())
+            .map_err(conf_error)
+    }
+
+    /// Restore the default values of `keys` atomically
+    pub async fn reset_conf(&mut self, keys: &[&str]) -> Result<(), ControlError> {
+        let changes = keys
+            .iter()
+            .fold(ConfChanges::new(), |changes, key| changes.reset(*key));
+        self.set_conf(&changes).await
+    }
+
+    /// Write the current configuration to the `torrc` file, like
+    /// [`ControlConnection::save_conf`]
+    pub async fn save_conf(&mut self, force: bool) -> Result<(), ControlError> {
+        let command = if force { "SAVECONF FORCE" } else { "SAVECONF" };
+        self.command(command).await.map(|_| ()).map_err(conf_error)
+    }
+
+    /// Send a signal to Tor
+    pub async fn signal(&mut self, signal: &Signal) -> Result<(), ControlError> {
+        self.command(&signal_command(signal)).await.map(|_| ())
+    }
+
+    /// Enable or disable the network by changing `DisableNetwork`
+    pub async fn set_network_enabled(&mut self, enabled: bool) -> Result<(), ControlError> {
+        self.set_conf(&network_changes(enabled)).await
+    }
+
+    /// Returns `false` if `DisableNetwork` is set
+    pub async fn network_enabled(&mut self) -> Result<bool, ControlError> {
+        let values = self.get_conf_list("DisableNetwork").await?;
+        Ok(values.iter().all(|value| value == "0"))
+    }
+
+    /// Enter or leave dormant mode with `SIGNAL DORMANT` and `SIGNAL ACTIVE`
+    pub async fn set_dormant(&mut self, dormant: bool) -> Result<(), ControlError> {
+        self.signal(&dormant_signal(dormant)).await
+    }
+
+    /// Returns `true` if Tor is dormant
+    pub async fn is_dormant(&mut self) -> Result<bool, ControlError> {
+        parse_bool(&self.get_info("dormant").await?)
+    }
+
+    async fn read_line(&mut self) -> Result<String, ControlError> {
+        loop {
+            if let Some(end) = self.buf.iter().position(|b| *b == b'\n') {
+                let mut line = self.buf.drain(..=end).collect::<Vec<_>>();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(String::from_utf8_lossy(&line).into_owned());
+            }
+
+            let mut chunk = [0; READ_SIZE];
+            match self.stream.read(&mut chunk).await? {
+                0 => return Err(ControlError::Closed),
+                n => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply, ControlError> {
+        loop {
+            let line = self.read_line().await?;
+            if let Some(reply) = self.parser.push_line(line)? {
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::MockStream;
+    use crate::control::{ConfError, StatusSeverity};
+
+    fn mock(input: &str) -> AsyncControlConnection<MockStream> {
+        AsyncControlConnection::new(MockStream::new(input))
+    }
+
+    #[tokio::test]
+    async fn test_command_and_events() {
+        let mut conn = mock(concat!(
+            "650 BW 1024 2048\r\n",
+            "250-version=0.4.7.13\r\n",
+            "250+config-text=\r\n",
+            "SocksPort 9050\r\n",
+            ".\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+            "650 STATUS_CLIENT NOTICE CIRCUIT_ESTABLISHED\r\n",
+        ));
+        let reply = conn.command("GETINFO version config-text").await.unwrap();
+        assert_eq!(reply.lines()[1].data.as_deref(), Some("SocksPort 9050"));
+        conn.set_events(&[EventKind::Bw, EventKind::StatusClient])
+            .await
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETINFO version config-text\r\nSETEVENTS BW STATUS_CLIENT\r\n"
+        );
+
+        match conn.recv_event().await.unwrap() {
+            Event::Bandwidth(bw) => assert_eq!((bw.read, bw.written), (1024, 2048)),
+            e => panic!("Unexpected event: {:?}", e),
+        }
+        match conn.recv_event().await.unwrap() {
+            Event::StatusClient(status) => {
+                assert_eq!(status.severity, StatusSeverity::Notice);
+                assert_eq!(status.action, "CIRCUIT_ESTABLISHED");
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+        match conn.next_event().await {
+            Err(ControlError::Closed) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let mut conn = mock(concat!(
+            "250-PROTOCOLINFO 1\r\n",
+            "250-AUTH METHODS=HASHEDPASSWORD\r\n",
+            "250-VERSION Tor=\"0.4.7.13\"\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+        ));
+        conn.authenticate(&Auth::Password("hunter2".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "PROTOCOLINFO 1\r\nAUTHENTICATE \"hunter2\"\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_bootstrapped() {
+        let phase = |progress, tag| {
+            format!(
+                "250-{}=NOTICE BOOTSTRAP PROGRESS={} TAG={} SUMMARY=\"{}\"\r\n250 OK\r\n",
+                PHASE_KEY, progress, tag, tag
+            )
+        };
+        let mut conn = mock(&format!(
+            "{}{}",
+            phase(50, "loading_descriptors"),
+            phase(100, "done")
+        ));
+        let mut progress = Vec::new();
+        let status = conn
+            .wait_until_bootstrapped_with(Duration::from_secs(10), |s| progress.push(s.progress))
+            .await
+            .unwrap();
+        assert!(status.is_done());
+        assert_eq!(progress, vec![50, 100]);
+
+        let mut conn = mock(&phase(10, "conn_pt"));
+        match conn.wait_until_bootstrapped(Duration::from_millis(0)).await {
+            Err(ControlError::Timeout) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        // Tor never answers
+        let (stream, _tor) = tokio::io::duplex(1024);
+        let mut conn = AsyncControlConnection::new(stream);
+        match conn
+            .wait_until_bootstrapped(Duration::from_millis(100))
+            .await
+        {
+            Err(ControlError::Timeout) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_info_accessors() {
+        let mut conn = mock(concat!(
+            "250-version=0.4.7.13\r\n250 OK\r\n",
+            "250-net/listeners/socks=\"127.0.0.1:9050\"\r\n250 OK\r\n",
+            "250-traffic/read=1024\r\n250 OK\r\n",
+        ));
+        assert!(conn.version().await.unwrap().matches_crate_version());
+        assert_eq!(
+            conn.listeners(&ListenerKind::Socks).await.unwrap(),
+            vec![Listener::Tcp("127.0.0.1:9050".parse().unwrap())]
+        );
+        assert_eq!(conn.traffic_read().await.unwrap(), 1024);
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETINFO version\r\nGETINFO net/listeners/socks\r\nGETINFO traffic/read\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conf() {
+        let mut conn = mock(concat!(
+            "250 DisableNetwork=1\r\n",
+            "250 OK\r\n",
+            "513 Unacceptable option value: Unrecognized value 2 in DisableNetwork\r\n",
+        ));
+        assert_eq!(
+            conn.get_conf_list("DisableNetwork").await.unwrap(),
+            vec!["1"]
+        );
+        conn.reset_conf(&["DisableNetwork"]).await.unwrap();
+        match conn
+            .set_conf(&ConfChanges::new().set("DisableNetwork", "2"))
+            .await
+        {
+            Err(ControlError::Conf(ConfError::InvalidValue(_))) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETCONF DisableNetwork\r\nRESETCONF DisableNetwork\r\nSETCONF DisableNetwork=\"2\"\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_network_control() {
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "250 DisableNetwork=0\r\n",
+            "250 OK\r\n",
+            "250-dormant=0\r\n250 OK\r\n",
+        ));
+        conn.set_network_enabled(true).await.unwrap();
+        assert!(conn.network_enabled().await.unwrap());
+        conn.set_dormant(false).await.unwrap();
+        assert!(!conn.is_dormant().await.unwrap());
+        assert_eq!(
+            conn.get_ref().sent(),
+            concat!(
+                "SETCONF DisableNetwork=\"0\"\r\n",
+                "GETCONF DisableNetwork\r\n",
+                "SIGNAL ACTIVE\r\n",
+                "GETINFO dormant\r\n",
+            )
+        );
+    }
+}
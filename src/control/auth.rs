@@ -0,0 +1,525 @@
+//! `PROTOCOLINFO` and the authentication methods supported by the control port
+
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use getrandom::getrandom;
+use hmac::{Hmac, Mac};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use super::parse::{quote, split_kv, tokenize, value};
+use super::{ControlConnection, ControlError, Reply};
+use crate::encoding::{hex_decode, hex_encode};
+
+const SAFECOOKIE_SERVER_KEY: &[u8] = b"Tor safe cookie authentication server-to-controller hash";
+const SAFECOOKIE_CLIENT_KEY: &[u8] = b"Tor safe cookie authentication controller-to-server hash";
+
+/// Length of the authentication cookie written by Tor
+pub const COOKIE_LEN: usize = 32;
+/// Length of the nonces exchanged by `AUTHCHALLENGE`
+const NONCE_LEN: usize = 32;
+/// S2K specifier used by `tor --hash-password`, meaning 64KiB of hashed data
+const S2K_INDICATOR: u8 = 0x60;
+
+/// Authentication method accepted by the control port
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AuthMethod {
+    /// No authentication required
+    Null,
+    /// `HashedControlPassword`
+    HashedPassword,
+    /// `CookieAuthentication`, sending the cookie in clear
+    Cookie,
+    /// `CookieAuthentication` with the challenge-response of `AUTHCHALLENGE SAFECOOKIE`
+    SafeCookie,
+    /// A method not known to this crate
+    Unknown(String),
+}
+
+impl AuthMethod {
+    fn parse(s: &str) -> Self {
+        match s {
+            "NULL" => AuthMethod::Null,
+            "HASHEDPASSWORD" => AuthMethod::HashedPassword,
+            "COOKIE" => AuthMethod::Cookie,
+            "SAFECOOKIE" => AuthMethod::SafeCookie,
+            other => AuthMethod::Unknown(other.to_string()),
+        }
+    }
+}
+
+/// Reply to `PROTOCOLINFO`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolInfo {
+    /// Version of the control protocol, always `1` so far
+    pub protocol_version: u32,
+    /// Authentication methods accepted by Tor
+    pub auth_methods: Vec<AuthMethod>,
+    /// Location of the authentication cookie, if cookie authentication is enabled
+    pub cookie_file: Option<PathBuf>,
+    /// Version of Tor, as in `VERSION Tor="..."`
+    pub tor_version: Option<String>,
+}
+
+/// Authentication credentials used by [`ControlConnection::authenticate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// No credentials, only works if `NULL` authentication is allowed
+    Null,
+    /// Password matching `HashedControlPassword`
+    Password(String),
+    /// Authenticate with the cookie, read from the file advertised by Tor unless a different
+    /// location is given. `SAFECOOKIE` is preferred over `COOKIE` when available.
+    Cookie(Option<PathBuf>),
+}
+
+impl ProtocolInfo {
+    pub(super) fn parse(reply: &Reply) -> Result<ProtocolInfo, ControlError> {
+        let mut info = ProtocolInfo {
+            protocol_version: 0,
+            auth_methods: Vec::new(),
+            cookie_file: None,
+            tor_version: None,
+        };
+        for line in reply.lines() {
+            let tokens = tokenize(&line.text)?;
+            match tokens.split_first() {
+                Some((&"PROTOCOLINFO", [version, ..])) => {
+                    info.protocol_version = version.parse().map_err(|_| {
+                        ControlError::Protocol(format!(
+                            "Invalid PROTOCOLINFO version `{}`",
+                            version
+                        ))
+                    })?;
+                }
+                Some((&"AUTH", args)) => {
+                    for (key, raw) in args.iter().filter_map(|t| split_kv(t)) {
+                        match key {
+                            "METHODS" => {
+                                info.auth_methods = raw.split(',').map(AuthMethod::parse).collect()
+                            }
+                            "COOKIEFILE" => info.cookie_file = Some(value(raw)?.into()),
+                            _ => {}
+                        }
+                    }
+                }
+                Some((&"VERSION", args)) => {
+                    for (key, raw) in args.iter().filter_map(|t| split_kv(t)) {
+                        if key == "Tor" {
+                            info.tor_version = Some(value(raw)?);
+                        }
+                    }
+                }
+                // Unknown lines are ignored, as required by the spec
+                _ => {}
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// Authentication command chosen by [`authentication_step`]
+pub(super) enum AuthStep {
+    Null,
+    Password(String),
+    Cookie(Vec<u8>),
+    SafeCookie(Vec<u8>),
+}
+
+/// Pick the best method allowed by both Tor and `auth`, reading the cookie if needed
+pub(super) fn authentication_step(
+    info: &ProtocolInfo,
+    auth: &Auth,
+) -> Result<AuthStep, ControlError> {
+    let supports = |method| info.auth_methods.contains(&method);
+
+    if supports(AuthMethod::Null) {
+        return Ok(AuthStep::Null);
+    }
+
+    match auth {
+        Auth::Null => {}
+        Auth::Password(password) => {
+            if supports(AuthMethod::HashedPassword) {
+                return Ok(AuthStep::Password(password.clone()));
+            }
+        }
+        Auth::Cookie(path) => {
+            let path = path.as_ref().or(info.cookie_file.as_ref());
+            if supports(AuthMethod::SafeCookie) || supports(AuthMethod::Cookie) {
+                let path = path.ok_or_else(|| {
+                    ControlError::Auth("The location of the cookie file is unknown".into())
+                })?;
+                let cookie = read_cookie_file(path)?;
+
+                return Ok(if supports(AuthMethod::SafeCookie) {
+                    AuthStep::SafeCookie(cookie)
+                } else {
+                    AuthStep::Cookie(cookie)
+                });
+            }
+        }
+    }
+
+    Err(ControlError::Auth(format!(
+        "Tor doesn't accept the given credentials, supported methods: {:?}",
+        info.auth_methods
+    )))
+}
+
+pub(super) fn password_command(password: &str) -> String {
+    format!("AUTHENTICATE {}", quote(password))
+}
+
+pub(super) fn cookie_command(cookie: &[u8]) -> String {
+    format!("AUTHENTICATE {}", hex_encode(cookie))
+}
+
+pub(super) fn new_client_nonce() -> Result<[u8; NONCE_LEN], ControlError> {
+    let mut client_nonce = [0u8; NONCE_LEN];
+    getrandom(&mut client_nonce)
+        .map_err(|e| ControlError::Auth(format!("Can't generate a nonce: {}", e)))?;
+    Ok(client_nonce)
+}
+
+pub(super) fn auth_challenge_command(client_nonce: &[u8]) -> String {
+    format!("AUTHCHALLENGE SAFECOOKIE {}", hex_encode(client_nonce))
+}
+
+/// Check the reply to `AUTHCHALLENGE` and compute the `AUTHENTICATE` command that completes the
+/// `SAFECOOKIE` exchange
+pub(super) fn safecookie_command(
+    reply: &Reply,
+    cookie: &[u8],
+    client_nonce: &[u8],
+) -> Result<String, ControlError> {
+    let tokens = tokenize(reply.message())?;
+    if tokens.first() != Some(&"AUTHCHALLENGE") {
+        return Err(ControlError::Protocol(format!(
+            "Invalid AUTHCHALLENGE reply `{}`",
+            reply.message()
+        )));
+    }
+    let get = |name: &str| {
+        tokens
+            .iter()
+            .filter_map(|t| split_kv(t))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| hex_decode(value))
+            .ok_or_else(|| ControlError::Protocol(format!("Missing or invalid {}", name)))
+    };
+    let server_hash = get("SERVERHASH")?;
+    let server_nonce = get("SERVERNONCE")?;
+
+    let client_hash = safecookie_client_hash(cookie, client_nonce, &server_nonce, &server_hash)?;
+    Ok(format!("AUTHENTICATE {}", hex_encode(&client_hash)))
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Ask Tor which authentication methods it supports.
+    ///
+    /// Tor only answers to this command once before authentication.
+    pub fn protocol_info(&mut self) -> Result<ProtocolInfo, ControlError> {
+        ProtocolInfo::parse(&self.command("PROTOCOLINFO 1")?)
+    }
+
+    /// Authenticate with `NULL` authentication
+    pub fn authenticate_null(&mut self) -> Result<(), ControlError> {
+        self.command("AUTHENTICATE").map(|_| ())
+    }
+
+    /// Authenticate with a password matching Tor's `HashedControlPassword`
+    pub fn authenticate_password(&mut self, password: &str) -> Result<(), ControlError> {
+        self.command(&password_command(password)).map(|_| ())
+    }
+
+    /// Authenticate by sending the content of the cookie file in clear (`COOKIE` method)
+    pub fn authenticate_cookie(&mut self, cookie: &[u8]) -> Result<(), ControlError> {
+        self.command(&cookie_command(cookie)).map(|_| ())
+    }
+
+    /// Authenticate with the `SAFECOOKIE` method, which proves knowledge of the cookie without
+    /// revealing it and checks that Tor knows it too
+    pub fn authenticate_safecookie(&mut self, cookie: &[u8]) -> Result<(), ControlError> {
+        let client_nonce = new_client_nonce()?;
+        let reply = self.command(&auth_challenge_command(&client_nonce))?;
+        self.command(&safecookie_command(&reply, cookie, &client_nonce)?)
+            .map(|_| ())
+    }
+
+    /// Query `PROTOCOLINFO` and authenticate with the best method allowed by both Tor and
+    /// `auth`
+    pub fn authenticate(&mut self, auth: &Auth) -> Result<(), ControlError> {
+        let info = self.protocol_info()?;
+        match authentication_step(&info, auth)? {
+            AuthStep::Null => self.authenticate_null(),
+            AuthStep::Password(password) => self.authenticate_password(&password),
+            AuthStep::Cookie(cookie) => self.authenticate_cookie(&cookie),
+            AuthStep::SafeCookie(cookie) => self.authenticate_safecookie(&cookie),
+        }
+    }
+}
+
+/// Read an authentication cookie, checking its length
+pub fn read_cookie_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, ControlError> {
+    let cookie = fs::read(path)?;
+    if cookie.len() != COOKIE_LEN {
+        return Err(ControlError::Auth(format!(
+            "Invalid cookie length: {} bytes",
+            cookie.len()
+        )));
+    }
+
+    Ok(cookie)
+}
+
+fn safecookie_hmac(
+    key: &[u8],
+    cookie: &[u8],
+    client_nonce: &[u8],
+    server_nonce: &[u8],
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(cookie);
+    mac.update(client_nonce);
+    mac.update(server_nonce);
+    mac
+}
+
+/// Check the server hash sent by Tor and compute the hash we have to reply with
+fn safecookie_client_hash(
+    cookie: &[u8],
+    client_nonce: &[u8],
+    server_nonce: &[u8],
+    server_hash: &[u8],
+) -> Result<Vec<u8>, ControlError> {
+    safecookie_hmac(SAFECOOKIE_SERVER_KEY, cookie, client_nonce, server_nonce)
+        .verify_slice(server_hash)
+        .map_err(|_| {
+            ControlError::Auth("Tor's SAFECOOKIE hash doesn't match, wrong cookie?".into())
+        })?;
+
+    Ok(
+        safecookie_hmac(SAFECOOKIE_CLIENT_KEY, cookie, client_nonce, server_nonce)
+            .finalize()
+            .into_bytes()
+            .to_vec(),
+    )
+}
+
+/// RFC2440 iterated and salted S2K with SHA1, as implemented by Tor
+fn secret_to_key(secret: &[u8], salt: &[u8; 8], indicator: u8) -> [u8; 20] {
+    let mut count = (16usize + (indicator as usize & 15)) << ((indicator >> 4) + 6);
+    let mut data = salt.to_vec();
+    data.extend_from_slice(secret);
+
+    let mut hasher = Sha1::new();
+    while count > 0 {
+        let len = count.min(data.len());
+        hasher.update(&data[..len]);
+        count -= len;
+    }
+
+    hasher.finalize().into()
+}
+
+/// Hash `password` for the `HashedControlPassword` option, like `tor --hash-password` does
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; 8];
+    getrandom(&mut salt).expect("The system RNG is unavailable");
+
+    hash_password_with_salt(password, &salt)
+}
+
+/// Hash `password` for the `HashedControlPassword` option with a specific salt
+pub fn hash_password_with_salt(password: &str, salt: &[u8; 8]) -> String {
+    let key = secret_to_key(password.as_bytes(), salt, S2K_INDICATOR);
+
+    let mut data = salt.to_vec();
+    data.push(S2K_INDICATOR);
+    data.extend_from_slice(&key);
+
+    format!("16:{}", hex_encode(&data))
+}
+
+/// Check `password` against a `HashedControlPassword` value
+pub fn verify_password(hashed: &str, password: &str) -> bool {
+    let data = match hashed.strip_prefix("16:").and_then(hex_decode) {
+        Some(data) if data.len() == 8 + 1 + 20 => data,
+        _ => return false,
+    };
+    let mut salt = [0u8; 8];
+    salt.copy_from_slice(&data[..8]);
+
+    // Compare in constant time, like the HMAC check of SAFECOOKIE
+    let key = secret_to_key(password.as_bytes(), &salt, data[8]);
+    key.iter()
+        .zip(&data[9..])
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+
+    #[test]
+    fn test_protocol_info() {
+        let mut conn = mock(concat!(
+            "250-PROTOCOLINFO 1\r\n",
+            "250-AUTH METHODS=COOKIE,SAFECOOKIE,HASHEDPASSWORD,FUTURE ",
+            "COOKIEFILE=\"/home/user/.tor/control auth \\\"cookie\\\"\"\r\n",
+            "250-VERSION Tor=\"0.4.7.13\" OTHER=1\r\n",
+            "250-SOMETHINGNEW foo\r\n",
+            "250 OK\r\n",
+        ));
+        let info = conn.protocol_info().unwrap();
+        assert_eq!(conn.get_ref().sent(), "PROTOCOLINFO 1\r\n");
+        assert_eq!(
+            info,
+            ProtocolInfo {
+                protocol_version: 1,
+                auth_methods: vec![
+                    AuthMethod::Cookie,
+                    AuthMethod::SafeCookie,
+                    AuthMethod::HashedPassword,
+                    AuthMethod::Unknown("FUTURE".into()),
+                ],
+                cookie_file: Some("/home/user/.tor/control auth \"cookie\"".into()),
+                tor_version: Some("0.4.7.13".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_password() {
+        let mut conn = mock(concat!(
+            "250-PROTOCOLINFO 1\r\n",
+            "250-AUTH METHODS=HASHEDPASSWORD\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+        ));
+        conn.authenticate(&Auth::Password("pass \"word\"".into()))
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "PROTOCOLINFO 1\r\nAUTHENTICATE \"pass \\\"word\\\"\"\r\n"
+        );
+
+        let mut conn = mock("250-PROTOCOLINFO 1\r\n250-AUTH METHODS=HASHEDPASSWORD\r\n250 OK\r\n");
+        match conn.authenticate(&Auth::Cookie(None)) {
+            Err(ControlError::Auth(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        let mut conn = mock("515 Authentication failed: Password did not match\r\n");
+        match conn.authenticate_password("wrong") {
+            Err(ControlError::Reply { code: 515, .. }) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_null_and_cookie() {
+        let mut conn = mock(concat!(
+            "250-PROTOCOLINFO 1\r\n",
+            "250-AUTH METHODS=NULL\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+        ));
+        conn.authenticate(&Auth::Password("unused".into())).unwrap();
+        assert_eq!(conn.get_ref().sent(), "PROTOCOLINFO 1\r\nAUTHENTICATE\r\n");
+
+        let dir = crate::test_utils::test_dir("control-cookie");
+        let cookie_file = dir.join("control_auth_cookie");
+        fs::write(&cookie_file, [0xab; COOKIE_LEN]).unwrap();
+
+        let mut conn = mock(&format!(
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=COOKIE COOKIEFILE={}\r\n250 OK\r\n250 OK\r\n",
+            quote(cookie_file.to_str().unwrap())
+        ));
+        conn.authenticate(&Auth::Cookie(None)).unwrap();
+        assert!(conn
+            .get_ref()
+            .sent()
+            .ends_with(&format!("AUTHENTICATE {}\r\n", "AB".repeat(COOKIE_LEN))));
+
+        fs::write(&cookie_file, [0xab; 16]).unwrap();
+        match read_cookie_file(&cookie_file) {
+            Err(ControlError::Auth(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_safecookie_hashes() {
+        let cookie = (0..32).collect::<Vec<u8>>();
+        let client_nonce = [0xaa; 32];
+        let server_nonce = [0xbb; 32];
+        let server_hash =
+            hex_decode("7780BA467C566548B61DA5B927043DF643CC6FB18E6258718F47591A09909213").unwrap();
+
+        let client_hash =
+            safecookie_client_hash(&cookie, &client_nonce, &server_nonce, &server_hash).unwrap();
+        assert_eq!(
+            hex_encode(&client_hash),
+            "ECD5845C1ED9B11354FED620D989E9F34243606E0F7F97C0F3C8A9303A06D468"
+        );
+
+        // A server that doesn't know the cookie is rejected
+        match safecookie_client_hash(&[0; 32], &client_nonce, &server_nonce, &server_hash) {
+            Err(ControlError::Auth(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_safecookie_exchange() {
+        let mut conn = mock(concat!(
+            "250 AUTHCHALLENGE SERVERHASH=0000 SERVERNONCE=BBBB\r\n",
+            "250 OK\r\n",
+        ));
+        match conn.authenticate_safecookie(&[0; 32]) {
+            Err(ControlError::Auth(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(conn
+            .get_ref()
+            .sent()
+            .starts_with("AUTHCHALLENGE SAFECOOKIE "));
+
+        let mut conn = mock("250 AUTHCHALLENGE SERVERNONCE=BBBB\r\n");
+        match conn.authenticate_safecookie(&[0; 32]) {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let hashed = hash_password_with_salt("password", &[1, 2, 3, 4, 5, 6, 7, 8]);
+        // Same output as Tor's `secret_to_key_rfc2440`
+        assert_eq!(
+            hashed,
+            "16:01020304050607086019FADB83496D201EB48E03E4EE94EF72F0A4FB70"
+        );
+        assert!(verify_password(&hashed, "password"));
+        assert!(!verify_password(&hashed, "Password"));
+        assert!(!verify_password("16:0102", "password"));
+        // Only the last byte of the key differs
+        assert!(!verify_password(
+            "16:01020304050607086019FADB83496D201EB48E03E4EE94EF72F0A4FB71",
+            "password"
+        ));
+
+        let random = hash_password("password");
+        assert_ne!(random, hash_password("password"));
+        assert!(verify_password(&random, "password"));
+    }
+}
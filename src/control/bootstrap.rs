@@ -0,0 +1,476 @@
+//! Bootstrap progress, from `status/bootstrap-phase` and `STATUS_CLIENT BOOTSTRAP` events
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::events::{Event, StatusEvent, StatusSeverity};
+use super::{is_timeout, ControlConnection, ControlError, ReadTimeout};
+
+/// Key polled while waiting for the bootstrap to complete
+pub(super) const PHASE_KEY: &str = "status/bootstrap-phase";
+/// How often `status/bootstrap-phase` is polled while waiting for the bootstrap to complete
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+keyword_enum! {
+    /// What Tor recommends doing about a [`BootstrapWarning`]
+    pub enum BootstrapRecommendation {
+        /// The problem is probably transient, Tor keeps trying
+        Ignore => "ignore",
+        /// The problem should be reported to the user
+        Warn => "warn",
+    }
+}
+
+/// Problem that is holding back the bootstrap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapWarning {
+    /// Description of the problem (`WARNING`)
+    pub warning: String,
+    /// Keyword describing the cause (`REASON`), like `CONNECTREFUSED` or `NOROUTE`
+    pub reason: String,
+    /// How many times the problem happened (`COUNT`)
+    pub count: Option<u32>,
+    /// What Tor recommends doing about the problem (`RECOMMENDATION`)
+    pub recommendation: BootstrapRecommendation,
+    /// Relay that Tor was trying to reach (`HOST`)
+    pub host: Option<String>,
+    /// Address of the relay that Tor was trying to reach (`HOSTADDR`)
+    pub host_addr: Option<String>,
+}
+
+/// Bootstrap progress reported by Tor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootstrapStatus {
+    /// `NOTICE` while making progress, `WARN` when a problem is reported
+    pub severity: StatusSeverity,
+    /// Percentage of completion, `100` once Tor is ready
+    pub progress: u8,
+    /// Keyword identifying the phase, like `conn_pt` or `done`
+    pub tag: String,
+    /// Human-readable description of the phase
+    pub summary: String,
+    /// Problem holding back the bootstrap, if any
+    pub warning: Option<BootstrapWarning>,
+}
+
+impl BootstrapStatus {
+    /// Parse the content of a `BOOTSTRAP` status event
+    pub fn from_event(event: &StatusEvent) -> Result<BootstrapStatus, ControlError> {
+        if event.action != "BOOTSTRAP" {
+            return Err(ControlError::Protocol(format!(
+                "Not a BOOTSTRAP status: `{}`",
+                event.action
+            )));
+        }
+        let required = |key| {
+            event.argument(key).ok_or_else(|| {
+                ControlError::Protocol(format!("Missing {} in BOOTSTRAP status", key))
+            })
+        };
+        let progress = required("PROGRESS")?;
+
+        let warning = match event.argument("WARNING") {
+            Some(warning) => Some(BootstrapWarning {
+                warning: warning.to_string(),
+                reason: required("REASON")?.to_string(),
+                count: event.argument("COUNT").and_then(|c| c.parse().ok()),
+                recommendation: BootstrapRecommendation::parse(required("RECOMMENDATION")?),
+                host: event.argument("HOST").map(str::to_string),
+                host_addr: event.argument("HOSTADDR").map(str::to_string),
+            }),
+            None => None,
+        };
+
+        Ok(BootstrapStatus {
+            severity: event.severity.clone(),
+            progress: progress.parse().ok().filter(|p| *p <= 100).ok_or_else(|| {
+                ControlError::Protocol(format!("Invalid PROGRESS `{}`", progress))
+            })?,
+            tag: required("TAG")?.to_string(),
+            summary: required("SUMMARY")?.to_string(),
+            warning,
+        })
+    }
+
+    /// Returns `true` once Tor has finished bootstrapping
+    pub fn is_done(&self) -> bool {
+        self.progress == 100
+    }
+
+    /// Returns `true` if the bootstrap hasn't progressed since `previous` and is held back by a
+    /// problem that Tor thinks should be reported to the user
+    ///
+    /// Tor can report warnings while it keeps making progress, for example when some of the
+    /// relays it tries are unreachable, so a warning alone doesn't mean that it's stuck.
+    pub fn is_stalled(&self, previous: &BootstrapStatus) -> bool {
+        self.progress <= previous.progress
+            && self
+                .warning
+                .as_ref()
+                .map(|w| w.recommendation == BootstrapRecommendation::Warn)
+                .unwrap_or(false)
+    }
+}
+
+impl fmt::Display for BootstrapStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}% ({}): {}", self.progress, self.tag, self.summary)?;
+        if let Some(warning) = &self.warning {
+            write!(f, " [{}: {}]", warning.reason, warning.warning)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Event {
+    /// Returns the bootstrap progress carried by `STATUS_CLIENT BOOTSTRAP` events
+    pub fn bootstrap_status(&self) -> Option<BootstrapStatus> {
+        match self {
+            Event::StatusClient(status) => BootstrapStatus::from_event(status).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Progress of a wait for the bootstrap, shared by the blocking and the async clients
+pub(super) struct BootstrapWait {
+    deadline: Instant,
+    last: Option<BootstrapStatus>,
+}
+
+/// What to do after polling the bootstrap status
+pub(super) enum WaitStep {
+    Done(BootstrapStatus),
+    Sleep(Duration),
+}
+
+impl BootstrapWait {
+    pub(super) fn new(timeout: Duration) -> Self {
+        BootstrapWait {
+            deadline: Instant::now() + timeout,
+            last: None,
+        }
+    }
+
+    /// Time left before the deadline, failing with [`ControlError::Timeout`] once it has passed
+    pub(super) fn remaining(&self) -> Result<Duration, ControlError> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(ControlError::Timeout);
+        }
+
+        Ok(self.deadline - now)
+    }
+
+    /// Process the value of `status/bootstrap-phase`
+    pub(super) fn update<F>(
+        &mut self,
+        phase: &str,
+        on_progress: &mut F,
+    ) -> Result<WaitStep, ControlError>
+    where
+        F: FnMut(&BootstrapStatus),
+    {
+        let status = BootstrapStatus::from_event(&StatusEvent::parse(phase)?)?;
+        if self.last.as_ref() != Some(&status) {
+            on_progress(&status);
+        }
+        if status.is_done() {
+            return Ok(WaitStep::Done(status));
+        }
+        if self
+            .last
+            .as_ref()
+            .map(|last| status.is_stalled(last))
+            .unwrap_or(false)
+        {
+            return Err(ControlError::BootstrapStalled(Box::new(status)));
+        }
+
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(ControlError::Timeout);
+        }
+        self.last = Some(status);
+
+        Ok(WaitStep::Sleep(POLL_INTERVAL.min(self.deadline - now)))
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Query the current bootstrap progress
+    pub fn bootstrap_status(&mut self) -> Result<BootstrapStatus, ControlError> {
+        let phase = self.get_info(PHASE_KEY)?;
+        BootstrapStatus::from_event(&StatusEvent::parse(&phase)?)
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> ControlConnection<S> {
+    /// Wait until Tor has finished bootstrapping.
+    ///
+    /// Fails with [`ControlError::Timeout`] if Tor isn't ready after `timeout`, or with
+    /// [`ControlError::BootstrapStalled`] as soon as the progress stops between two polls while
+    /// Tor reports a problem, see [`BootstrapStatus::is_stalled`].
+    ///
+    /// The time left is applied as the read timeout of the stream while waiting for each reply,
+    /// and the stream is left without a read timeout when this returns. If the timeout expires
+    /// while a reply is pending, that reply is returned by the next call to
+    /// [`recv_reply`](ControlConnection::recv_reply).
+    pub fn wait_until_bootstrapped(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<BootstrapStatus, ControlError> {
+        self.wait_until_bootstrapped_with(timeout, |_| {})
+    }
+
+    /// Like [`wait_until_bootstrapped`](ControlConnection::wait_until_bootstrapped), calling
+    /// `on_progress` every time the bootstrap status changes
+    pub fn wait_until_bootstrapped_with<F>(
+        &mut self,
+        timeout: Duration,
+        mut on_progress: F,
+    ) -> Result<BootstrapStatus, ControlError>
+    where
+        F: FnMut(&BootstrapStatus),
+    {
+        let result = self.poll_bootstrap(BootstrapWait::new(timeout), &mut on_progress);
+        self.get_ref().set_read_timeout(None)?;
+
+        result
+    }
+
+    fn poll_bootstrap<F>(
+        &mut self,
+        mut wait: BootstrapWait,
+        on_progress: &mut F,
+    ) -> Result<BootstrapStatus, ControlError>
+    where
+        F: FnMut(&BootstrapStatus),
+    {
+        loop {
+            self.get_ref().set_read_timeout(Some(wait.remaining()?))?;
+            let phase = match self.get_info(PHASE_KEY) {
+                Err(ControlError::Io(e)) if is_timeout(&e) => return Err(ControlError::Timeout),
+                result => result?,
+            };
+            match wait.update(&phase, on_progress)? {
+                WaitStep::Done(status) => return Ok(status),
+                WaitStep::Sleep(duration) => thread::sleep(duration),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+
+    fn phase(status: &str) -> String {
+        format!("250-status/bootstrap-phase={}\r\n250 OK\r\n", status)
+    }
+
+    #[test]
+    fn test_bootstrap_status() {
+        let mut conn = mock(&phase(
+            "NOTICE BOOTSTRAP PROGRESS=14 TAG=handshake SUMMARY=\"Handshaking with a relay\"",
+        ));
+        let status = conn.bootstrap_status().unwrap();
+        assert_eq!(conn.get_ref().sent(), "GETINFO status/bootstrap-phase\r\n");
+        assert_eq!(
+            status,
+            BootstrapStatus {
+                severity: StatusSeverity::Notice,
+                progress: 14,
+                tag: "handshake".into(),
+                summary: "Handshaking with a relay".into(),
+                warning: None,
+            }
+        );
+        assert!(!status.is_done());
+        let handshake = status;
+
+        let mut conn = mock(&phase(concat!(
+            "WARN BOOTSTRAP PROGRESS=5 TAG=conn SUMMARY=\"Connecting to a relay\" ",
+            "WARNING=\"Connection refused\" REASON=CONNECTREFUSED COUNT=12 ",
+            "RECOMMENDATION=warn HOST=$AAAA HOSTADDR=\"1.2.3.4:443\"",
+        )));
+        let status = conn.bootstrap_status().unwrap();
+        assert_eq!(
+            status.warning,
+            Some(BootstrapWarning {
+                warning: "Connection refused".into(),
+                reason: "CONNECTREFUSED".into(),
+                count: Some(12),
+                recommendation: BootstrapRecommendation::Warn,
+                host: Some("$AAAA".into()),
+                host_addr: Some("1.2.3.4:443".into()),
+            })
+        );
+        assert!(status.is_stalled(&status));
+        assert!(status.is_stalled(&handshake));
+        let mut advanced = status.clone();
+        advanced.progress = 15;
+        assert!(!advanced.is_stalled(&handshake));
+        assert_eq!(
+            status.to_string(),
+            "5% (conn): Connecting to a relay [CONNECTREFUSED: Connection refused]"
+        );
+
+        for invalid in &[
+            "NOTICE BOOTSTRAP TAG=done SUMMARY=Done",
+            "NOTICE BOOTSTRAP PROGRESS=101 TAG=done SUMMARY=Done",
+            "NOTICE CIRCUIT_ESTABLISHED",
+        ] {
+            match mock(&phase(invalid)).bootstrap_status() {
+                Err(ControlError::Protocol(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", invalid, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_event() {
+        let event =
+            mock("650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\"\r\n")
+                .recv_event()
+                .unwrap();
+        assert!(event.bootstrap_status().unwrap().is_done());
+
+        let event = mock("650 STATUS_CLIENT NOTICE CIRCUIT_ESTABLISHED\r\n")
+            .recv_event()
+            .unwrap();
+        assert_eq!(event.bootstrap_status(), None);
+    }
+
+    #[test]
+    fn test_wait_until_bootstrapped() {
+        let input = [
+            "NOTICE BOOTSTRAP PROGRESS=0 TAG=starting SUMMARY=\"Starting\"",
+            "NOTICE BOOTSTRAP PROGRESS=0 TAG=starting SUMMARY=\"Starting\"",
+            "NOTICE BOOTSTRAP PROGRESS=50 TAG=loading_descriptors SUMMARY=\"Loading\"",
+            "NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\"",
+        ]
+        .iter()
+        .map(|s| phase(s))
+        .collect::<String>();
+        let mut progress = Vec::new();
+        let status = mock(&input)
+            .wait_until_bootstrapped_with(Duration::from_secs(10), |s| progress.push(s.progress))
+            .unwrap();
+        assert!(status.is_done());
+        assert_eq!(progress, vec![0, 50, 100]);
+
+        let mut conn = mock(&phase(
+            "NOTICE BOOTSTRAP PROGRESS=0 TAG=starting SUMMARY=\"Starting\"",
+        ));
+        match conn.wait_until_bootstrapped(Duration::from_secs(0)) {
+            Err(ControlError::Timeout) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        let input = [
+            concat!(
+                "WARN BOOTSTRAP PROGRESS=5 TAG=conn SUMMARY=\"Connecting\" WARNING=\"No route\" ",
+                "REASON=NOROUTE COUNT=1 RECOMMENDATION=ignore",
+            ),
+            concat!(
+                "WARN BOOTSTRAP PROGRESS=5 TAG=conn SUMMARY=\"Connecting\" WARNING=\"No route\" ",
+                "REASON=NOROUTE COUNT=10 RECOMMENDATION=warn",
+            ),
+        ]
+        .iter()
+        .map(|s| phase(s))
+        .collect::<String>();
+        match mock(&input).wait_until_bootstrapped(Duration::from_secs(10)) {
+            Err(ControlError::BootstrapStalled(status)) => {
+                assert_eq!(status.warning.unwrap().count, Some(10))
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    /// Stream that never answers, as if Tor was wedged
+    #[derive(Default)]
+    struct WedgedStream {
+        timeouts: std::cell::RefCell<Vec<Option<Duration>>>,
+    }
+
+    impl Read for WedgedStream {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for WedgedStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for WedgedStream {
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            self.timeouts.borrow_mut().push(timeout);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_wait_timeout_while_reading() {
+        let mut conn = ControlConnection::new(WedgedStream::default());
+        match conn.wait_until_bootstrapped(Duration::from_secs(10)) {
+            Err(ControlError::Timeout) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        let timeouts = conn.get_ref().timeouts.borrow();
+        assert_eq!(timeouts.len(), 2);
+        assert!(timeouts[0].unwrap() <= Duration::from_secs(10));
+        assert_eq!(timeouts[1], None);
+    }
+
+    #[test]
+    fn test_warnings_while_progressing() {
+        let warning = |progress, tag| {
+            phase(&format!(
+                concat!(
+                    "WARN BOOTSTRAP PROGRESS={} TAG={} SUMMARY=\"Loading\" ",
+                    "WARNING=\"Connection refused\" REASON=CONNECTREFUSED COUNT=3 ",
+                    "RECOMMENDATION=warn",
+                ),
+                progress, tag
+            ))
+        };
+        let input = [
+            warning(10, "conn_done"),
+            warning(20, "onehop_create"),
+            warning(50, "loading_descriptors"),
+            phase("NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\""),
+        ]
+        .concat();
+        let mut progress = Vec::new();
+        let status = mock(&input)
+            .wait_until_bootstrapped_with(Duration::from_secs(10), |s| progress.push(s.progress))
+            .unwrap();
+        assert!(status.is_done());
+        assert_eq!(progress, vec![10, 20, 50, 100]);
+
+        // Stuck with the same warning
+        let input = [
+            warning(50, "loading_descriptors"),
+            warning(50, "loading_descriptors"),
+        ]
+        .concat();
+        match mock(&input).wait_until_bootstrapped(Duration::from_secs(10)) {
+            Err(ControlError::BootstrapStalled(status)) => assert_eq!(status.progress, 50),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
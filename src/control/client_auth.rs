@@ -0,0 +1,296 @@
+//! Client authorization for v3 onion services, with the `ONION_CLIENT_AUTH_*` commands
+
+use std::io::{Read, Write};
+
+use super::parse::{checked_service_id, split_kv, tokenize, value};
+use super::{ControlConnection, ControlError};
+use crate::onion::{ClientAuthFile, OnionAddress, OnionError, X25519SecretKey};
+
+const KEY_TYPE_PREFIX: &str = "x25519:";
+
+keyword_enum! {
+    /// Flag of the credentials added with `ONION_CLIENT_AUTH_ADD`
+    pub enum ClientAuthFlag {
+        /// Store the credentials in `ClientOnionAuthDir`, so that they survive restarts
+        Permanent => "Permanent",
+    }
+}
+
+/// Credentials used by Tor to access an onion service that requires client authorization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionClientAuth {
+    /// Onion address of the service, without `.onion`
+    pub service_id: String,
+    /// Secret key of the client
+    pub key: X25519SecretKey,
+    /// Optional name of the credentials
+    pub client_name: Option<String>,
+    /// Flags of the credentials
+    pub flags: Vec<ClientAuthFlag>,
+}
+
+impl OnionClientAuth {
+    /// Credentials for the service `service_id`, which may end with `.onion`
+    ///
+    /// Fails if `service_id` isn't a valid v3 onion address.
+    pub fn new(service_id: &str, key: X25519SecretKey) -> Result<Self, OnionError> {
+        let address = service_id.parse::<OnionAddress>()?;
+        Ok(OnionClientAuth {
+            service_id: address.service_id(),
+            key,
+            client_name: None,
+            flags: Vec::new(),
+        })
+    }
+
+    /// Returns `true` if the credentials are stored on disk
+    pub fn is_permanent(&self) -> bool {
+        self.flags.contains(&ClientAuthFlag::Permanent)
+    }
+
+    /// Parse a `CLIENT` line, returning `None` for key types other than x25519
+    fn parse(text: &str) -> Result<Option<Self>, ControlError> {
+        let err = || ControlError::Protocol(format!("Invalid client credentials `{}`", text));
+
+        let tokens = tokenize(text)?;
+        let (service_id, key) = match tokens.as_slice() {
+            ["CLIENT", service_id, key, ..] => (*service_id, *key),
+            _ => return Err(err()),
+        };
+        let key = match key.strip_prefix(KEY_TYPE_PREFIX) {
+            Some(key) => X25519SecretKey::from_base64(key).map_err(|_| err())?,
+            // Only x25519 is defined for now, skip the keys added by future versions of Tor
+            None => return Ok(None),
+        };
+
+        let mut auth = OnionClientAuth::new(service_id, key).map_err(|_| err())?;
+        for (k, raw) in tokens[3..].iter().filter_map(|t| split_kv(t)) {
+            match k {
+                "ClientName" => auth.client_name = Some(value(raw)?),
+                "Flags" => auth.flags = raw.split(',').map(ClientAuthFlag::parse).collect(),
+                _ => {}
+            }
+        }
+
+        Ok(Some(auth))
+    }
+}
+
+impl From<ClientAuthFile> for OnionClientAuth {
+    fn from(file: ClientAuthFile) -> Self {
+        OnionClientAuth {
+            service_id: file.service_id,
+            key: file.key,
+            client_name: None,
+            flags: Vec::new(),
+        }
+    }
+}
+
+impl From<OnionClientAuth> for ClientAuthFile {
+    fn from(auth: OnionClientAuth) -> Self {
+        ClientAuthFile {
+            service_id: auth.service_id,
+            key: auth.key,
+        }
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Add credentials for an onion service, replacing the existing ones
+    pub fn onion_client_auth_add(&mut self, auth: &OnionClientAuth) -> Result<(), ControlError> {
+        let mut command = format!(
+            "ONION_CLIENT_AUTH_ADD {} {}{}",
+            checked_service_id(&auth.service_id)?,
+            KEY_TYPE_PREFIX,
+            auth.key.to_base64()
+        );
+        if let Some(name) = &auth.client_name {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(ControlError::InvalidCommand(format!(
+                    "Invalid client name `{}`",
+                    name
+                )));
+            }
+            command.push_str(&format!(" ClientName={}", name));
+        }
+        if !auth.flags.is_empty() {
+            let flags = auth
+                .flags
+                .iter()
+                .map(ClientAuthFlag::as_str)
+                .collect::<Vec<_>>();
+            command.push_str(&format!(" Flags={}", flags.join(",")));
+        }
+
+        self.command(&command).map(|_| ())
+    }
+
+    /// Remove the credentials for an onion service, returning `false` if there were none
+    pub fn onion_client_auth_remove(&mut self, service_id: &str) -> Result<bool, ControlError> {
+        let command = format!(
+            "ONION_CLIENT_AUTH_REMOVE {}",
+            checked_service_id(service_id)?
+        );
+        // 251 means that the credentials didn't exist
+        Ok(self.command(&command)?.code() == 250)
+    }
+
+    /// List the credentials known to Tor, either all of them or only those of `service_id`.
+    ///
+    /// Credentials with a key type other than x25519 are skipped.
+    pub fn onion_client_auth_view(
+        &mut self,
+        service_id: Option<&str>,
+    ) -> Result<Vec<OnionClientAuth>, ControlError> {
+        let command = match service_id {
+            Some(service_id) => {
+                format!("ONION_CLIENT_AUTH_VIEW {}", checked_service_id(service_id)?)
+            }
+            None => "ONION_CLIENT_AUTH_VIEW".to_string(),
+        };
+
+        self.command(&command)?
+            .lines()
+            .iter()
+            .filter(|line| line.text.starts_with("CLIENT "))
+            .filter_map(|line| OnionClientAuth::parse(&line.text).transpose())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+
+    const SERVICE_ID: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+    const OTHER_SERVICE_ID: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+
+    fn key() -> X25519SecretKey {
+        X25519SecretKey::from_bytes([0x11; 32])
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut auth = OnionClientAuth::new(&format!("{}.onion", SERVICE_ID), key()).unwrap();
+        auth.client_name = Some("alice".into());
+        auth.flags.push(ClientAuthFlag::Permanent);
+
+        let mut conn = mock("252 Registered client and stored in file system\r\n");
+        conn.onion_client_auth_add(&auth).unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            format!(
+                "ONION_CLIENT_AUTH_ADD {} x25519:{} ClientName=alice Flags=Permanent\r\n",
+                SERVICE_ID,
+                key().to_base64()
+            )
+        );
+
+        for invalid in &[
+            "",
+            ".onion",
+            "abc def",
+            &format!("{}.onion.onion", SERVICE_ID),
+        ] {
+            match OnionClientAuth::new(invalid, key()) {
+                Err(OnionError::InvalidAddress(_)) => {}
+                r => panic!("Unexpected result for `{}`: {:?}", invalid, r),
+            }
+        }
+
+        auth.client_name = Some("bob smith".into());
+        match mock("").onion_client_auth_add(&auth) {
+            Err(ControlError::InvalidCommand(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        let mut conn =
+            mock("250 OK\r\n251 Client credentials for this onion service did not exist\r\n");
+        assert!(conn.onion_client_auth_remove(SERVICE_ID).unwrap());
+        assert!(!conn.onion_client_auth_remove(SERVICE_ID).unwrap());
+        assert_eq!(
+            conn.get_ref().sent(),
+            format!(
+                "ONION_CLIENT_AUTH_REMOVE {0}\r\nONION_CLIENT_AUTH_REMOVE {0}\r\n",
+                SERVICE_ID
+            )
+        );
+    }
+
+    #[test]
+    fn test_view() {
+        let other = X25519SecretKey::from_bytes([0x22; 32]);
+        let mut conn = mock(&format!(
+            concat!(
+                "250-ONION_CLIENT_AUTH_VIEW\r\n",
+                "250-CLIENT {} x25519:{} ClientName=alice Flags=Permanent,Future\r\n",
+                "250-CLIENT {} x25519:{}\r\n",
+                "250 OK\r\n",
+            ),
+            SERVICE_ID,
+            key().to_base64(),
+            OTHER_SERVICE_ID,
+            other.to_base64(),
+        ));
+        let auths = conn.onion_client_auth_view(None).unwrap();
+        assert_eq!(conn.get_ref().sent(), "ONION_CLIENT_AUTH_VIEW\r\n");
+        assert_eq!(auths.len(), 2);
+        assert_eq!(auths[0].service_id, SERVICE_ID);
+        assert_eq!(auths[0].key, key());
+        assert_eq!(auths[0].client_name.as_deref(), Some("alice"));
+        assert_eq!(
+            auths[0].flags,
+            vec![
+                ClientAuthFlag::Permanent,
+                ClientAuthFlag::Unknown("Future".into())
+            ]
+        );
+        assert!(auths[0].is_permanent());
+        let file = ClientAuthFile::from(auths[0].clone());
+        assert_eq!(file.service_id, SERVICE_ID);
+        assert_eq!(OnionClientAuth::from(file).key, key());
+        assert_eq!(auths[1].service_id, OTHER_SERVICE_ID);
+        assert_eq!(auths[1].key, other);
+        assert!(!auths[1].is_permanent());
+
+        // Unknown key types don't hide the other credentials
+        let mut conn = mock(&format!(
+            concat!(
+                "250-ONION_CLIENT_AUTH_VIEW {0}\r\n",
+                "250-CLIENT {0} x448:AAAA\r\n",
+                "250-CLIENT {0} x25519:{1}\r\n",
+                "250 OK\r\n",
+            ),
+            SERVICE_ID,
+            key().to_base64()
+        ));
+        let auths = conn.onion_client_auth_view(Some(SERVICE_ID)).unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            format!("ONION_CLIENT_AUTH_VIEW {}\r\n", SERVICE_ID)
+        );
+        assert_eq!(auths.len(), 1);
+        assert_eq!(auths[0].key, key());
+
+        let mut conn = mock(&format!(
+            "250-ONION_CLIENT_AUTH_VIEW\r\n250-CLIENT {} x25519:AAAA\r\n250 OK\r\n",
+            SERVICE_ID
+        ));
+        match conn.onion_client_auth_view(None) {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        let mut conn = mock(&format!(
+            "250-ONION_CLIENT_AUTH_VIEW\r\n250-CLIENT {} x25519:{}\r\n250 OK\r\n",
+            "b".repeat(56),
+            key().to_base64()
+        ));
+        match conn.onion_client_auth_view(None) {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
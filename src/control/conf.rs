@@ -0,0 +1,407 @@
+//! Runtime configuration changes with `GETCONF`, `SETCONF`, `RESETCONF` and `SAVECONF`
+//!
+//! Every change made by any controller is announced with a `CONF_CHANGED` event, which is parsed
+//! as [`Event::ConfChanged`](super::Event::ConfChanged) with the same [`ConfValues`] returned by
+//! [`ControlConnection::get_conf`].
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{Read, Write};
+
+use super::parse::{quote, unquote};
+use super::{ControlConnection, ControlError, Reply};
+
+/// Options and their values, in the order sent by Tor, with `None` for options without a value
+///
+/// Options that accept multiple lines, like `Bridge`, appear once for each line.
+pub type ConfValues = Vec<(String, Option<String>)>;
+
+/// Reason why Tor rejected a configuration change, parsed from its error reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfError {
+    /// The option doesn't exist (`552`)
+    UnrecognizedOption(String),
+    /// The value isn't valid for the option (`513`)
+    InvalidValue(String),
+    /// The option can't be changed while Tor is running (`553`)
+    TransitionNotAllowed(String),
+    /// The value is valid, but Tor failed to apply it, for example because a port is already in
+    /// use (`553`)
+    SettingFailed(String),
+    /// Tor couldn't write the configuration file (`551`)
+    SaveFailed(String),
+}
+
+impl ConfError {
+    /// Decode an error reply, returning `None` for codes that aren't specific to configuration
+    /// changes
+    pub fn from_reply(code: u16, message: &str) -> Option<Self> {
+        let strip = |prefix: &str| message.strip_prefix(prefix).unwrap_or(message).to_string();
+
+        Some(match code {
+            552 => ConfError::UnrecognizedOption(strip("Unrecognized option: ")),
+            513 => ConfError::InvalidValue(strip("Unacceptable option value: ")),
+            553 if message.starts_with("Transition not allowed: ") => {
+                ConfError::TransitionNotAllowed(strip("Transition not allowed: "))
+            }
+            553 => ConfError::SettingFailed(strip("Unable to set option: ")),
+            551 => ConfError::SaveFailed(message.to_string()),
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ConfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfError::UnrecognizedOption(msg) => write!(f, "Unrecognized option: {}", msg),
+            ConfError::InvalidValue(msg) => write!(f, "Invalid value: {}", msg),
+            ConfError::TransitionNotAllowed(msg) => write!(f, "Transition not allowed: {}", msg),
+            ConfError::SettingFailed(msg) => write!(f, "Failed to apply the option: {}", msg),
+            ConfError::SaveFailed(msg) => write!(f, "Failed to save the configuration: {}", msg),
+        }
+    }
+}
+
+impl StdError for ConfError {}
+
+/// Set of changes applied atomically by [`ControlConnection::set_conf`]: either all of them are
+/// accepted, or the configuration is left untouched.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tor_sys::control::{ConfChanges, ControlConnection};
+/// let mut conn = ControlConnection::connect_tcp("127.0.0.1:9051")?;
+/// conn.authenticate_null()?;
+/// conn.set_conf(
+///     &ConfChanges::new()
+///         .set("ExitNodes", "{ch},{is}")
+///         .set("StrictNodes", "1")
+///         .reset("Bridge"),
+/// )?;
+/// # Ok::<(), tor_sys::control::ControlError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfChanges {
+    values: ConfValues,
+}
+
+impl ConfChanges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` to `value`, replacing all of its current values
+    pub fn set<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.values.push((key.into(), Some(value.into())));
+        self
+    }
+
+    /// Set an option that accepts multiple lines, like `Bridge`, replacing all of its current
+    /// values
+    pub fn set_list<K, I, V>(mut self, key: K, values: I) -> Self
+    where
+        K: Into<String>,
+        I: IntoIterator<Item = V>,
+        V: Into<String>,
+    {
+        let key = key.into();
+        let len = self.values.len();
+        self.values
+            .extend(values.into_iter().map(|v| (key.clone(), Some(v.into()))));
+        if self.values.len() == len {
+            // An empty list resets the option
+            self.values.push((key, None));
+        }
+        self
+    }
+
+    /// Restore the default value of `key`
+    pub fn reset<K: Into<String>>(mut self, key: K) -> Self {
+        self.values.push((key.into(), None));
+        self
+    }
+
+    /// Returns `true` if there are no changes
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub(super) fn command(&self) -> Result<String, ControlError> {
+        if self.values.is_empty() {
+            return Err(ControlError::InvalidCommand(
+                "SETCONF without options".into(),
+            ));
+        }
+
+        // `SETCONF` clears the options without a value instead of restoring their default, while
+        // `RESETCONF` restores the default before applying the new values: it's only needed
+        // when something is reset, and it still applies all the changes at once.
+        let resets = self.values.iter().any(|(_, value)| value.is_none());
+        let mut command = if resets { "RESETCONF" } else { "SETCONF" }.to_string();
+        for (key, value) in &self.values {
+            command.push(' ');
+            command.push_str(checked_key(key)?);
+            if let Some(value) = value {
+                command.push('=');
+                command.push_str(&quote(value));
+            }
+        }
+
+        Ok(command)
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Query the current values of `keys` with `GETCONF`
+    pub fn get_conf(&mut self, keys: &[&str]) -> Result<ConfValues, ControlError> {
+        let reply = self.command(&get_conf_command(keys)?).map_err(conf_error)?;
+        parse_conf_values(&reply)
+    }
+
+    /// Query all the values of a single option, which is empty if the option isn't set
+    pub fn get_conf_list(&mut self, key: &str) -> Result<Vec<String>, ControlError> {
+        Ok(self
+            .get_conf(&[key])?
+            .into_iter()
+            .filter_map(|(_, value)| value)
+            .collect())
+    }
+
+    /// Apply `changes` atomically, failing with [`ControlError::Conf`] if Tor rejects them
+    pub fn set_conf(&mut self, changes: &ConfChanges) -> Result<(), ControlError> {
+        self.command(&changes.command()?)
+            .map(|_| ())
+            .map_err(conf_error)
+    }
+
+    /// Restore the default values of `keys` atomically
+    pub fn reset_conf(&mut self, keys: &[&str]) -> Result<(), ControlError> {
+        let changes = keys
+            .iter()
+            .fold(ConfChanges::new(), |changes, key| changes.reset(*key));
+        self.set_conf(&changes)
+    }
+
+    /// Write the current configuration to the `torrc` file with `SAVECONF`.
+    ///
+    /// Unless `force` is set, Tor refuses to overwrite a `torrc` that uses `%include`.
+    pub fn save_conf(&mut self, force: bool) -> Result<(), ControlError> {
+        let command = if force { "SAVECONF FORCE" } else { "SAVECONF" };
+        self.command(command).map(|_| ()).map_err(conf_error)
+    }
+}
+
+/// Options are made of letters, digits and `_`, and Tor matches them case-insensitively
+fn checked_key(key: &str) -> Result<&str, ControlError> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(ControlError::InvalidCommand(format!(
+            "Invalid option `{}`",
+            key
+        )));
+    }
+
+    Ok(key)
+}
+
+pub(super) fn get_conf_command(keys: &[&str]) -> Result<String, ControlError> {
+    if keys.is_empty() {
+        return Err(ControlError::InvalidCommand(
+            "GETCONF without options".into(),
+        ));
+    }
+
+    let mut command = "GETCONF".to_string();
+    for key in keys {
+        command.push(' ');
+        command.push_str(checked_key(key)?);
+    }
+
+    Ok(command)
+}
+
+/// Parse the reply to `GETCONF`, where every line is a `key=value` or a `key` without a value,
+/// including the last one
+pub(super) fn parse_conf_values(reply: &Reply) -> Result<ConfValues, ControlError> {
+    reply
+        .lines()
+        .iter()
+        .map(|line| {
+            let text = &line.text;
+            Ok(match text.find('=') {
+                Some(i) if text[i + 1..].starts_with('"') => {
+                    (text[..i].to_string(), Some(unquote(&text[i + 1..])?))
+                }
+                Some(i) => (text[..i].to_string(), Some(text[i + 1..].to_string())),
+                None => (text.clone(), None),
+            })
+        })
+        .collect()
+}
+
+/// Turn the error replies to configuration commands into [`ControlError::Conf`]
+pub(super) fn conf_error(e: ControlError) -> ControlError {
+    match e {
+        ControlError::Reply { code, message } => match ConfError::from_reply(code, &message) {
+            Some(e) => ControlError::Conf(e),
+            None => ControlError::Reply { code, message },
+        },
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+    use crate::control::{Event, EventKind};
+
+    #[test]
+    fn test_get_conf() {
+        let mut conn = mock(concat!(
+            "250-SocksPort=9050\r\n",
+            "250-Bridge=obfs4 192.0.2.1:443 cert=abc iat-mode=0\r\n",
+            "250-Bridge=192.0.2.2:443\r\n",
+            "250-ExitNodes\r\n",
+            "250 DataDirectory=\"/var/lib/my tor\"\r\n",
+            "250 Bridge\r\n",
+            "552 Unrecognized configuration key \"NoSuchOption\"\r\n",
+        ));
+        assert_eq!(
+            conn.get_conf(&["SocksPort", "Bridge", "ExitNodes", "DataDirectory"])
+                .unwrap(),
+            vec![
+                ("SocksPort".into(), Some("9050".into())),
+                (
+                    "Bridge".into(),
+                    Some("obfs4 192.0.2.1:443 cert=abc iat-mode=0".into())
+                ),
+                ("Bridge".into(), Some("192.0.2.2:443".into())),
+                ("ExitNodes".into(), None),
+                ("DataDirectory".into(), Some("/var/lib/my tor".into())),
+            ]
+        );
+        assert!(conn.get_conf_list("Bridge").unwrap().is_empty());
+        match conn.get_conf(&["NoSuchOption"]) {
+            Err(ControlError::Conf(ConfError::UnrecognizedOption(_))) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETCONF SocksPort Bridge ExitNodes DataDirectory\r\nGETCONF Bridge\r\n\
+             GETCONF NoSuchOption\r\n"
+        );
+        assert!(conn.get_conf(&[]).is_err());
+        assert!(conn.get_conf(&["Socks Port"]).is_err());
+    }
+
+    #[test]
+    fn test_set_conf() {
+        let mut conn = mock("250 OK\r\n250 OK\r\n250 OK\r\n");
+        conn.set_conf(
+            &ConfChanges::new()
+                .set("ExitNodes", "{ch},{is}")
+                .set_list("Bridge", vec!["192.0.2.1:443", "192.0.2.2:443"]),
+        )
+        .unwrap();
+        conn.set_conf(
+            &ConfChanges::new()
+                .set("DisableNetwork", "1")
+                .set_list("Bridge", Vec::<String>::new()),
+        )
+        .unwrap();
+        conn.reset_conf(&["ExitNodes", "StrictNodes"]).unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            concat!(
+                "SETCONF ExitNodes=\"{ch},{is}\" Bridge=\"192.0.2.1:443\" ",
+                "Bridge=\"192.0.2.2:443\"\r\n",
+                "RESETCONF DisableNetwork=\"1\" Bridge\r\n",
+                "RESETCONF ExitNodes StrictNodes\r\n",
+            )
+        );
+
+        assert!(ConfChanges::new().command().is_err());
+        assert!(ConfChanges::new().set("Bad\nKey", "1").command().is_err());
+        assert!(ConfChanges::new()
+            .set("Nickname", "a\r\nSIGNAL HALT")
+            .command()
+            .unwrap()
+            .ends_with("Nickname=\"a\\r\\nSIGNAL HALT\""));
+    }
+
+    #[test]
+    fn test_set_conf_errors() {
+        let mut conn = mock(concat!(
+            "552 Unrecognized option: Unknown option 'ExitNode'.  Failing.\r\n",
+            "513 Unacceptable option value: Unrecognized value -1 in SocksPort\r\n",
+            "553 Transition not allowed: While Tor is running, changing DataDirectory is not allowed.\r\n",
+            "553 Unable to set option: Failed to bind one of the listener ports.\r\n",
+            "551 Unable to write configuration to disk.\r\n",
+            "510 Unrecognized command\r\n",
+        ));
+        let mut errors = vec![
+            conn.set_conf(&ConfChanges::new().set("ExitNode", "{ch}")),
+            conn.set_conf(&ConfChanges::new().set("SocksPort", "-1")),
+            conn.set_conf(&ConfChanges::new().set("DataDirectory", "/tmp")),
+            conn.set_conf(&ConfChanges::new().set("SocksPort", "80")),
+            conn.save_conf(true),
+            conn.save_conf(false),
+        ]
+        .into_iter()
+        .map(Result::unwrap_err);
+
+        let mut next_conf_error = || match errors.next() {
+            Some(ControlError::Conf(e)) => e,
+            r => panic!("Unexpected result: {:?}", r),
+        };
+        assert_eq!(
+            next_conf_error(),
+            ConfError::UnrecognizedOption("Unknown option 'ExitNode'.  Failing.".into())
+        );
+        assert_eq!(
+            next_conf_error(),
+            ConfError::InvalidValue("Unrecognized value -1 in SocksPort".into())
+        );
+        assert_eq!(
+            next_conf_error(),
+            ConfError::TransitionNotAllowed(
+                "While Tor is running, changing DataDirectory is not allowed.".into()
+            )
+        );
+        assert_eq!(
+            next_conf_error(),
+            ConfError::SettingFailed("Failed to bind one of the listener ports.".into())
+        );
+        assert_eq!(
+            next_conf_error(),
+            ConfError::SaveFailed("Unable to write configuration to disk.".into())
+        );
+        match errors.next() {
+            Some(ControlError::Reply { code: 510, .. }) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(conn
+            .get_ref()
+            .sent()
+            .ends_with("SAVECONF FORCE\r\nSAVECONF\r\n"));
+    }
+
+    #[test]
+    fn test_conf_changed() {
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "650-CONF_CHANGED\r\n",
+            "650-DisableNetwork=0\r\n",
+            "650-ExitNodes\r\n",
+            "650 OK\r\n",
+        ));
+        conn.set_events(&[EventKind::ConfChanged]).unwrap();
+        let expected: ConfValues = vec![
+            ("DisableNetwork".into(), Some("0".into())),
+            ("ExitNodes".into(), None),
+        ];
+        assert_eq!(conn.recv_event().unwrap(), Event::ConfChanged(expected));
+    }
+}
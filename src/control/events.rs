@@ -0,0 +1,790 @@
+//! Typed asynchronous events, subscribed to with `SETEVENTS`
+//!
+//! Only a subset of the events is parsed into dedicated types. Everything else, including events
+//! added by future versions of Tor, is returned as [`Event::Unknown`] with the raw reply, and
+//! unknown status values or keyword arguments are preserved instead of being rejected.
+
+use std::io::{Read, Write};
+
+use super::parse::{split_kv, tokenize, value};
+use super::{ConfValues, ControlConnection, ControlError, Reply};
+use crate::builder::LogSeverity;
+
+keyword_enum! {
+    /// Type of event that can be subscribed to with [`ControlConnection::set_events`]
+    ///
+    /// Events without a dedicated variant can be requested with [`EventKind::Unknown`], and are
+    /// then returned as [`Event::Unknown`].
+    pub enum EventKind {
+        /// Circuit status changes, parsed as [`CircEvent`]
+        Circ => "CIRC",
+        /// Stream status changes, parsed as [`StreamEvent`]
+        Stream => "STREAM",
+        /// OR connection status changes, parsed as [`OrConnEvent`]
+        OrConn => "ORCONN",
+        /// Bandwidth used in the last second, parsed as [`BandwidthEvent`]
+        Bw => "BW",
+        /// Client status events, like bootstrap progress, parsed as [`StatusEvent`]
+        StatusClient => "STATUS_CLIENT",
+        /// General status events, parsed as [`StatusEvent`]
+        StatusGeneral => "STATUS_GENERAL",
+        /// Onion service descriptor events, parsed as [`HsDescEvent`]
+        HsDesc => "HS_DESC",
+        /// Network reachability changes
+        NetworkLiveness => "NETWORK_LIVENESS",
+        /// Configuration changes
+        ConfChanged => "CONF_CHANGED",
+        /// Log messages at the `debug` severity, parsed as [`LogEvent`]
+        Debug => "DEBUG",
+        /// Log messages at the `info` severity, parsed as [`LogEvent`]
+        Info => "INFO",
+        /// Log messages at the `notice` severity, parsed as [`LogEvent`]
+        Notice => "NOTICE",
+        /// Log messages at the `warn` severity, parsed as [`LogEvent`]
+        Warn => "WARN",
+        /// Log messages at the `err` severity, parsed as [`LogEvent`]
+        Err => "ERR",
+    }
+}
+
+keyword_enum! {
+    /// Status of a circuit in a [`CircEvent`]
+    pub enum CircStatus {
+        Launched => "LAUNCHED",
+        Built => "BUILT",
+        GuardWait => "GUARD_WAIT",
+        Extended => "EXTENDED",
+        Failed => "FAILED",
+        Closed => "CLOSED",
+    }
+}
+
+keyword_enum! {
+    /// Status of a stream in a [`StreamEvent`]
+    pub enum StreamStatus {
+        New => "NEW",
+        NewResolve => "NEWRESOLVE",
+        Remap => "REMAP",
+        SentConnect => "SENTCONNECT",
+        SentResolve => "SENTRESOLVE",
+        Succeeded => "SUCCEEDED",
+        Failed => "FAILED",
+        Closed => "CLOSED",
+        Detached => "DETACHED",
+        ControllerWait => "CONTROLLER_WAIT",
+        XoffSent => "XOFF_SENT",
+        XoffRecv => "XOFF_RECV",
+        XonSent => "XON_SENT",
+        XonRecv => "XON_RECV",
+    }
+}
+
+keyword_enum! {
+    /// Status of an OR connection in an [`OrConnEvent`]
+    pub enum OrConnStatus {
+        New => "NEW",
+        Launched => "LAUNCHED",
+        Connected => "CONNECTED",
+        Failed => "FAILED",
+        Closed => "CLOSED",
+    }
+}
+
+keyword_enum! {
+    /// Severity of a [`StatusEvent`]
+    pub enum StatusSeverity {
+        Notice => "NOTICE",
+        Warn => "WARN",
+        Err => "ERR",
+    }
+}
+
+keyword_enum! {
+    /// Action reported by an [`HsDescEvent`]
+    pub enum HsDescAction {
+        Requested => "REQUESTED",
+        Upload => "UPLOAD",
+        Received => "RECEIVED",
+        Uploaded => "UPLOADED",
+        Ignore => "IGNORE",
+        Failed => "FAILED",
+        Created => "CREATED",
+    }
+}
+
+keyword_enum! {
+    /// State reported by a `NETWORK_LIVENESS` event
+    pub enum NetworkLiveness {
+        Up => "UP",
+        Down => "DOWN",
+    }
+}
+
+/// Keyword arguments of an event that don't have a dedicated field, in the order they were sent
+pub type ExtraArgs = Vec<(String, String)>;
+
+/// `CIRC` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircEvent {
+    /// Identifier of the circuit
+    pub id: String,
+    /// New status of the circuit
+    pub status: CircStatus,
+    /// Relays of the circuit, as `$fingerprint~nickname`
+    pub path: Vec<String>,
+    /// Flags from `BUILD_FLAGS`
+    pub build_flags: Vec<String>,
+    /// `PURPOSE` of the circuit
+    pub purpose: Option<String>,
+    /// `REASON` why the circuit failed or was closed
+    pub reason: Option<String>,
+    /// `REMOTE_REASON` sent by the relay that closed the circuit
+    pub remote_reason: Option<String>,
+    /// Other keyword arguments
+    pub extra: ExtraArgs,
+}
+
+impl CircEvent {
+    /// Parse the part following the event keyword, which is also the format of the lines of
+    /// the `circuit-status` GETINFO key
+    pub(crate) fn parse(text: &str) -> Result<CircEvent, ControlError> {
+        let mut args = Args::parse(text)?;
+        let path = args.optional(2).filter(|s| split_kv(s).is_none());
+        Ok(CircEvent {
+            id: args.positional(0, "CircuitID")?.to_string(),
+            status: CircStatus::parse(args.positional(1, "CircStatus")?),
+            path: list(path),
+            build_flags: list(args.take("BUILD_FLAGS").as_deref()),
+            purpose: args.take("PURPOSE"),
+            reason: args.take("REASON"),
+            remote_reason: args.take("REMOTE_REASON"),
+            extra: args.keywords,
+        })
+    }
+}
+
+/// `STREAM` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEvent {
+    /// Identifier of the stream
+    pub id: String,
+    /// New status of the stream
+    pub status: StreamStatus,
+    /// Circuit the stream is attached to, `0` if it's not attached
+    pub circuit_id: String,
+    /// Destination of the stream, as `address:port`
+    pub target: String,
+    /// `REASON` why the stream failed or was closed
+    pub reason: Option<String>,
+    /// `REMOTE_REASON` sent by the relay that closed the stream
+    pub remote_reason: Option<String>,
+    /// `SOURCE_ADDR` of the application that opened the stream
+    pub source_addr: Option<String>,
+    /// `PURPOSE` of the stream
+    pub purpose: Option<String>,
+    /// Other keyword arguments
+    pub extra: ExtraArgs,
+}
+
+impl StreamEvent {
+    /// Parse the part following the event keyword, which is also the format of the lines of
+    /// the `stream-status` GETINFO key
+    pub(crate) fn parse(text: &str) -> Result<StreamEvent, ControlError> {
+        let mut args = Args::parse(text)?;
+        Ok(StreamEvent {
+            id: args.positional(0, "StreamID")?.to_string(),
+            status: StreamStatus::parse(args.positional(1, "StreamStatus")?),
+            circuit_id: args.positional(2, "CircuitID")?.to_string(),
+            target: args.positional(3, "Target")?.to_string(),
+            reason: args.take("REASON"),
+            remote_reason: args.take("REMOTE_REASON"),
+            source_addr: args.take("SOURCE_ADDR"),
+            purpose: args.take("PURPOSE"),
+            extra: args.keywords,
+        })
+    }
+}
+
+/// `ORCONN` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrConnEvent {
+    /// Relay or address at the other end of the connection
+    pub target: String,
+    /// New status of the connection
+    pub status: OrConnStatus,
+    /// `REASON` why the connection failed or was closed
+    pub reason: Option<String>,
+    /// Number of circuits using the connection (`NCIRCS`)
+    pub circuits: Option<u32>,
+    /// Identifier of the connection (`ID`)
+    pub id: Option<String>,
+    /// Other keyword arguments
+    pub extra: ExtraArgs,
+}
+
+impl OrConnEvent {
+    /// Parse the part following the event keyword, which is also the format of the lines of
+    /// the `orconn-status` GETINFO key
+    pub(crate) fn parse(text: &str) -> Result<OrConnEvent, ControlError> {
+        let mut args = Args::parse(text)?;
+        Ok(OrConnEvent {
+            target: args.positional(0, "Target")?.to_string(),
+            status: OrConnStatus::parse(args.positional(1, "ORStatus")?),
+            reason: args.take("REASON"),
+            circuits: args
+                .take("NCIRCS")
+                .map(|n| number(&n, "NCIRCS"))
+                .transpose()?,
+            id: args.take("ID"),
+            extra: args.keywords,
+        })
+    }
+}
+
+/// `BW` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthEvent {
+    /// Bytes read in the last second
+    pub read: u64,
+    /// Bytes written in the last second
+    pub written: u64,
+    /// Other keyword arguments
+    pub extra: ExtraArgs,
+}
+
+/// `STATUS_CLIENT` or `STATUS_GENERAL` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEvent {
+    /// Severity of the event
+    pub severity: StatusSeverity,
+    /// What happened, like `BOOTSTRAP` or `CIRCUIT_ESTABLISHED`
+    pub action: String,
+    /// Arguments of the action, with quoted values decoded
+    pub arguments: ExtraArgs,
+}
+
+impl StatusEvent {
+    /// Parse the part following the event keyword, which is also the format of the
+    /// `status/*` GETINFO keys
+    pub(crate) fn parse(text: &str) -> Result<StatusEvent, ControlError> {
+        let args = Args::parse(text)?;
+        Ok(StatusEvent {
+            severity: StatusSeverity::parse(args.positional(0, "Severity")?),
+            action: args.positional(1, "Action")?.to_string(),
+            arguments: args.keywords,
+        })
+    }
+
+    /// Returns the value of the argument `key`
+    pub fn argument(&self, key: &str) -> Option<&str> {
+        self.arguments
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// `HS_DESC` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HsDescEvent {
+    /// What happened to the descriptor
+    pub action: HsDescAction,
+    /// Onion address of the service, without `.onion`
+    pub address: String,
+    /// Client authorization type, like `NO_AUTH`
+    pub auth_type: String,
+    /// Directory the descriptor was fetched from or uploaded to, or `UNKNOWN`
+    pub hs_dir: String,
+    /// Identifier of the descriptor, if known
+    pub descriptor_id: Option<String>,
+    /// `REASON` why the action failed
+    pub reason: Option<String>,
+    /// Other keyword arguments
+    pub extra: ExtraArgs,
+}
+
+/// Log message, sent for each of the severities subscribed to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEvent {
+    pub severity: LogSeverity,
+    /// Message, which can span multiple lines
+    pub message: String,
+}
+
+/// Asynchronous event sent by Tor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Circ(CircEvent),
+    Stream(StreamEvent),
+    OrConn(OrConnEvent),
+    Bandwidth(BandwidthEvent),
+    StatusClient(StatusEvent),
+    StatusGeneral(StatusEvent),
+    HsDesc(HsDescEvent),
+    NetworkLiveness(NetworkLiveness),
+    /// Options that were changed, with `None` for options reset to their default value
+    ConfChanged(ConfValues),
+    Log(LogEvent),
+    /// Event without a dedicated type
+    Unknown {
+        /// Keyword identifying the event
+        keyword: String,
+        /// Complete event as received
+        reply: Reply,
+    },
+}
+
+impl Event {
+    /// Parse an asynchronous reply
+    pub fn parse(reply: &Reply) -> Result<Event, ControlError> {
+        if !reply.is_async() {
+            return Err(ControlError::Protocol(format!(
+                "Not an event: `{} {}`",
+                reply.code(),
+                reply.message()
+            )));
+        }
+
+        let first = &reply.lines()[0].text;
+        let (keyword, rest) = match first.find(' ') {
+            Some(i) => (&first[..i], &first[i + 1..]),
+            None => (&first[..], ""),
+        };
+        let unknown = || Event::Unknown {
+            keyword: keyword.to_string(),
+            reply: reply.clone(),
+        };
+
+        let event = match EventKind::parse(keyword) {
+            EventKind::Circ => Event::Circ(CircEvent::parse(rest)?),
+            EventKind::Stream => Event::Stream(StreamEvent::parse(rest)?),
+            EventKind::OrConn => Event::OrConn(OrConnEvent::parse(rest)?),
+            EventKind::Bw => {
+                let args = Args::parse(rest)?;
+                Event::Bandwidth(BandwidthEvent {
+                    read: number(args.positional(0, "BytesRead")?, "BytesRead")?,
+                    written: number(args.positional(1, "BytesWritten")?, "BytesWritten")?,
+                    extra: args.keywords,
+                })
+            }
+            EventKind::StatusClient | EventKind::StatusGeneral => {
+                let status = StatusEvent::parse(rest)?;
+                if keyword == "STATUS_CLIENT" {
+                    Event::StatusClient(status)
+                } else {
+                    Event::StatusGeneral(status)
+                }
+            }
+            EventKind::HsDesc => {
+                let mut args = Args::parse(rest)?;
+                Event::HsDesc(HsDescEvent {
+                    action: HsDescAction::parse(args.positional(0, "Action")?),
+                    address: args.positional(1, "HSAddress")?.to_string(),
+                    auth_type: args.positional(2, "AuthType")?.to_string(),
+                    hs_dir: args.positional(3, "HsDir")?.to_string(),
+                    descriptor_id: args.optional(4).map(str::to_string),
+                    reason: args.take("REASON"),
+                    extra: args.keywords,
+                })
+            }
+            EventKind::NetworkLiveness => {
+                let args = Args::parse(rest)?;
+                Event::NetworkLiveness(NetworkLiveness::parse(args.positional(0, "Status")?))
+            }
+            EventKind::ConfChanged => {
+                // The options are sent on the following lines, and the last one is just `OK`
+                let lines = reply.lines();
+                let options = lines[1..lines.len().saturating_sub(1)]
+                    .iter()
+                    .map(|line| match line.text.find('=') {
+                        Some(i) => (
+                            line.text[..i].to_string(),
+                            Some(line.text[i + 1..].to_string()),
+                        ),
+                        None => (line.text.clone(), None),
+                    })
+                    .collect();
+                Event::ConfChanged(options)
+            }
+            EventKind::Debug
+            | EventKind::Info
+            | EventKind::Notice
+            | EventKind::Warn
+            | EventKind::Err => {
+                let severity = match keyword {
+                    "DEBUG" => LogSeverity::Debug,
+                    "INFO" => LogSeverity::Info,
+                    "NOTICE" => LogSeverity::Notice,
+                    "WARN" => LogSeverity::Warn,
+                    _ => LogSeverity::Err,
+                };
+                // Messages with multiple lines are sent as a data reply
+                let message = match &reply.lines()[0].data {
+                    Some(data) => data.clone(),
+                    None => rest.to_string(),
+                };
+                Event::Log(LogEvent { severity, message })
+            }
+            EventKind::Unknown(_) => unknown(),
+        };
+
+        Ok(event)
+    }
+
+    /// Type of the event
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Circ(_) => EventKind::Circ,
+            Event::Stream(_) => EventKind::Stream,
+            Event::OrConn(_) => EventKind::OrConn,
+            Event::Bandwidth(_) => EventKind::Bw,
+            Event::StatusClient(_) => EventKind::StatusClient,
+            Event::StatusGeneral(_) => EventKind::StatusGeneral,
+            Event::HsDesc(_) => EventKind::HsDesc,
+            Event::NetworkLiveness(_) => EventKind::NetworkLiveness,
+            Event::ConfChanged(_) => EventKind::ConfChanged,
+            Event::Log(log) => match log.severity {
+                LogSeverity::Debug => EventKind::Debug,
+                LogSeverity::Info => EventKind::Info,
+                LogSeverity::Notice => EventKind::Notice,
+                LogSeverity::Warn => EventKind::Warn,
+                LogSeverity::Err => EventKind::Err,
+            },
+            Event::Unknown { keyword, .. } => EventKind::Unknown(keyword.clone()),
+        }
+    }
+}
+
+/// Iterator over the events received on a connection, returned by
+/// [`ControlConnection::events`]
+///
+/// The iterator ends when Tor closes the connection.
+#[derive(Debug)]
+pub struct Events<'a, S: Read + Write> {
+    conn: &'a mut ControlConnection<S>,
+}
+
+impl<'a, S: Read + Write> Iterator for Events<'a, S> {
+    type Item = Result<Event, ControlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.conn.recv_event() {
+            Err(ControlError::Closed) => None,
+            r => Some(r),
+        }
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Subscribe to `events`, replacing the previous subscriptions.
+    ///
+    /// An empty list disables all the events.
+    pub fn set_events(&mut self, events: &[EventKind]) -> Result<(), ControlError> {
+        self.command(&set_events_command(events)?).map(|_| ())
+    }
+
+    /// Wait for the next event and parse it
+    pub fn recv_event(&mut self) -> Result<Event, ControlError> {
+        Event::parse(&self.next_event()?)
+    }
+
+    /// Iterate over the incoming events, blocking while waiting for each of them
+    pub fn events(&mut self) -> Events<'_, S> {
+        Events { conn: self }
+    }
+}
+
+pub(super) fn set_events_command(events: &[EventKind]) -> Result<String, ControlError> {
+    let mut command = "SETEVENTS".to_string();
+    for event in events {
+        let keyword = event.as_str();
+        if keyword.is_empty()
+            || !keyword
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(ControlError::InvalidCommand(format!(
+                "SETEVENTS {}",
+                keyword
+            )));
+        }
+        command.push(' ');
+        command.push_str(keyword);
+    }
+
+    Ok(command)
+}
+
+/// Positional and keyword arguments of an event line
+struct Args<'a> {
+    positional: Vec<&'a str>,
+    keywords: ExtraArgs,
+}
+
+impl<'a> Args<'a> {
+    fn parse(text: &'a str) -> Result<Self, ControlError> {
+        let mut args = Args {
+            positional: Vec::new(),
+            keywords: Vec::new(),
+        };
+        for token in tokenize(text)? {
+            match split_kv(token) {
+                Some((key, raw)) => args.keywords.push((key.to_string(), value(raw)?)),
+                None => args.positional.push(token),
+            }
+        }
+
+        Ok(args)
+    }
+
+    fn optional(&self, index: usize) -> Option<&'a str> {
+        self.positional.get(index).cloned()
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&'a str, ControlError> {
+        self.optional(index)
+            .ok_or_else(|| ControlError::Protocol(format!("Missing {} in event", name)))
+    }
+
+    /// Remove a keyword argument, so that only the unknown ones are left
+    fn take(&mut self, key: &str) -> Option<String> {
+        let index = self.keywords.iter().position(|(k, _)| k == key)?;
+        Some(self.keywords.remove(index).1)
+    }
+}
+
+fn list(s: Option<&str>) -> Vec<String> {
+    s.map(|s| s.split(',').map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+pub(super) fn number<T: std::str::FromStr>(s: &str, name: &str) -> Result<T, ControlError> {
+    s.parse()
+        .map_err(|_| ControlError::Protocol(format!("Invalid {} `{}`", name, s)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+
+    fn parse(input: &str) -> Event {
+        mock(input).recv_event().unwrap()
+    }
+
+    #[test]
+    fn test_circ_and_stream() {
+        let event = parse(concat!(
+            "650 CIRC 12 BUILT $AAAA~relay1,$BBBB~relay2 BUILD_FLAGS=IS_INTERNAL,NEED_CAPACITY ",
+            "PURPOSE=GENERAL TIME_CREATED=2023-01-01T00:00:00.000000\r\n",
+        ));
+        assert_eq!(
+            event,
+            Event::Circ(CircEvent {
+                id: "12".into(),
+                status: CircStatus::Built,
+                path: vec!["$AAAA~relay1".into(), "$BBBB~relay2".into()],
+                build_flags: vec!["IS_INTERNAL".into(), "NEED_CAPACITY".into()],
+                purpose: Some("GENERAL".into()),
+                reason: None,
+                remote_reason: None,
+                extra: vec![("TIME_CREATED".into(), "2023-01-01T00:00:00.000000".into())],
+            })
+        );
+
+        match parse("650 CIRC 3 LAUNCHED BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL\r\n") {
+            Event::Circ(circ) => assert!(circ.path.is_empty()),
+            e => panic!("Unexpected event: {:?}", e),
+        }
+        match parse("650 CIRC 3 REBUILDING REASON=FINISHED\r\n") {
+            Event::Circ(circ) => {
+                assert_eq!(circ.status, CircStatus::Unknown("REBUILDING".into()));
+                assert_eq!(circ.reason.as_deref(), Some("FINISHED"));
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+
+        assert_eq!(
+            parse(concat!(
+                "650 STREAM 42 SUCCEEDED 12 example.com:443 ",
+                "SOURCE_ADDR=127.0.0.1:50000 PURPOSE=USER\r\n",
+            )),
+            Event::Stream(StreamEvent {
+                id: "42".into(),
+                status: StreamStatus::Succeeded,
+                circuit_id: "12".into(),
+                target: "example.com:443".into(),
+                reason: None,
+                remote_reason: None,
+                source_addr: Some("127.0.0.1:50000".into()),
+                purpose: Some("USER".into()),
+                extra: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_orconn_bw_and_liveness() {
+        assert_eq!(
+            parse("650 ORCONN $AAAA~relay1 CLOSED REASON=DONE NCIRCS=2 ID=7\r\n"),
+            Event::OrConn(OrConnEvent {
+                target: "$AAAA~relay1".into(),
+                status: OrConnStatus::Closed,
+                reason: Some("DONE".into()),
+                circuits: Some(2),
+                id: Some("7".into()),
+                extra: vec![],
+            })
+        );
+        assert_eq!(
+            parse("650 BW 1024 2048 OR=10\r\n"),
+            Event::Bandwidth(BandwidthEvent {
+                read: 1024,
+                written: 2048,
+                extra: vec![("OR".into(), "10".into())],
+            })
+        );
+        assert_eq!(
+            parse("650 NETWORK_LIVENESS DOWN\r\n"),
+            Event::NetworkLiveness(NetworkLiveness::Down)
+        );
+
+        match mock("650 BW lots 2048\r\n").recv_event() {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match mock("650 ORCONN $AAAA~relay1\r\n").recv_event() {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_status_and_hs_desc() {
+        assert_eq!(
+            parse(concat!(
+                "650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=50 TAG=loading_descriptors ",
+                "SUMMARY=\"Loading relay descriptors\"\r\n",
+            )),
+            Event::StatusClient(StatusEvent {
+                severity: StatusSeverity::Notice,
+                action: "BOOTSTRAP".into(),
+                arguments: vec![
+                    ("PROGRESS".into(), "50".into()),
+                    ("TAG".into(), "loading_descriptors".into()),
+                    ("SUMMARY".into(), "Loading relay descriptors".into()),
+                ],
+            })
+        );
+        match parse("650 STATUS_GENERAL WARN CLOCK_SKEW SKEW=-300 SOURCE=OR:1.2.3.4:9001\r\n") {
+            Event::StatusGeneral(status) => {
+                assert_eq!(status.severity, StatusSeverity::Warn);
+                assert_eq!(status.arguments[0], ("SKEW".into(), "-300".into()));
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+
+        assert_eq!(
+            parse(concat!(
+                "650 HS_DESC UPLOADED abcdefgh NO_AUTH $AAAA~relay1 ",
+                "zVGGjg0oSfGPI6PrV8APjpNkgIKZn6uXzS6lLY0TjLs HSDIR_INDEX=0123\r\n",
+            )),
+            Event::HsDesc(HsDescEvent {
+                action: HsDescAction::Uploaded,
+                address: "abcdefgh".into(),
+                auth_type: "NO_AUTH".into(),
+                hs_dir: "$AAAA~relay1".into(),
+                descriptor_id: Some("zVGGjg0oSfGPI6PrV8APjpNkgIKZn6uXzS6lLY0TjLs".into()),
+                reason: None,
+                extra: vec![("HSDIR_INDEX".into(), "0123".into())],
+            })
+        );
+        match parse("650 HS_DESC FAILED abcdefgh NO_AUTH UNKNOWN REASON=NOT_FOUND\r\n") {
+            Event::HsDesc(desc) => {
+                assert_eq!(desc.descriptor_id, None);
+                assert_eq!(desc.reason.as_deref(), Some("NOT_FOUND"));
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_conf_changed_and_unknown() {
+        assert_eq!(
+            parse(concat!(
+                "650-CONF_CHANGED\r\n",
+                "650-SocksPort=127.0.0.1:9050 IsolateDestAddr\r\n",
+                "650-ExitPolicy\r\n",
+                "650 OK\r\n",
+            )),
+            Event::ConfChanged(vec![
+                (
+                    "SocksPort".into(),
+                    Some("127.0.0.1:9050 IsolateDestAddr".into())
+                ),
+                ("ExitPolicy".into(), None),
+            ])
+        );
+
+        assert_eq!(
+            parse("650 WARN Problem bootstrapping. Stuck at 5%\r\n"),
+            Event::Log(LogEvent {
+                severity: LogSeverity::Warn,
+                message: "Problem bootstrapping. Stuck at 5%".into(),
+            })
+        );
+        let event = parse("650+NOTICE\r\nfirst line\r\nsecond line\r\n.\r\n650 OK\r\n");
+        assert_eq!(event.kind(), EventKind::Notice);
+        assert_eq!(
+            event,
+            Event::Log(LogEvent {
+                severity: LogSeverity::Notice,
+                message: "first line\nsecond line".into(),
+            })
+        );
+
+        let event = parse("650 NEWCONSENSUS_V2 some future format\r\n");
+        assert_eq!(event.kind(), EventKind::Unknown("NEWCONSENSUS_V2".into()));
+        match event {
+            Event::Unknown { keyword, reply } => {
+                assert_eq!(keyword, "NEWCONSENSUS_V2");
+                assert_eq!(reply.message(), "NEWCONSENSUS_V2 some future format");
+            }
+            e => panic!("Unexpected event: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_set_events() {
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "650 BW 1 2\r\n",
+            "650 ADDRMAP example.com 1.2.3.4 NEVER\r\n",
+            "250 OK\r\n",
+        ));
+        conn.set_events(&[
+            EventKind::Bw,
+            EventKind::ConfChanged,
+            EventKind::Unknown("ADDRMAP".into()),
+        ])
+        .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "SETEVENTS BW CONF_CHANGED ADDRMAP\r\n"
+        );
+        let events = conn.events().collect::<Result<Vec<_>, _>>();
+        // The final `250 OK` is not an event
+        assert!(events.is_err());
+
+        let mut conn = mock("250 OK\r\n650 BW 1 2\r\n650 NETWORK_LIVENESS UP\r\n");
+        conn.set_events(&[]).unwrap();
+        assert_eq!(conn.get_ref().sent(), "SETEVENTS\r\n");
+        let kinds = conn.events().map(|e| e.unwrap().kind()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![EventKind::Bw, EventKind::NetworkLiveness]);
+
+        match mock("").set_events(&[EventKind::Unknown("BW CIRC".into())]) {
+            Err(ControlError::InvalidCommand(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,739 @@
+//! Typed accessors for the most common `GETINFO` keys
+//!
+//! Every key has its own format: the lines of `circuit-status`, `stream-status` and
+//! `orconn-status` are the same as the matching events, so they are returned as [`CircEvent`],
+//! [`StreamEvent`] and [`OrConnEvent`], while the other keys get a dedicated type. Keys without
+//! an accessor can still be read with [`ControlConnection::get_info`].
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use super::events::number;
+use super::parse::{tokenize, unquote};
+use super::{
+    info_value, CircEvent, ControlConnection, ControlError, OrConnEvent, Reply, StreamEvent,
+};
+use crate::encoding::{base64_decode, hex_encode};
+use crate::socks::SocksConnector;
+use crate::version::TorVersion;
+
+keyword_enum! {
+    /// Type of listener, as used by the `net/listeners/*` GETINFO keys
+    pub enum ListenerKind {
+        Socks => "socks",
+        Control => "control",
+        Dns => "dns",
+        Trans => "trans",
+        Natd => "natd",
+        HttpTunnel => "httptunnel",
+        Or => "or",
+        Dir => "dir",
+        ExtOr => "extor",
+        Metrics => "metrics",
+    }
+}
+
+keyword_enum! {
+    /// Status of an [`EntryGuard`]
+    pub enum GuardStatus {
+        Up => "up",
+        Down => "down",
+        NeverConnected => "never-connected",
+        Unusable => "unusable",
+        Unlisted => "unlisted",
+    }
+}
+
+/// Address a listener is bound to, as returned by the `net/listeners/*` GETINFO keys
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Listener {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(addr) => write!(f, "{}", addr),
+            Listener::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Addresses of the listeners used by applications, which is how the ports picked by Tor for
+/// [`Port::Auto`](crate::Port::Auto) can be discovered
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BoundListeners {
+    pub socks: Vec<Listener>,
+    pub control: Vec<Listener>,
+    pub http_tunnel: Vec<Listener>,
+    pub dns: Vec<Listener>,
+}
+
+impl BoundListeners {
+    /// Returns a connector for the first `SocksPort` listener
+    pub fn socks_connector(&self) -> Option<SocksConnector> {
+        self.socks.iter().find_map(SocksConnector::from_listener)
+    }
+}
+
+/// Guard from the `entry-guards` GETINFO key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryGuard {
+    /// Relay, as `$fingerprint~nickname`
+    pub relay: String,
+    /// Status of the guard
+    pub status: GuardStatus,
+    /// When the guard went down or became unusable, as `YYYY-MM-DD HH:MM:SS`
+    pub since: Option<String>,
+}
+
+/// Router status entry from the consensus, as returned by the `ns/id/*` GETINFO keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterStatus {
+    pub nickname: String,
+    /// Base64 encoded identity digest, see [`RouterStatus::fingerprint`]
+    pub identity: String,
+    /// Base64 encoded digest of the server descriptor, or the first 20 bytes of the digest of
+    /// the microdescriptor with the microdescriptor consensus used by clients. Missing from the
+    /// entries of the microdescriptor consensus document.
+    pub digest: Option<String>,
+    /// Publication time of the descriptor, as `YYYY-MM-DD HH:MM:SS`
+    pub published: String,
+    pub address: IpAddr,
+    pub or_port: u16,
+    pub dir_port: u16,
+    /// Additional OR addresses, usually an IPv6 one
+    pub or_addresses: Vec<SocketAddr>,
+    /// Flags assigned by the directory authorities, like `Guard` or `Exit`
+    pub flags: Vec<String>,
+    /// Version of Tor running on the relay
+    pub version: Option<String>,
+    /// Bandwidth estimate, in kilobytes per second
+    pub bandwidth: Option<u64>,
+}
+
+impl RouterStatus {
+    /// Returns the identity fingerprint as 40 uppercase hex characters
+    pub fn fingerprint(&self) -> Option<String> {
+        base64_decode(&self.identity).map(|digest| hex_encode(&digest))
+    }
+
+    /// Returns `true` if the relay has `flag`
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+}
+
+/// Microdescriptor of a relay, as returned by the `md/id/*` GETINFO keys
+///
+/// Only the fields used to select relays are parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Microdescriptor {
+    /// Base64 encoded curve25519 key used for the ntor handshake
+    pub ntor_onion_key: Option<String>,
+    /// Base64 encoded ed25519 identity key
+    pub ed25519_id: Option<String>,
+    /// Relays declared as part of the same family
+    pub family: Vec<String>,
+    /// Summary of the IPv4 exit policy, like `accept 80,443`
+    pub policy: Option<String>,
+    /// Summary of the IPv6 exit policy
+    pub ipv6_policy: Option<String>,
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Query the version of Tor
+    pub fn version(&mut self) -> Result<TorVersion, ControlError> {
+        parse_version(&self.get_info("version")?)
+    }
+
+    /// Query the circuits currently open or being built
+    pub fn circuit_status(&mut self) -> Result<Vec<CircEvent>, ControlError> {
+        parse_lines(&self.get_info("circuit-status")?, CircEvent::parse)
+    }
+
+    /// Query the open streams
+    pub fn stream_status(&mut self) -> Result<Vec<StreamEvent>, ControlError> {
+        parse_lines(&self.get_info("stream-status")?, StreamEvent::parse)
+    }
+
+    /// Query the connections to relays
+    pub fn orconn_status(&mut self) -> Result<Vec<OrConnEvent>, ControlError> {
+        parse_lines(&self.get_info("orconn-status")?, OrConnEvent::parse)
+    }
+
+    /// Query the guards, in order of preference
+    pub fn entry_guards(&mut self) -> Result<Vec<EntryGuard>, ControlError> {
+        parse_lines(&self.get_info("entry-guards")?, parse_entry_guard)
+    }
+
+    /// Query the addresses of the listeners of type `kind`
+    pub fn listeners(&mut self, kind: &ListenerKind) -> Result<Vec<Listener>, ControlError> {
+        parse_listeners(&self.get_info(&listeners_key(kind))?)
+    }
+
+    /// Query the addresses of the `SocksPort`, `ControlPort`, `HTTPTunnelPort` and `DNSPort`
+    /// listeners with a single command
+    pub fn bound_listeners(&mut self) -> Result<BoundListeners, ControlError> {
+        parse_bound_listeners(&self.command(&bound_listeners_command())?)
+    }
+
+    /// Returns `true` if Tor has managed to build a circuit since it started or woke up
+    pub fn circuit_established(&mut self) -> Result<bool, ControlError> {
+        parse_bool(&self.get_info("status/circuit-established")?)
+    }
+
+    /// Query the total number of bytes read since Tor started
+    pub fn traffic_read(&mut self) -> Result<u64, ControlError> {
+        number(&self.get_info("traffic/read")?, "traffic/read")
+    }
+
+    /// Query the total number of bytes written since Tor started
+    pub fn traffic_written(&mut self) -> Result<u64, ControlError> {
+        number(&self.get_info("traffic/written")?, "traffic/written")
+    }
+
+    /// Query the location of the `torrc` file
+    pub fn config_file(&mut self) -> Result<PathBuf, ControlError> {
+        self.get_info("config-file").map(PathBuf::from)
+    }
+
+    /// Query the consensus entry of the relay with the hex fingerprint `relay`
+    pub fn router_status(&mut self, relay: &str) -> Result<RouterStatus, ControlError> {
+        parse_router_status(&self.get_info(&format!("ns/id/{}", relay))?)
+    }
+
+    /// Query the microdescriptor of the relay with the hex fingerprint `relay`
+    pub fn microdescriptor(&mut self, relay: &str) -> Result<Microdescriptor, ControlError> {
+        parse_microdescriptor(&self.get_info(&format!("md/id/{}", relay))?)
+    }
+}
+
+pub(super) fn listeners_key(kind: &ListenerKind) -> String {
+    format!("net/listeners/{}", kind)
+}
+
+/// Read the file written by Tor when `ControlPortWriteToFile` is set, returning the addresses of
+/// the control listeners
+pub fn read_control_port_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<Listener>> {
+    parse_control_port_file(&fs::read_to_string(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+fn parse_control_port_file(content: &str) -> Result<Vec<Listener>, ControlError> {
+    parse_lines(content, |line| {
+        if let Some(addr) = line.strip_prefix("PORT=") {
+            addr.parse()
+                .map(Listener::Tcp)
+                .map_err(|_| ControlError::Protocol(format!("Invalid listener `{}`", addr)))
+        } else if let Some(path) = line.strip_prefix("UNIX_PORT=") {
+            Ok(Listener::Unix(path.into()))
+        } else {
+            Err(ControlError::Protocol(format!(
+                "Invalid control port file line `{}`",
+                line
+            )))
+        }
+    })
+}
+
+const BOUND_LISTENERS: [ListenerKind; 4] = [
+    ListenerKind::Socks,
+    ListenerKind::Control,
+    ListenerKind::HttpTunnel,
+    ListenerKind::Dns,
+];
+
+pub(super) fn bound_listeners_command() -> String {
+    let keys = BOUND_LISTENERS
+        .iter()
+        .map(listeners_key)
+        .collect::<Vec<_>>();
+    format!("GETINFO {}", keys.join(" "))
+}
+
+pub(super) fn parse_bound_listeners(reply: &Reply) -> Result<BoundListeners, ControlError> {
+    let mut listeners = BOUND_LISTENERS
+        .iter()
+        .map(|kind| parse_listeners(&info_value(reply, &listeners_key(kind))?))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter();
+    let mut next = || listeners.next().unwrap_or_default();
+
+    Ok(BoundListeners {
+        socks: next(),
+        control: next(),
+        http_tunnel: next(),
+        dns: next(),
+    })
+}
+
+/// Parse each non-empty line of a multi-line value
+pub(super) fn parse_lines<T, F>(value: &str, parse: F) -> Result<Vec<T>, ControlError>
+where
+    F: Fn(&str) -> Result<T, ControlError>,
+{
+    value
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(parse)
+        .collect()
+}
+
+pub(super) fn parse_bool(value: &str) -> Result<bool, ControlError> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(ControlError::Protocol(format!(
+            "Invalid boolean `{}`",
+            value
+        ))),
+    }
+}
+
+pub(super) fn parse_version(value: &str) -> Result<TorVersion, ControlError> {
+    value
+        .parse()
+        .map_err(|_| ControlError::Protocol(format!("Invalid Tor version `{}`", value)))
+}
+
+pub(super) fn parse_entry_guard(line: &str) -> Result<EntryGuard, ControlError> {
+    let mut parts = line.splitn(3, ' ');
+    let relay = parts.next().unwrap_or_default();
+    let status = parts
+        .next()
+        .ok_or_else(|| ControlError::Protocol(format!("Invalid entry guard `{}`", line)))?;
+
+    Ok(EntryGuard {
+        relay: relay.to_string(),
+        status: GuardStatus::parse(status),
+        since: parts.next().map(str::to_string),
+    })
+}
+
+pub(super) fn parse_listeners(value: &str) -> Result<Vec<Listener>, ControlError> {
+    tokenize(value)?
+        .into_iter()
+        .map(|token| {
+            let addr = unquote(token)?;
+            match addr.strip_prefix("unix:") {
+                Some(path) => Ok(Listener::Unix(path.into())),
+                None => addr
+                    .parse()
+                    .map(Listener::Tcp)
+                    .map_err(|_| ControlError::Protocol(format!("Invalid listener `{}`", addr))),
+            }
+        })
+        .collect()
+}
+
+pub(super) fn parse_router_status(value: &str) -> Result<RouterStatus, ControlError> {
+    let err = |line: &str| ControlError::Protocol(format!("Invalid router status `{}`", line));
+
+    let mut status = None;
+    for line in value.lines() {
+        let (keyword, args) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+        if keyword == "r" {
+            let fields = args.split(' ').collect::<Vec<_>>();
+            // The descriptor digest is only included with the full consensus
+            let (digest, rest) = match fields.len() {
+                8 => (Some(fields[2].to_string()), &fields[3..]),
+                7 => (None, &fields[2..]),
+                _ => return Err(err(line)),
+            };
+            status = Some(RouterStatus {
+                nickname: fields[0].to_string(),
+                identity: fields[1].to_string(),
+                digest,
+                published: format!("{} {}", rest[0], rest[1]),
+                address: rest[2].parse().map_err(|_| err(line))?,
+                or_port: number(rest[3], "ORPort")?,
+                dir_port: number(rest[4], "DirPort")?,
+                or_addresses: Vec::new(),
+                flags: Vec::new(),
+                version: None,
+                bandwidth: None,
+            });
+            continue;
+        }
+
+        let status = status.as_mut().ok_or_else(|| err(line))?;
+        match keyword {
+            "a" => status
+                .or_addresses
+                .push(args.parse().map_err(|_| err(line))?),
+            "s" => status.flags = args.split(' ').map(str::to_string).collect(),
+            "v" => status.version = Some(args.to_string()),
+            "w" => {
+                status.bandwidth = args
+                    .split(' ')
+                    .find_map(|arg| arg.strip_prefix("Bandwidth="))
+                    .map(|bw| number(bw, "Bandwidth"))
+                    .transpose()?
+            }
+            _ => {}
+        }
+    }
+
+    status.ok_or_else(|| err(value))
+}
+
+pub(super) fn parse_microdescriptor(value: &str) -> Result<Microdescriptor, ControlError> {
+    let mut md = Microdescriptor {
+        ntor_onion_key: None,
+        ed25519_id: None,
+        family: Vec::new(),
+        policy: None,
+        ipv6_policy: None,
+    };
+    for line in value.lines() {
+        let (keyword, args) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+        match keyword {
+            "ntor-onion-key" => md.ntor_onion_key = Some(args.to_string()),
+            "id" => {
+                if let Some(key) = args.strip_prefix("ed25519 ") {
+                    md.ed25519_id = Some(key.to_string());
+                }
+            }
+            "family" => md.family = args.split(' ').map(str::to_string).collect(),
+            "p" => md.policy = Some(args.to_string()),
+            "p6" => md.ipv6_policy = Some(args.to_string()),
+            _ => {}
+        }
+    }
+    if md.ntor_onion_key.is_none() {
+        return Err(ControlError::Protocol(
+            "Missing ntor-onion-key in microdescriptor".into(),
+        ));
+    }
+
+    Ok(md)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+    use crate::control::{CircStatus, OrConnStatus, StreamStatus};
+    use crate::socks::SocksProxy;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn test_version() {
+        let mut conn = mock("250-version=0.4.7.13\r\n250 OK\r\n");
+        let version = conn.version().unwrap();
+        assert_eq!(conn.get_ref().sent(), "GETINFO version\r\n");
+        assert_eq!(
+            version,
+            TorVersion {
+                major: 0,
+                minor: 4,
+                micro: 7,
+                patch: 13,
+                status: None,
+                extra: None,
+            }
+        );
+        assert!(version.matches_crate_version());
+
+        match mock("250-version=0.4.7.13 (git\r\n250 OK\r\n").version() {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_status_lists() {
+        // Several lines make a data reply, while a single line is sent as a plain value
+        let mut conn = mock(concat!(
+            "250+circuit-status=\r\n",
+            "5 BUILT $6D9E22C1F4F0E99867F98F2546A9B76D5F08B4CF~libreapp01,",
+            "$BF54EE3193751481579BA7CC7D8E1DF0A01AFB30~gesdm,",
+            "$C5E420FAF05680EE590542AE7216C77602FE68DC~TreeFiddy ",
+            "BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL TIME_CREATED=2023-02-07T10:31:52.118042\r\n",
+            "9 LAUNCHED BUILD_FLAGS=IS_INTERNAL,NEED_CAPACITY PURPOSE=HS_CLIENT_HSDIR ",
+            "TIME_CREATED=2023-02-07T10:32:04.552190\r\n",
+            ".\r\n",
+            "250 OK\r\n",
+            "250-stream-status=12 SUCCEEDED 5 example.com:443\r\n",
+            "250 OK\r\n",
+            "250-orconn-status=$6D9E22C1F4F0E99867F98F2546A9B76D5F08B4CF~libreapp01 CONNECTED\r\n",
+            "250 OK\r\n",
+            "250-stream-status=\r\n",
+            "250 OK\r\n",
+        ));
+
+        let circuits = conn.circuit_status().unwrap();
+        assert_eq!(circuits.len(), 2);
+        assert_eq!(circuits[0].id, "5");
+        assert_eq!(circuits[0].status, CircStatus::Built);
+        assert_eq!(circuits[0].path.len(), 3);
+        assert_eq!(circuits[0].purpose.as_deref(), Some("GENERAL"));
+        assert_eq!(circuits[1].status, CircStatus::Launched);
+        assert!(circuits[1].path.is_empty());
+        assert_eq!(
+            circuits[1].build_flags,
+            vec!["IS_INTERNAL", "NEED_CAPACITY"]
+        );
+
+        let streams = conn.stream_status().unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].status, StreamStatus::Succeeded);
+        assert_eq!(streams[0].circuit_id, "5");
+        assert_eq!(streams[0].target, "example.com:443");
+
+        let conns = conn.orconn_status().unwrap();
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].status, OrConnStatus::Connected);
+
+        assert!(conn.stream_status().unwrap().is_empty());
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETINFO circuit-status\r\nGETINFO stream-status\r\nGETINFO orconn-status\r\n\
+             GETINFO stream-status\r\n"
+        );
+    }
+
+    #[test]
+    fn test_entry_guards() {
+        // Guards that left the consensus are listed without their nickname
+        let mut conn = mock(concat!(
+            "250+entry-guards=\r\n",
+            "$6D9E22C1F4F0E99867F98F2546A9B76D5F08B4CF~libreapp01 up\r\n",
+            "$BF54EE3193751481579BA7CC7D8E1DF0A01AFB30~gesdm never-connected\r\n",
+            "$C5E420FAF05680EE590542AE7216C77602FE68DC~TreeFiddy down 2023-02-07 10:29:14\r\n",
+            "$28090710ABE433A47021F22208B3EC245A912900 unusable 2023-02-06 22:00:00\r\n",
+            ".\r\n",
+            "250 OK\r\n",
+        ));
+        let guards = conn.entry_guards().unwrap();
+        assert_eq!(guards.len(), 4);
+        assert_eq!(
+            guards[0].relay,
+            "$6D9E22C1F4F0E99867F98F2546A9B76D5F08B4CF~libreapp01"
+        );
+        assert_eq!(guards[0].status, GuardStatus::Up);
+        assert_eq!(guards[1].status, GuardStatus::NeverConnected);
+        assert_eq!(guards[2].status, GuardStatus::Down);
+        assert_eq!(guards[2].since.as_deref(), Some("2023-02-07 10:29:14"));
+        assert_eq!(guards[3].relay, "$28090710ABE433A47021F22208B3EC245A912900");
+        assert_eq!(guards[3].status, GuardStatus::Unusable);
+        assert!(parse_entry_guard("$28090710ABE433A47021F22208B3EC245A912900").is_err());
+    }
+
+    #[test]
+    fn test_listeners() {
+        let mut conn = mock(concat!(
+            "250-net/listeners/socks=\"127.0.0.1:9050\" \"[::1]:9050\" ",
+            "\"unix:/run/tor/socks dir/socket\"\r\n",
+            "250 OK\r\n",
+            "250-net/listeners/control=\r\n",
+            "250 OK\r\n",
+        ));
+        assert_eq!(
+            conn.listeners(&ListenerKind::Socks).unwrap(),
+            vec![
+                Listener::Tcp("127.0.0.1:9050".parse().unwrap()),
+                Listener::Tcp("[::1]:9050".parse().unwrap()),
+                Listener::Unix("/run/tor/socks dir/socket".into()),
+            ]
+        );
+        assert!(conn.listeners(&ListenerKind::Control).unwrap().is_empty());
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETINFO net/listeners/socks\r\nGETINFO net/listeners/control\r\n"
+        );
+        assert!(parse_listeners("\"localhost\"").is_err());
+    }
+
+    #[test]
+    fn test_bound_listeners() {
+        let mut conn = mock(concat!(
+            "250-net/listeners/socks=\"127.0.0.1:41517\"\r\n",
+            "250-net/listeners/control=\"127.0.0.1:38215\" \"unix:/run/tor/control\"\r\n",
+            "250-net/listeners/httptunnel=\r\n",
+            "250-net/listeners/dns=\"127.0.0.1:45043\"\r\n",
+            "250 OK\r\n",
+        ));
+        let listeners = conn.bound_listeners().unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETINFO net/listeners/socks net/listeners/control net/listeners/httptunnel \
+             net/listeners/dns\r\n"
+        );
+        assert_eq!(
+            listeners,
+            BoundListeners {
+                socks: vec![Listener::Tcp("127.0.0.1:41517".parse().unwrap())],
+                control: vec![
+                    Listener::Tcp("127.0.0.1:38215".parse().unwrap()),
+                    Listener::Unix("/run/tor/control".into()),
+                ],
+                http_tunnel: vec![],
+                dns: vec![Listener::Tcp("127.0.0.1:45043".parse().unwrap())],
+            }
+        );
+        assert_eq!(
+            listeners.socks_connector().unwrap().proxy(),
+            &SocksProxy::Tcp("127.0.0.1:41517".parse().unwrap())
+        );
+        assert_eq!(BoundListeners::default().socks_connector(), None);
+    }
+
+    #[test]
+    fn test_control_port_file() {
+        assert_eq!(
+            parse_control_port_file(
+                "PORT=127.0.0.1:38215\nPORT=[::1]:38216\nUNIX_PORT=/run/tor/c\n"
+            )
+            .unwrap(),
+            vec![
+                Listener::Tcp("127.0.0.1:38215".parse().unwrap()),
+                Listener::Tcp("[::1]:38216".parse().unwrap()),
+                Listener::Unix("/run/tor/c".into()),
+            ]
+        );
+        assert!(parse_control_port_file("PORT=auto\n").is_err());
+        assert!(parse_control_port_file("127.0.0.1:9051\n").is_err());
+
+        let path = crate::test_utils::test_dir("control-port-file").join("control-port");
+        fs::write(&path, "PORT=127.0.0.1:38215\n").unwrap();
+        assert_eq!(
+            read_control_port_file(&path).unwrap(),
+            vec![Listener::Tcp("127.0.0.1:38215".parse().unwrap())]
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_scalars() {
+        let mut conn = mock(concat!(
+            "250-status/circuit-established=1\r\n250 OK\r\n",
+            "250-traffic/read=1843200\r\n250 OK\r\n",
+            "250-traffic/written=262144\r\n250 OK\r\n",
+            "250-config-file=/etc/tor/torrc\r\n250 OK\r\n",
+        ));
+        assert!(conn.circuit_established().unwrap());
+        assert_eq!(conn.traffic_read().unwrap(), 1843200);
+        assert_eq!(conn.traffic_written().unwrap(), 262144);
+        assert_eq!(conn.config_file().unwrap(), PathBuf::from("/etc/tor/torrc"));
+        assert!(!parse_bool("0").unwrap());
+        assert!(parse_bool("yes").is_err());
+    }
+
+    /// Microdescriptor of `test005r`, generated by Tor from a chutney relay descriptor in
+    /// `src/test/test_microdesc.c`
+    const TEST005R_MD: &str = concat!(
+        "onion-key\n",
+        "-----BEGIN RSA PUBLIC KEY-----\n",
+        "MIGJAoGBAMvEJ/JVNK7I38PPWhQMuCgkET/ki4WIas4tj5Kmqfb9kHqxMR+EunRD\n",
+        "83k4pel1yB7QdV+iTd/4SZOI8RpZP+BO1KnOTWfpztAU1lDGr19/PwdwcHaILpBD\n",
+        "nNzm6otk4/bKUQ0vqpOfJljtg0DfAm4uMAQ6BMFy6uEAF7+JupuPAgMBAAE=\n",
+        "-----END RSA PUBLIC KEY-----\n",
+        "ntor-onion-key FChIfm77vrWB7JsxQ+jMbN6VSSp1P0DYbw/2aqey4iA=\n",
+        "family !Strange $D219590AC9513BCDEBBA9AB721007A4CC01BBAE3 othernode\n",
+        "p accept 1-65535\n",
+        "id ed25519 J5lkRqyL6qW+CpN3E4RIlgJZeLgwjtmOOrjZvVhuwLQ\n",
+    );
+
+    #[test]
+    fn test_router_status() {
+        // Clients use the microdescriptor consensus, so the digest is the beginning of the
+        // microdescriptor digest and Tor doesn't know the version nor the exit policy
+        let mut conn = mock(concat!(
+            "250+ns/id/D219590AC9513BCDEBBA9AB721007A4CC01BBAE3=\r\n",
+            "r test005r 0hlZCslRO83rupq3IQB6TMAbuuM CpdL2uY39avHLlK/ZYwojP5NQ/U ",
+            "2020-10-13 13:27:34 127.0.0.1 5005 7005\r\n",
+            "s Exit Fast HSDir Running Stable V2Dir Valid\r\n",
+            "w Bandwidth=637\r\n",
+            ".\r\n",
+            "250 OK\r\n",
+        ));
+        let status = conn
+            .router_status("D219590AC9513BCDEBBA9AB721007A4CC01BBAE3")
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "GETINFO ns/id/D219590AC9513BCDEBBA9AB721007A4CC01BBAE3\r\n"
+        );
+        assert_eq!(status.nickname, "test005r");
+        assert_eq!(
+            status.fingerprint().as_deref(),
+            Some("D219590AC9513BCDEBBA9AB721007A4CC01BBAE3")
+        );
+        let md_digest = Sha256::digest(TEST005R_MD.as_bytes());
+        assert_eq!(
+            status.digest.as_deref().and_then(base64_decode).as_deref(),
+            Some(&md_digest[..20])
+        );
+        assert_eq!(status.published, "2020-10-13 13:27:34");
+        assert_eq!(status.address, "127.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!((status.or_port, status.dir_port), (5005, 7005));
+        assert!(status.or_addresses.is_empty());
+        assert!(status.has_flag("Exit"));
+        assert!(!status.has_flag("Authority"));
+        assert_eq!(status.version, None);
+        assert_eq!(status.bandwidth, Some(637));
+
+        // Entries of the microdescriptor consensus document don't have the digest
+        let status = parse_router_status(concat!(
+            "r tor26 hHsfhQNE14dkkaVIkvkEk05OuF0 2023-02-07 09:14:21 86.59.21.38 443 80\n",
+            "a [2001:858:2:2:aabb:0:563b:1526]:443\n",
+            "s Authority Fast Running Stable V2Dir Valid\n",
+            "v Tor 0.4.7.13\n",
+            "w Bandwidth=20 Unmeasured=1\n",
+        ))
+        .unwrap();
+        assert_eq!(status.digest, None);
+        assert_eq!(
+            status.fingerprint().as_deref(),
+            Some("847B1F850344D7876491A54892F904934E4EB85D")
+        );
+        assert_eq!(status.address, "86.59.21.38".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            status.or_addresses,
+            vec!["[2001:858:2:2:aabb:0:563b:1526]:443".parse().unwrap()]
+        );
+        assert!(status.has_flag("Authority"));
+        assert_eq!(status.version.as_deref(), Some("Tor 0.4.7.13"));
+        assert_eq!(status.bandwidth, Some(20));
+        assert!(parse_router_status("s Running").is_err());
+        assert!(parse_router_status("r tor26 hHsfhQNE14dkkaVIkvkEk05OuF0").is_err());
+    }
+
+    #[test]
+    fn test_microdescriptor() {
+        let mut conn = mock(&format!(
+            "250+md/id/D219590AC9513BCDEBBA9AB721007A4CC01BBAE3=\r\n{}.\r\n250 OK\r\n",
+            TEST005R_MD.replace('\n', "\r\n")
+        ));
+        let md = conn
+            .microdescriptor("D219590AC9513BCDEBBA9AB721007A4CC01BBAE3")
+            .unwrap();
+        assert_eq!(
+            md,
+            Microdescriptor {
+                ntor_onion_key: Some("FChIfm77vrWB7JsxQ+jMbN6VSSp1P0DYbw/2aqey4iA=".into()),
+                ed25519_id: Some("J5lkRqyL6qW+CpN3E4RIlgJZeLgwjtmOOrjZvVhuwLQ".into()),
+                family: vec![
+                    "!Strange".into(),
+                    "$D219590AC9513BCDEBBA9AB721007A4CC01BBAE3".into(),
+                    "othernode".into()
+                ],
+                policy: Some("accept 1-65535".into()),
+                ipv6_policy: None,
+            }
+        );
+        assert!(parse_microdescriptor("p accept 1-65535").is_err());
+    }
+}
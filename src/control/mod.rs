@@ -0,0 +1,871 @@
+//! Client for Tor's control protocol, as described in `control-spec.txt`
+//!
+//! [`ControlConnection`] handles the framing of the protocol: replies made of multiple `250-`
+//! lines, `250+` data replies with dot-encoding and `650` asynchronous events that can be
+//! interleaved with the replies to commands. Commands can be pipelined by sending them with
+//! [`ControlConnection::send`] and then reading the replies in order with
+//! [`ControlConnection::recv_reply`].
+//!
+//! Events subscribed to with [`ControlConnection::set_events`] are parsed into [`Event`]s by
+//! [`ControlConnection::recv_event`], and the most common `GETINFO` keys have typed accessors
+//! like [`ControlConnection::circuit_status`]. The configuration can be changed at runtime with
+//! [`ControlConnection::set_conf`], and the network can be disabled or put in dormant mode with
+//! [`ControlConnection::set_network_enabled`] and [`ControlConnection::set_dormant`].
+//!
+//! With the `tokio` feature, [`AsyncControlConnection`] offers the same features without blocking
+//! the runtime.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use tor_sys::control::ControlConnection;
+//! let mut conn = ControlConnection::connect_tcp("127.0.0.1:9051")?;
+//! conn.command("AUTHENTICATE")?;
+//! let reply = conn.command("GETINFO version")?;
+//! println!("{}", reply.lines()[0].text);
+//! # Ok::<(), tor_sys::control::ControlError>(())
+//! ```
+
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+
+#[macro_use]
+mod parse;
+#[cfg(feature = "tokio")]
+mod async_client;
+mod auth;
+mod bootstrap;
+mod client_auth;
+mod conf;
+mod events;
+mod info;
+mod network;
+mod onion;
+
+#[cfg(feature = "tokio")]
+pub use self::async_client::AsyncControlConnection;
+pub use self::auth::{
+    hash_password, hash_password_with_salt, read_cookie_file, verify_password, Auth, AuthMethod,
+    ProtocolInfo, COOKIE_LEN,
+};
+pub use self::bootstrap::{BootstrapRecommendation, BootstrapStatus, BootstrapWarning};
+pub use self::client_auth::{ClientAuthFlag, OnionClientAuth};
+pub use self::conf::{ConfChanges, ConfError, ConfValues};
+pub use self::events::{
+    BandwidthEvent, CircEvent, CircStatus, Event, EventKind, Events, ExtraArgs, HsDescAction,
+    HsDescEvent, LogEvent, NetworkLiveness, OrConnEvent, OrConnStatus, StatusEvent, StatusSeverity,
+    StreamEvent, StreamStatus,
+};
+pub use self::info::{
+    read_control_port_file, BoundListeners, EntryGuard, GuardStatus, Listener, ListenerKind,
+    Microdescriptor, RouterStatus,
+};
+pub use self::network::{NetworkChange, Signal};
+pub use self::onion::{AddOnion, OnionFlag, OnionService};
+
+/// Errors returned by the control connection
+#[derive(Debug)]
+pub enum ControlError {
+    /// I/O error on the underlying stream
+    Io(io::Error),
+    /// Tor closed the connection
+    Closed,
+    /// Tor sent something that doesn't follow the control protocol
+    Protocol(String),
+    /// The command contains characters that can't be sent
+    InvalidCommand(String),
+    /// Authentication couldn't be attempted or Tor failed to prove its identity
+    Auth(String),
+    /// Tor replied with an error status
+    Reply { code: u16, message: String },
+    /// The operation didn't complete in time
+    Timeout,
+    /// Bootstrap stopped making progress, as reported by a `BOOTSTRAP` warning
+    BootstrapStalled(Box<BootstrapStatus>),
+    /// Tor rejected a configuration change
+    Conf(ConfError),
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlError::Io(e) => write!(f, "I/O error: {}", e),
+            ControlError::Closed => write!(f, "The control connection was closed"),
+            ControlError::Protocol(msg) => write!(f, "Control protocol error: {}", msg),
+            ControlError::InvalidCommand(cmd) => write!(f, "Invalid command `{}`", cmd),
+            ControlError::Auth(msg) => write!(f, "Authentication error: {}", msg),
+            ControlError::Reply { code, message } => {
+                write!(f, "Tor replied with an error: {} {}", code, message)
+            }
+            ControlError::Timeout => write!(f, "Timed out"),
+            ControlError::BootstrapStalled(status) => write!(f, "Bootstrap stalled: {}", status),
+            ControlError::Conf(e) => write!(f, "Configuration error: {}", e),
+        }
+    }
+}
+
+impl StdError for ControlError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ControlError::Io(e) => Some(e),
+            ControlError::Conf(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ControlError {
+    fn from(e: io::Error) -> Self {
+        ControlError::Io(e)
+    }
+}
+
+/// Single line of a [`Reply`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyLine {
+    /// Status code of the line
+    pub code: u16,
+    /// Text following the status code
+    pub text: String,
+    /// Content of a data reply (`250+`), with the dot-encoding removed and the lines joined with
+    /// `\n`
+    pub data: Option<String>,
+}
+
+/// Complete reply sent by Tor, either in response to a command or as an asynchronous event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    lines: Vec<ReplyLine>,
+}
+
+impl Reply {
+    /// Status code of the reply, taken from its last line
+    pub fn code(&self) -> u16 {
+        self.last().code
+    }
+
+    /// Returns `true` for `2xx` replies
+    pub fn is_success(&self) -> bool {
+        self.code() / 100 == 2
+    }
+
+    /// Returns `true` for asynchronous events (`6xx` replies)
+    pub fn is_async(&self) -> bool {
+        self.code() / 100 == 6
+    }
+
+    /// All the lines of the reply, never empty
+    pub fn lines(&self) -> &[ReplyLine] {
+        &self.lines
+    }
+
+    /// Text of the last line of the reply, which is normally `OK` for successful commands or
+    /// an explanation of the error
+    pub fn message(&self) -> &str {
+        &self.last().text
+    }
+
+    /// Turn a reply with a status other than `2xx` into a [`ControlError::Reply`]
+    pub fn into_result(self) -> Result<Reply, ControlError> {
+        if self.is_success() {
+            Ok(self)
+        } else {
+            Err(ControlError::Reply {
+                code: self.code(),
+                message: self.message().to_string(),
+            })
+        }
+    }
+
+    fn last(&self) -> &ReplyLine {
+        self.lines.last().expect("Replies are never empty")
+    }
+}
+
+/// Connection to Tor's control port
+///
+/// The connection can run on top of any stream: the owning control socket created by
+/// [`TorRunner::owning_controller`](crate::TorRunner::owning_controller) (returned by
+/// [`TorHandle::controller`](crate::TorHandle::controller)), a TCP `ControlPort` or a
+/// `ControlSocket` unix socket.
+#[derive(Debug)]
+pub struct ControlConnection<S: Read + Write> {
+    stream: BufReader<S>,
+    /// Partial line, kept when a read times out or would block
+    line: Vec<u8>,
+    /// Reply being read, kept for the same reason
+    parser: ReplyParser,
+    events: VecDeque<Reply>,
+}
+
+/// Stream whose reads can time out, used to bound the time spent waiting for Tor
+pub trait ReadTimeout {
+    /// Set the read timeout of the stream, `None` blocks indefinitely
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ReadTimeout for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Returns `true` if a read failed because its timeout expired
+fn is_timeout(e: &io::Error) -> bool {
+    // `WouldBlock` on UNIX-like systems, `TimedOut` on Windows
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+impl ControlConnection<TcpStream> {
+    /// Connect to a TCP `ControlPort`
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> Result<Self, ControlError> {
+        Ok(ControlConnection::new(TcpStream::connect(addr)?))
+    }
+}
+
+#[cfg(unix)]
+impl ControlConnection<UnixStream> {
+    /// Connect to a `ControlSocket` or a `ControlPort unix:` listener
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self, ControlError> {
+        Ok(ControlConnection::new(UnixStream::connect(path)?))
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Wrap an already connected stream
+    pub fn new(stream: S) -> Self {
+        ControlConnection {
+            stream: BufReader::new(stream),
+            line: Vec::new(),
+            parser: ReplyParser::default(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying stream
+    pub fn get_ref(&self) -> &S {
+        self.stream.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying stream
+    pub fn get_mut(&mut self) -> &mut S {
+        self.stream.get_mut()
+    }
+
+    /// Returns the underlying stream, discarding any buffered data
+    pub fn into_inner(self) -> S {
+        self.stream.into_inner()
+    }
+
+    /// Send a command without waiting for the reply.
+    ///
+    /// The replies to multiple commands sent in a row are returned in order by
+    /// [`recv_reply`](ControlConnection::recv_reply).
+    pub fn send(&mut self, command: &str) -> Result<(), ControlError> {
+        let line = command_line(command)?;
+
+        let stream = self.stream.get_mut();
+        stream.write_all(line.as_bytes())?;
+        stream.flush()?;
+
+        Ok(())
+    }
+
+    /// Send a multi-line command (like `+LOADCONF`) without waiting for the reply.
+    ///
+    /// `command` is the first line without the leading `+`, while `data` is dot-encoded and
+    /// sent on the following lines.
+    pub fn send_with_data(&mut self, command: &str, data: &str) -> Result<(), ControlError> {
+        if command.is_empty() || command.contains(['\r', '\n']) || data.contains('\r') {
+            return Err(ControlError::InvalidCommand(command.to_string()));
+        }
+
+        let mut buf = format!("+{}\r\n", command);
+        for line in data.lines() {
+            if line.starts_with('.') {
+                buf.push('.');
+            }
+            buf.push_str(line);
+            buf.push_str("\r\n");
+        }
+        buf.push_str(".\r\n");
+
+        let stream = self.stream.get_mut();
+        stream.write_all(buf.as_bytes())?;
+        stream.flush()?;
+
+        Ok(())
+    }
+
+    /// Read the next reply to a command, queueing any asynchronous event received in the
+    /// meantime
+    pub fn recv_reply(&mut self) -> Result<Reply, ControlError> {
+        loop {
+            let reply = self.read_reply()?;
+            if reply.is_async() {
+                self.events.push_back(reply);
+            } else {
+                return Ok(reply);
+            }
+        }
+    }
+
+    /// Send a command and wait for its reply, failing if the status is not `2xx`
+    pub fn command(&mut self, command: &str) -> Result<Reply, ControlError> {
+        self.send(command)?;
+        self.recv_reply()?.into_result()
+    }
+
+    /// Send a multi-line command and wait for its reply, failing if the status is not `2xx`
+    pub fn command_with_data(&mut self, command: &str, data: &str) -> Result<Reply, ControlError> {
+        self.send_with_data(command, data)?;
+        self.recv_reply()?.into_result()
+    }
+
+    /// Send all the commands at once and then collect their replies, in order
+    pub fn pipeline<I, C>(&mut self, commands: I) -> Result<Vec<Reply>, ControlError>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<str>,
+    {
+        let mut count = 0;
+        for command in commands {
+            self.send(command.as_ref())?;
+            count += 1;
+        }
+
+        (0..count).map(|_| self.recv_reply()).collect()
+    }
+
+    /// Query a single `GETINFO` key, returning its value or the content of the data reply
+    pub fn get_info(&mut self, key: &str) -> Result<String, ControlError> {
+        let reply = self.command(&get_info_command(key)?)?;
+        info_value(&reply, key)
+    }
+
+    /// Wait for the next asynchronous event
+    pub fn next_event(&mut self) -> Result<Reply, ControlError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        let reply = self.read_reply()?;
+        if reply.is_async() {
+            Ok(reply)
+        } else {
+            // Replies only arrive in response to commands, and those are all waited for
+            Err(ControlError::Protocol(format!(
+                "Unexpected reply `{} {}`",
+                reply.code(),
+                reply.message()
+            )))
+        }
+    }
+
+    /// Return the asynchronous events already received while waiting for replies, without
+    /// blocking
+    pub fn pending_events(&mut self) -> Vec<Reply> {
+        self.events.drain(..).collect()
+    }
+
+    fn read_line(&mut self) -> Result<String, ControlError> {
+        // On errors `read_until` leaves the bytes it has already read in `self.line`, so that
+        // the next call picks up where this one stopped
+        if self.stream.read_until(b'\n', &mut self.line)? == 0 {
+            return Err(ControlError::Closed);
+        }
+        if self.line.last() != Some(&b'\n') {
+            return Err(ControlError::Closed);
+        }
+        let mut line = std::mem::take(&mut self.line);
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    fn read_reply(&mut self) -> Result<Reply, ControlError> {
+        loop {
+            let line = self.read_line()?;
+            if let Some(reply) = self.parser.push_line(line)? {
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+/// Validate a command and terminate it with CRLF
+fn command_line(command: &str) -> Result<String, ControlError> {
+    if command.is_empty() || command.contains(['\r', '\n']) {
+        return Err(ControlError::InvalidCommand(command.to_string()));
+    }
+
+    Ok(format!("{}\r\n", command))
+}
+
+fn get_info_command(key: &str) -> Result<String, ControlError> {
+    if key.is_empty() || key.contains(' ') {
+        return Err(ControlError::InvalidCommand(format!("GETINFO {}", key)));
+    }
+
+    Ok(format!("GETINFO {}", key))
+}
+
+/// Extract the value of `key` from the reply to `GETINFO`
+fn info_value(reply: &Reply, key: &str) -> Result<String, ControlError> {
+    let prefix = format!("{}=", key);
+    let line = reply
+        .lines()
+        .iter()
+        .find(|line| line.text.starts_with(&prefix))
+        .ok_or_else(|| ControlError::Protocol(format!("Missing `{}` in reply", key)))?;
+
+    Ok(match &line.data {
+        Some(data) => data.clone(),
+        None => line.text[prefix.len()..].to_string(),
+    })
+}
+
+/// Assembles a [`Reply`] from the lines read from the stream, without the line terminators
+#[derive(Debug, Default)]
+struct ReplyParser {
+    lines: Vec<ReplyLine>,
+    /// Lines of the data reply being read, if any
+    data: Option<Vec<String>>,
+}
+
+impl ReplyParser {
+    /// Add the next line, returning the reply once it's complete
+    fn push_line(&mut self, line: String) -> Result<Option<Reply>, ControlError> {
+        if let Some(data) = &mut self.data {
+            if line == "." {
+                let data = self.data.take().map(|data| data.join("\n"));
+                self.lines.last_mut().expect("Data follows a line").data = data;
+            } else {
+                data.push(match line.strip_prefix('.') {
+                    Some(line) => line.to_string(),
+                    None => line,
+                });
+            }
+            return Ok(None);
+        }
+
+        let (code, separator, text) = parse_reply_line(&line)?;
+        if let Some(previous) = self.lines.first().map(|l| l.code) {
+            if previous / 100 != code / 100 {
+                return Err(ControlError::Protocol(format!(
+                    "Mismatched status codes in the same reply: `{}`",
+                    line
+                )));
+            }
+        }
+
+        self.lines.push(ReplyLine {
+            code,
+            text: text.to_string(),
+            data: None,
+        });
+        match separator {
+            '+' => self.data = Some(Vec::new()),
+            ' ' => {
+                return Ok(Some(Reply {
+                    lines: std::mem::take(&mut self.lines),
+                }))
+            }
+            _ => {}
+        }
+
+        Ok(None)
+    }
+}
+
+/// Split a reply line into status code, separator and text
+fn parse_reply_line(line: &str) -> Result<(u16, char, &str), ControlError> {
+    let err = || ControlError::Protocol(format!("Malformed reply line `{}`", line));
+
+    if line.len() < 4 || !line.is_char_boundary(3) || !line.is_char_boundary(4) {
+        return Err(err());
+    }
+    let (code, rest) = line.split_at(3);
+    if !code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(err());
+    }
+    let code = code.parse::<u16>().map_err(|_| err())?;
+    let separator = rest.chars().next().ok_or_else(err)?;
+    if !['-', '+', ' '].contains(&separator) {
+        return Err(err());
+    }
+
+    Ok((code, separator, &rest[1..]))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Stream that replays canned data from Tor and records what is sent to it
+    #[derive(Debug)]
+    pub(crate) struct MockStream {
+        input: Cursor<Vec<u8>>,
+        pub(crate) output: Vec<u8>,
+    }
+
+    impl MockStream {
+        pub(crate) fn new(input: &str) -> Self {
+            MockStream {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+
+        pub(crate) fn sent(&self) -> &str {
+            std::str::from_utf8(&self.output).unwrap()
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for MockStream {
+        fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl tokio::io::AsyncRead for MockStream {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            let n = self.input.read(buf.initialize_unfilled())?;
+            buf.advance(n);
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    impl tokio::io::AsyncWrite for MockStream {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            std::task::Poll::Ready(self.output.write(buf))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    pub(crate) fn mock(input: &str) -> ControlConnection<MockStream> {
+        ControlConnection::new(MockStream::new(input))
+    }
+
+    #[test]
+    fn test_simple_reply() {
+        let mut conn = mock("250 OK\r\n");
+        let reply = conn.command("SIGNAL NEWNYM").unwrap();
+        assert_eq!(reply.code(), 250);
+        assert_eq!(reply.message(), "OK");
+        assert_eq!(conn.get_ref().sent(), "SIGNAL NEWNYM\r\n");
+    }
+
+    /// Stream returning its input in chunks, separated by `WouldBlock` errors
+    struct ChunkedStream(VecDeque<Option<&'static str>>);
+
+    impl Read for ChunkedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Some(chunk)) => {
+                    buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+                    Ok(chunk.len())
+                }
+                Some(None) => Err(io::ErrorKind::WouldBlock.into()),
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl Write for ChunkedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_interrupted_reads() {
+        let mut conn = ControlConnection::new(ChunkedStream(VecDeque::from(vec![
+            Some("250-version=0.4"),
+            None,
+            Some(".7.13\r\n250+config-text=\r\nSocks"),
+            None,
+            Some("Port 9050\r\n.\r\n250 OK\r\n"),
+        ])));
+        for _ in 0..2 {
+            match conn.recv_reply() {
+                Err(ControlError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+                r => panic!("Unexpected result: {:?}", r),
+            }
+        }
+        let reply = conn.recv_reply().unwrap();
+        assert_eq!(reply.lines()[0].text, "version=0.4.7.13");
+        assert_eq!(reply.lines()[1].data.as_deref(), Some("SocksPort 9050"));
+        assert_eq!(reply.message(), "OK");
+        match conn.recv_reply() {
+            Err(ControlError::Closed) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_multi_line_and_data_reply() {
+        let mut conn = mock(concat!(
+            "250-version=0.4.7.13\r\n",
+            "250+config-text=\r\n",
+            "SocksPort 9050\r\n",
+            "..hidden\r\n",
+            ".\r\n",
+            "250 OK\r\n",
+        ));
+        let reply = conn.command("GETINFO version config-text").unwrap();
+        let lines = reply.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text, "version=0.4.7.13");
+        assert_eq!(lines[0].data, None);
+        assert_eq!(lines[1].text, "config-text=");
+        assert_eq!(lines[1].data.as_deref(), Some("SocksPort 9050\n.hidden"));
+        assert_eq!(reply.message(), "OK");
+    }
+
+    #[test]
+    fn test_async_events() {
+        let mut conn = mock(concat!(
+            "650 BW 1024 2048\r\n",
+            "650-CONF_CHANGED\r\n",
+            "650-SocksPort=9050\r\n",
+            "650 OK\r\n",
+            "250 OK\r\n",
+            "650 CIRC 1 LAUNCHED\r\n",
+        ));
+        assert!(conn.command("SETEVENTS BW CONF_CHANGED CIRC").is_ok());
+
+        let events = conn.pending_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].message(), "BW 1024 2048");
+        assert_eq!(events[1].lines()[0].text, "CONF_CHANGED");
+        assert!(conn.pending_events().is_empty());
+
+        let event = conn.next_event().unwrap();
+        assert_eq!(event.message(), "CIRC 1 LAUNCHED");
+        match conn.next_event() {
+            Err(ControlError::Closed) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "650 BW 1 2\r\n",
+            "552 Unrecognized key \"foo\"\r\n",
+            "250-version=0.4.7.13\r\n",
+            "250 OK\r\n",
+        ));
+        let replies = conn
+            .pipeline(vec!["SETEVENTS BW", "GETINFO foo", "GETINFO version"])
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "SETEVENTS BW\r\nGETINFO foo\r\nGETINFO version\r\n"
+        );
+        assert_eq!(replies.len(), 3);
+        assert!(replies[0].is_success());
+        match replies[1].clone().into_result() {
+            Err(ControlError::Reply { code: 552, message }) => {
+                assert_eq!(message, "Unrecognized key \"foo\"")
+            }
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(replies[2].lines()[0].text, "version=0.4.7.13");
+        assert_eq!(conn.pending_events().len(), 1);
+    }
+
+    #[test]
+    fn test_get_info() {
+        let mut conn = mock(concat!(
+            "250-version=0.4.7.13\r\n",
+            "250 OK\r\n",
+            "250+config-text=\r\n",
+            "SocksPort 9050\r\n",
+            ".\r\n",
+            "250 OK\r\n",
+            "250 OK\r\n",
+        ));
+        assert_eq!(conn.get_info("version").unwrap(), "0.4.7.13");
+        assert_eq!(conn.get_info("config-text").unwrap(), "SocksPort 9050");
+        match conn.get_info("version") {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match conn.get_info("a b") {
+            Err(ControlError::InvalidCommand(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_send_with_data() {
+        let mut conn = mock("250 OK\r\n");
+        conn.command_with_data("LOADCONF", "SocksPort 9050\n.dot")
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            "+LOADCONF\r\nSocksPort 9050\r\n..dot\r\n.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut conn = mock("");
+        match conn.send("GETINFO version\r\nSIGNAL HALT") {
+            Err(ControlError::InvalidCommand(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert!(conn.get_ref().sent().is_empty());
+
+        for input in &[
+            "25 OK\r\n",
+            "250OK\r\n",
+            "abc OK\r\n",
+            "250-OK\r\n550 Error\r\n",
+        ] {
+            match mock(input).recv_reply() {
+                Err(ControlError::Protocol(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", input, r),
+            }
+        }
+        // Truncated replies
+        for input in &["250-OK\r\n", "250 OK", "250+data=\r\nfoo\r\n"] {
+            match mock(input).recv_reply() {
+                Err(ControlError::Closed) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", input, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tcp() {
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&mut stream).read_line(&mut line).unwrap();
+            assert_eq!(line, "GETINFO version\r\n");
+            stream
+                .write_all(b"250-version=0.4.7.13\r\n250 OK\r\n")
+                .unwrap();
+        });
+
+        let mut conn = ControlConnection::connect_tcp(addr).unwrap();
+        let reply = conn.command("GETINFO version").unwrap();
+        assert_eq!(reply.lines()[0].text, "version=0.4.7.13");
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owning_controller() {
+        use crate::control_socket::OwningController;
+
+        let (ours, mut tor) = UnixStream::pair().unwrap();
+        let mut conn = ControlConnection::new(OwningController::new(ours));
+        tor.write_all(b"250 OK\r\n").unwrap();
+        assert!(conn.command("TAKEOWNERSHIP").is_ok());
+
+        // Dropping the controller closes the connection
+        drop(conn);
+        let mut sent = String::new();
+        tor.read_to_string(&mut sent).unwrap();
+        assert_eq!(sent, "TAKEOWNERSHIP\r\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix() {
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        let dir = crate::test_utils::test_dir("control-unix");
+        let path = dir.join("control");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&mut stream).read_line(&mut line).unwrap();
+            assert_eq!(line, "SIGNAL NEWNYM\r\n");
+            stream.write_all(b"250 OK\r\n").unwrap();
+        });
+
+        let mut conn = ControlConnection::connect_unix(&path).unwrap();
+        assert!(conn.command("SIGNAL NEWNYM").is_ok());
+        server.join().unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+//! Network participation: `DisableNetwork`, dormant mode and the signals that control them
+//!
+//! Disabling the network closes every connection and stops Tor from making new ones until it's
+//! enabled again, which is what a platform should do when it loses connectivity. Dormant mode is
+//! lighter: Tor stops building circuits and fetching directory information, but wakes up as soon
+//! as a client opens a stream. Tor goes dormant on its own after `DormantClientTimeout`, see
+//! [`TorConfigBuilder::dormant_client_timeout`](crate::TorConfigBuilder::dormant_client_timeout).
+//!
+//! Tor doesn't have a dedicated event for these transitions: [`Event::network_change`] detects
+//! them from the `CONF_CHANGED` events and from the `NOTICE` log messages, which can be
+//! subscribed to with [`NetworkChange::EVENTS`].
+
+use std::io::{Read, Write};
+
+use super::conf::ConfChanges;
+use super::info::parse_bool;
+use super::{ControlConnection, ControlError, Event, EventKind, LogEvent};
+use crate::builder::LogSeverity;
+
+/// Message logged by Tor when it becomes dormant because it wasn't used
+const IDLE_DORMANT_MESSAGE: &str = "No user activity in a long time: becoming dormant.";
+/// Message logged by Tor when it becomes dormant because of `SIGNAL DORMANT`
+const CONTROLLER_DORMANT_MESSAGE: &str = "Going dormant because of controller request.";
+/// Message logged by Tor when it leaves dormant mode
+const ACTIVE_MESSAGE: &str = "Tor is no longer dormant.";
+
+keyword_enum! {
+    /// Signal sent with the `SIGNAL` command
+    pub enum Signal {
+        /// Reload the configuration
+        Reload => "RELOAD",
+        /// Shut down cleanly, waiting for `ShutdownWaitLength` if Tor is a relay
+        Shutdown => "SHUTDOWN",
+        /// Log information about the open connections and circuits
+        Dump => "DUMP",
+        /// Switch all the logs to the debug level
+        Debug => "DEBUG",
+        /// Shut down immediately
+        Halt => "HALT",
+        /// Forget the cached DNS answers
+        ClearDnsCache => "CLEARDNSCACHE",
+        /// Use new circuits for the next streams
+        NewNym => "NEWNYM",
+        /// Log a heartbeat message
+        Heartbeat => "HEARTBEAT",
+        /// Become dormant, until a client opens a stream or [`Signal::Active`] is sent
+        Dormant => "DORMANT",
+        /// Leave dormant mode
+        Active => "ACTIVE",
+    }
+}
+
+/// Change of Tor's network participation, reported by [`Event::network_change`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NetworkChange {
+    /// `DisableNetwork` was set to `0` or reset
+    NetworkEnabled,
+    /// `DisableNetwork` was set to `1`
+    NetworkDisabled,
+    /// Tor became dormant, because it wasn't used for `DormantClientTimeout` or because a
+    /// controller sent [`Signal::Dormant`]
+    Dormant,
+    /// Tor left dormant mode
+    Active,
+}
+
+impl NetworkChange {
+    /// Events needed to detect all the changes
+    pub const EVENTS: [EventKind; 2] = [EventKind::ConfChanged, EventKind::Notice];
+}
+
+impl Event {
+    /// Returns the change of network participation reported by this event, if any
+    pub fn network_change(&self) -> Option<NetworkChange> {
+        match self {
+            Event::ConfChanged(values) => values
+                .iter()
+                .rev()
+                .find(|(key, _)| key.eq_ignore_ascii_case("DisableNetwork"))
+                .map(|(_, value)| match value.as_deref() {
+                    Some("1") => NetworkChange::NetworkDisabled,
+                    _ => NetworkChange::NetworkEnabled,
+                }),
+            Event::Log(LogEvent {
+                severity: LogSeverity::Notice,
+                message,
+            }) => match message.as_str() {
+                IDLE_DORMANT_MESSAGE | CONTROLLER_DORMANT_MESSAGE => Some(NetworkChange::Dormant),
+                ACTIVE_MESSAGE => Some(NetworkChange::Active),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Send a signal to Tor
+    pub fn signal(&mut self, signal: &Signal) -> Result<(), ControlError> {
+        self.command(&signal_command(signal)).map(|_| ())
+    }
+
+    /// Enable or disable the network by changing `DisableNetwork`
+    pub fn set_network_enabled(&mut self, enabled: bool) -> Result<(), ControlError> {
+        self.set_conf(&network_changes(enabled))
+    }
+
+    /// Returns `false` if `DisableNetwork` is set
+    pub fn network_enabled(&mut self) -> Result<bool, ControlError> {
+        let values = self.get_conf_list("DisableNetwork")?;
+        Ok(values.iter().all(|value| value == "0"))
+    }
+
+    /// Enter or leave dormant mode with `SIGNAL DORMANT` and `SIGNAL ACTIVE`
+    pub fn set_dormant(&mut self, dormant: bool) -> Result<(), ControlError> {
+        self.signal(&dormant_signal(dormant))
+    }
+
+    /// Returns `true` if Tor is dormant
+    pub fn is_dormant(&mut self) -> Result<bool, ControlError> {
+        parse_bool(&self.get_info("dormant")?)
+    }
+}
+
+pub(super) fn signal_command(signal: &Signal) -> String {
+    format!("SIGNAL {}", signal)
+}
+
+pub(super) fn network_changes(enabled: bool) -> ConfChanges {
+    ConfChanges::new().set("DisableNetwork", if enabled { "0" } else { "1" })
+}
+
+pub(super) fn dormant_signal(dormant: bool) -> Signal {
+    if dormant {
+        Signal::Dormant
+    } else {
+        Signal::Active
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+
+    #[test]
+    fn test_network_control() {
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "250 DisableNetwork=1\r\n",
+            "250 OK\r\n",
+            "250-dormant=1\r\n250 OK\r\n",
+            "250 OK\r\n",
+            "552 Unrecognized signal\r\n",
+        ));
+        conn.set_network_enabled(false).unwrap();
+        assert!(!conn.network_enabled().unwrap());
+        conn.set_dormant(true).unwrap();
+        assert!(conn.is_dormant().unwrap());
+        conn.signal(&Signal::NewNym).unwrap();
+        match conn.signal(&Signal::Unknown("NOPE".into())) {
+            Err(ControlError::Reply { code: 552, .. }) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(
+            conn.get_ref().sent(),
+            concat!(
+                "SETCONF DisableNetwork=\"1\"\r\n",
+                "GETCONF DisableNetwork\r\n",
+                "SIGNAL DORMANT\r\n",
+                "GETINFO dormant\r\n",
+                "SIGNAL NEWNYM\r\n",
+                "SIGNAL NOPE\r\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_network_change() {
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "650-CONF_CHANGED\r\n650-DisableNetwork=1\r\n650 OK\r\n",
+            "650-CONF_CHANGED\r\n650-DisableNetwork\r\n650 OK\r\n",
+            "650-CONF_CHANGED\r\n650-ExitNodes={ch}\r\n650 OK\r\n",
+            "650 NOTICE No user activity in a long time: becoming dormant.\r\n",
+            "650 NOTICE Bootstrapped 100% (done): Done\r\n",
+            "650 NOTICE Tor is no longer dormant.\r\n",
+        ));
+        conn.set_events(&NetworkChange::EVENTS).unwrap();
+        assert_eq!(conn.get_ref().sent(), "SETEVENTS CONF_CHANGED NOTICE\r\n");
+
+        let changes = (0..6)
+            .map(|_| conn.recv_event().unwrap().network_change())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                Some(NetworkChange::NetworkDisabled),
+                Some(NetworkChange::NetworkEnabled),
+                None,
+                Some(NetworkChange::Dormant),
+                None,
+                Some(NetworkChange::Active),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_controller_dormant() {
+        // Tor logs the transition before replying to the signal
+        let mut conn = mock(concat!(
+            "250 OK\r\n",
+            "650 NOTICE Going dormant because of controller request.\r\n",
+            "250 OK\r\n",
+            "650 NOTICE Tor is no longer dormant.\r\n",
+            "250 OK\r\n",
+        ));
+        conn.set_events(&NetworkChange::EVENTS).unwrap();
+        conn.set_dormant(true).unwrap();
+        assert_eq!(
+            conn.recv_event().unwrap().network_change(),
+            Some(NetworkChange::Dormant)
+        );
+        conn.set_dormant(false).unwrap();
+        assert_eq!(
+            conn.recv_event().unwrap().network_change(),
+            Some(NetworkChange::Active)
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Ephemeral v3 onion services, managed with `ADD_ONION` and `DEL_ONION`
+
+use std::io::{Read, Write};
+
+use super::parse::checked_service_id;
+use super::{ControlConnection, ControlError};
+use crate::builder::HiddenServiceTarget;
+use crate::onion::{OnionAddress, OnionError, OnionSecretKey, X25519PublicKey, ED25519_V3_PREFIX};
+
+keyword_enum! {
+    /// Flag passed to `ADD_ONION`
+    pub enum OnionFlag {
+        /// Don't return the private key of a newly generated service
+        DiscardPK => "DiscardPK",
+        /// Keep the service running after the control connection that created it is closed
+        Detach => "Detach",
+        /// Close the circuit when `MaxStreams` is exceeded, instead of ignoring the new streams
+        MaxStreamsCloseCircuit => "MaxStreamsCloseCircuit",
+        /// Require client authorization, with the keys given with `ClientAuthV3`
+        V3Auth => "V3Auth",
+        /// Single onion service, only allowed if Tor is configured for it
+        NonAnonymous => "NonAnonymous",
+    }
+}
+
+/// Parameters of an `ADD_ONION` command
+///
+/// Unless the service is [detached](OnionFlag::Detach), Tor removes it when the control
+/// connection that created it is closed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tor_sys::control::{AddOnion, ControlConnection};
+/// # use tor_sys::HiddenServiceTarget;
+/// let mut conn = ControlConnection::connect_tcp("127.0.0.1:9051")?;
+/// conn.authenticate_null()?;
+/// let service = conn.add_onion(&AddOnion::new().port(80, HiddenServiceTarget::Port(8080)))?;
+/// println!("Listening on {}.onion", service.service_id);
+/// # Ok::<(), tor_sys::control::ControlError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddOnion {
+    key: Option<OnionSecretKey>,
+    ports: Vec<(u16, HiddenServiceTarget)>,
+    flags: Vec<OnionFlag>,
+    max_streams: Option<u16>,
+    client_auth: Vec<X25519PublicKey>,
+}
+
+impl Default for AddOnion {
+    fn default() -> Self {
+        AddOnion::new()
+    }
+}
+
+impl AddOnion {
+    /// Create a service with a new key generated by Tor
+    pub fn new() -> Self {
+        AddOnion {
+            key: None,
+            ports: Vec::new(),
+            flags: Vec::new(),
+            max_streams: None,
+            client_auth: Vec::new(),
+        }
+    }
+
+    /// Create a service from an existing key
+    pub fn with_key(key: OnionSecretKey) -> Self {
+        AddOnion {
+            key: Some(key),
+            ..AddOnion::new()
+        }
+    }
+
+    /// Forward connections to `virtual_port` to `target`
+    pub fn port(mut self, virtual_port: u16, target: HiddenServiceTarget) -> Self {
+        self.ports.push((virtual_port, target));
+        self
+    }
+
+    /// Set a flag
+    pub fn flag(mut self, flag: OnionFlag) -> Self {
+        if !self.flags.contains(&flag) {
+            self.flags.push(flag);
+        }
+        self
+    }
+
+    /// Limit the number of concurrent streams, `0` meaning unlimited
+    pub fn max_streams(mut self, max_streams: u16) -> Self {
+        self.max_streams = Some(max_streams);
+        self
+    }
+
+    /// Authorize a client, which also sets [`OnionFlag::V3Auth`]
+    pub fn client_auth(mut self, key: X25519PublicKey) -> Self {
+        self.client_auth.push(key);
+        self.flag(OnionFlag::V3Auth)
+    }
+
+    fn command(&self) -> Result<String, ControlError> {
+        let invalid = |msg: &str| ControlError::InvalidCommand(format!("ADD_ONION: {}", msg));
+
+        let mut command = match &self.key {
+            Some(key) => format!("ADD_ONION {}", key.to_blob()),
+            None => "ADD_ONION NEW:ED25519-V3".to_string(),
+        };
+        if !self.flags.is_empty() {
+            let flags = self.flags.iter().map(OnionFlag::as_str).collect::<Vec<_>>();
+            command.push_str(&format!(" Flags={}", flags.join(",")));
+        }
+        if let Some(max_streams) = self.max_streams {
+            command.push_str(&format!(" MaxStreams={}", max_streams));
+        }
+
+        if self.ports.is_empty() {
+            return Err(invalid("the service has no ports"));
+        }
+        for (virtual_port, target) in &self.ports {
+            let target = match target {
+                _ if *virtual_port == 0 => return Err(invalid("virtual ports can't be 0")),
+                HiddenServiceTarget::Port(0) => return Err(invalid("target ports can't be 0")),
+                HiddenServiceTarget::Port(port) => port.to_string(),
+                HiddenServiceTarget::Addr(addr) => addr.to_string(),
+                HiddenServiceTarget::Unix(path) => match path.to_str() {
+                    // The arguments of ADD_ONION are separated by spaces and can't be quoted
+                    Some(s) if path.is_absolute() && !s.contains([' ', '"', '\\']) => {
+                        format!("unix:{}", s)
+                    }
+                    _ => {
+                        return Err(invalid(&format!(
+                            "unsupported unix socket path `{}`",
+                            path.display()
+                        )))
+                    }
+                },
+            };
+            command.push_str(&format!(" Port={},{}", virtual_port, target));
+        }
+
+        for key in &self.client_auth {
+            command.push_str(&format!(" ClientAuthV3={}", key));
+        }
+
+        Ok(command)
+    }
+}
+
+/// Onion service created by `ADD_ONION`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionService {
+    /// Onion address of the service, without `.onion`
+    pub service_id: String,
+    /// Key generated by Tor, unless [`OnionFlag::DiscardPK`] was set or the key was supplied
+    pub private_key: Option<OnionSecretKey>,
+}
+
+impl OnionService {
+    /// Parse and validate the address of the service
+    pub fn address(&self) -> Result<OnionAddress, OnionError> {
+        self.service_id.parse()
+    }
+}
+
+impl<S: Read + Write> ControlConnection<S> {
+    /// Create an ephemeral onion service
+    pub fn add_onion(&mut self, request: &AddOnion) -> Result<OnionService, ControlError> {
+        let reply = self.command(&request.command()?)?;
+
+        let mut service_id = None;
+        let mut private_key = None;
+        for line in reply.lines() {
+            if let Some(id) = line.text.strip_prefix("ServiceID=") {
+                service_id = Some(id.to_string());
+            } else if let Some(blob) = line.text.strip_prefix("PrivateKey=") {
+                if !blob.starts_with(ED25519_V3_PREFIX) {
+                    return Err(ControlError::Protocol(format!(
+                        "Unexpected key type in `{}`",
+                        line.text
+                    )));
+                }
+                private_key = Some(
+                    OnionSecretKey::from_blob(blob)
+                        .map_err(|e| ControlError::Protocol(e.to_string()))?,
+                );
+            }
+        }
+
+        Ok(OnionService {
+            service_id: service_id
+                .ok_or_else(|| ControlError::Protocol("Missing ServiceID in reply".into()))?,
+            private_key,
+        })
+    }
+
+    /// Remove an onion service created with `ADD_ONION`
+    pub fn del_onion(&mut self, service_id: &str) -> Result<(), ControlError> {
+        let command = format!("DEL_ONION {}", checked_service_id(service_id)?);
+        self.command(&command).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::control::test::mock;
+
+    const SERVICE_ID: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+
+    fn key() -> OnionSecretKey {
+        OnionSecretKey::from_bytes([7; 64])
+    }
+
+    #[test]
+    fn test_add_onion_new() {
+        let mut conn = mock(&format!(
+            "250-ServiceID={}\r\n250-PrivateKey={}\r\n250 OK\r\n",
+            SERVICE_ID,
+            key().to_blob()
+        ));
+        let service = conn
+            .add_onion(
+                &AddOnion::new()
+                    .port(80, HiddenServiceTarget::Port(8080))
+                    .port(
+                        443,
+                        HiddenServiceTarget::Addr("127.0.0.1:8443".parse().unwrap()),
+                    )
+                    .port(22, HiddenServiceTarget::Unix("/run/ssh.sock".into()))
+                    .flag(OnionFlag::Detach)
+                    .flag(OnionFlag::MaxStreamsCloseCircuit)
+                    .max_streams(10),
+            )
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            concat!(
+                "ADD_ONION NEW:ED25519-V3 Flags=Detach,MaxStreamsCloseCircuit MaxStreams=10 ",
+                "Port=80,8080 Port=443,127.0.0.1:8443 Port=22,unix:/run/ssh.sock\r\n"
+            )
+        );
+        assert_eq!(
+            service,
+            OnionService {
+                service_id: SERVICE_ID.into(),
+                private_key: Some(key()),
+            }
+        );
+        assert_eq!(service.address().unwrap().service_id(), SERVICE_ID);
+    }
+
+    #[test]
+    fn test_add_onion_existing_key() {
+        let client = X25519PublicKey::from_bytes([1; 32]);
+        let mut conn = mock(&format!("250-ServiceID={}\r\n250 OK\r\n", SERVICE_ID));
+        let service = conn
+            .add_onion(
+                &AddOnion::with_key(key())
+                    .port(80, HiddenServiceTarget::Port(8080))
+                    .flag(OnionFlag::DiscardPK)
+                    .client_auth(client),
+            )
+            .unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            format!(
+                "ADD_ONION {} Flags=DiscardPK,V3Auth Port=80,8080 ClientAuthV3={}\r\n",
+                key().to_blob(),
+                client.to_base32()
+            )
+        );
+        assert_eq!(service.private_key, None);
+    }
+
+    #[test]
+    fn test_add_onion_invalid() {
+        for request in &[
+            AddOnion::new(),
+            AddOnion::new().port(0, HiddenServiceTarget::Port(80)),
+            AddOnion::new().port(80, HiddenServiceTarget::Port(0)),
+            AddOnion::new().port(80, HiddenServiceTarget::Unix("/run/my app.sock".into())),
+            AddOnion::new().port(80, HiddenServiceTarget::Unix("app.sock".into())),
+        ] {
+            match mock("").add_onion(request) {
+                Err(ControlError::InvalidCommand(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", request, r),
+            }
+        }
+
+        let mut conn = mock("512 Bad arguments to ADD_ONION: Unknown flag\r\n");
+        match conn.add_onion(&AddOnion::new().port(80, HiddenServiceTarget::Port(80))) {
+            Err(ControlError::Reply { code: 512, .. }) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        let mut conn = mock("250-PrivateKey=RSA1024:AAAA\r\n250 OK\r\n");
+        match conn.add_onion(&AddOnion::new().port(80, HiddenServiceTarget::Port(80))) {
+            Err(ControlError::Protocol(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_del_onion() {
+        let mut conn = mock("250 OK\r\n");
+        conn.del_onion(&format!("{}.onion", SERVICE_ID)).unwrap();
+        assert_eq!(
+            conn.get_ref().sent(),
+            format!("DEL_ONION {}\r\n", SERVICE_ID)
+        );
+
+        for invalid in &["abc def", ".onion", &format!("{}.onion.onion", SERVICE_ID)] {
+            match mock("").del_onion(invalid) {
+                Err(ControlError::InvalidCommand(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", invalid, r),
+            }
+        }
+    }
+}
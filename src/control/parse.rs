@@ -0,0 +1,236 @@
+//! Helpers to build and parse the arguments of control commands and replies
+
+use super::ControlError;
+
+/// Define an enum of protocol keywords, with an `Unknown` variant preserving the values that
+/// aren't known to this crate
+macro_rules! keyword_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $( $(#[$vmeta:meta])* $variant:ident => $keyword:expr, )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $( $(#[$vmeta])* $variant, )*
+            /// A value not known to this crate
+            Unknown(String),
+        }
+
+        impl $name {
+            /// Keyword used by the control protocol
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $( $name::$variant => $keyword, )*
+                    $name::Unknown(s) => s,
+                }
+            }
+
+            /// Parse a keyword, keeping the unknown ones in `Unknown`
+            pub fn parse(s: &str) -> Self {
+                match s {
+                    $( $keyword => $name::$variant, )*
+                    other => $name::Unknown(other.to_string()),
+                }
+            }
+        }
+
+        impl ::std::fmt::Display for $name {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+    };
+}
+
+/// Encode `s` as a `QuotedString`
+pub(crate) fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Decode a `QuotedString`, which must span the whole of `s`
+pub(crate) fn unquote(s: &str) -> Result<String, ControlError> {
+    let err = || ControlError::Protocol(format!("Invalid quoted string `{}`", s));
+
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(err)?;
+
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next().ok_or_else(err)? {
+                'n' => bytes.push(b'\n'),
+                'r' => bytes.push(b'\r'),
+                't' => bytes.push(b'\t'),
+                'x' => {
+                    let hex = chars.by_ref().take(2).collect::<String>();
+                    if hex.len() != 2 {
+                        return Err(err());
+                    }
+                    bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| err())?);
+                }
+                c @ '0'..='7' => {
+                    let mut octal = c.to_string();
+                    octal.extend(chars.by_ref().take(2));
+                    if octal.len() != 3 {
+                        return Err(err());
+                    }
+                    bytes.push(u8::from_str_radix(&octal, 8).map_err(|_| err())?);
+                }
+                c => {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            },
+            '"' => return Err(err()),
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Split `s` on spaces, keeping quoted strings (even after `key=`) in a single token
+pub(crate) fn tokenize(s: &str) -> Result<Vec<&str>, ControlError> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if in_quotes {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quotes = false;
+            }
+            continue;
+        }
+
+        match c {
+            ' ' => {
+                if let Some(start) = start.take() {
+                    tokens.push(&s[start..i]);
+                }
+            }
+            c => {
+                if start.is_none() {
+                    start = Some(i);
+                }
+                if c == '"' {
+                    in_quotes = true;
+                }
+            }
+        }
+    }
+    if in_quotes {
+        return Err(ControlError::Protocol(format!(
+            "Unterminated quote in `{}`",
+            s
+        )));
+    }
+    if let Some(start) = start {
+        tokens.push(&s[start..]);
+    }
+
+    Ok(tokens)
+}
+
+/// Split a `key=value` token, where the key is made of alphanumeric characters, `_` and `-`
+pub(crate) fn split_kv(token: &str) -> Option<(&str, &str)> {
+    let (key, value) = token.split_at(token.find('=')?);
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+
+    Some((key, &value[1..]))
+}
+
+/// Decode a value that may or may not be quoted
+pub(crate) fn value(raw: &str) -> Result<String, ControlError> {
+    if raw.starts_with('"') {
+        unquote(raw)
+    } else {
+        Ok(raw.to_string())
+    }
+}
+
+/// Strip `.onion` from an onion address and check that it can be sent as an argument
+pub(crate) fn checked_service_id(service_id: &str) -> Result<&str, ControlError> {
+    let service_id = service_id.strip_suffix(".onion").unwrap_or(service_id);
+    if service_id.is_empty() || !service_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ControlError::InvalidCommand(format!(
+            "Invalid onion address `{}`",
+            service_id
+        )));
+    }
+
+    Ok(service_id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        let s = "pass \"word\"\\\n";
+        assert_eq!(quote(s), "\"pass \\\"word\\\"\\\\\\n\"");
+        assert_eq!(unquote(&quote(s)).unwrap(), s);
+
+        assert_eq!(unquote("\"a\\101\\x42\"").unwrap(), "aAB");
+        assert!(unquote("\"abc").is_err());
+        assert!(unquote("\"a\"b\"").is_err());
+        assert!(unquote("\"a\\\"").is_err());
+        assert!(unquote("\"\\12\"").is_err());
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens =
+            tokenize("AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"/a b/c\\\"d\"  X").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                "AUTH",
+                "METHODS=COOKIE,SAFECOOKIE",
+                "COOKIEFILE=\"/a b/c\\\"d\"",
+                "X"
+            ]
+        );
+        assert_eq!(split_kv(tokens[1]), Some(("METHODS", "COOKIE,SAFECOOKIE")));
+        assert_eq!(value(split_kv(tokens[2]).unwrap().1).unwrap(), "/a b/c\"d");
+        assert_eq!(split_kv(tokens[0]), None);
+        assert_eq!(split_kv("a+b/c="), None);
+        assert!(tokenize("A=\"unterminated").is_err());
+        assert!(tokenize("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! Owned handle for the control socket created by
+//! [`tor_main_configuration_setup_control_socket`](crate::tor_main_configuration_setup_control_socket)
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+#[cfg(windows)]
+use std::os::windows::io::{FromRawSocket, RawSocket};
+
+use crate::control::ReadTimeout;
+use crate::{
+    tor_main_configuration_setup_control_socket, tor_main_configuration_t,
+    INVALID_TOR_CONTROL_SOCKET,
+};
+
+/// Stream connected to the controller end of the socket pair created by Tor.
+///
+/// Tor creates the pair with `socketpair(AF_UNIX, ..)` on UNIX-like systems, and emulates it with
+/// a pair of connected loopback TCP sockets on Windows.
+#[cfg(unix)]
+pub type ControlStream = std::os::unix::net::UnixStream;
+/// Stream connected to the controller end of the socket pair created by Tor.
+///
+/// Tor creates the pair with `socketpair(AF_UNIX, ..)` on UNIX-like systems, and emulates it with
+/// a pair of connected loopback TCP sockets on Windows.
+#[cfg(windows)]
+pub type ControlStream = std::net::TcpStream;
+
+/// Ask Tor to create a control socket for `config` and take ownership of our end of it.
+///
+/// The other end is kept by the configuration and passed to Tor as `__OwningControllerFD`, which
+/// means that the connection is already authenticated and that Tor will shut down once it's
+/// closed. Only one control socket can be created for each configuration: subsequent calls will
+/// fail.
+///
+/// # Safety
+///
+/// `config` must be a valid pointer returned by
+/// [`tor_main_configuration_new`](crate::tor_main_configuration_new) that hasn't been freed yet.
+pub unsafe fn setup_control_socket(
+    config: *mut tor_main_configuration_t,
+) -> io::Result<ControlStream> {
+    let socket = tor_main_configuration_setup_control_socket(config);
+    if socket == INVALID_TOR_CONTROL_SOCKET {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Tor failed to create the control socket",
+        ));
+    }
+
+    #[cfg(unix)]
+    let stream = ControlStream::from_raw_fd(socket);
+    #[cfg(windows)]
+    let stream = ControlStream::from_raw_socket(socket as RawSocket);
+
+    Ok(stream)
+}
+
+/// Owning controller connection: Tor shuts down as soon as this handle is dropped.
+///
+/// The connection is created with [`setup_control_socket`], so Tor registers it through
+/// `__OwningControllerFD`: this is the in-process equivalent of sending `TAKEOWNERSHIP` on a
+/// regular control connection, and it also means that the connection starts already
+/// authenticated. Dropping the handle shuts the socket down in both directions, which makes Tor
+/// exit even if the underlying stream has been duplicated in the meantime.
+#[derive(Debug)]
+pub struct OwningController {
+    stream: ControlStream,
+}
+
+impl OwningController {
+    pub(crate) fn new(stream: ControlStream) -> Self {
+        OwningController { stream }
+    }
+
+    /// Returns the underlying stream, which can be used to send commands to Tor
+    pub fn stream(&self) -> &ControlStream {
+        &self.stream
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl OwningController {
+    /// Register the stream with Tokio, keeping the ownership semantics.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn into_async(self) -> io::Result<AsyncOwningController> {
+        let stream = self.stream.try_clone()?;
+        stream.set_nonblocking(true)?;
+
+        Ok(AsyncOwningController {
+            #[cfg(unix)]
+            stream: tokio::net::UnixStream::from_std(stream)?,
+            #[cfg(windows)]
+            stream: tokio::net::TcpStream::from_std(stream)?,
+            _owner: self,
+        })
+    }
+}
+
+impl Read for OwningController {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for OwningController {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl ReadTimeout for OwningController {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+impl Drop for OwningController {
+    fn drop(&mut self) {
+        // Tor might have already exited and closed its end
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Async version of [`OwningController`], available with the `tokio` feature: Tor shuts down as
+/// soon as this handle is dropped.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncOwningController {
+    #[cfg(unix)]
+    stream: tokio::net::UnixStream,
+    #[cfg(windows)]
+    stream: tokio::net::TcpStream,
+    /// Shuts the socket down when dropped, which also closes `stream`
+    _owner: OwningController,
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for AsyncOwningController {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for AsyncOwningController {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
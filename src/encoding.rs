@@ -0,0 +1,147 @@
+//! Small helpers for the text encodings used by Tor
+
+/// Encode `data` as uppercase hex, like Tor does
+pub(crate) fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Decode a hex string, accepting both upper and lower case digits
+pub(crate) fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 == 1 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Encode `data` with `bits` bits per character, padding the last character with zeros
+fn encode_bits(data: &[u8], bits: u32, alphabet: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 8 / bits as usize + 1);
+    let mut acc = 0u32;
+    let mut len = 0;
+    for byte in data {
+        acc = (acc << 8) | u32::from(*byte);
+        len += 8;
+        while len >= bits {
+            len -= bits;
+            out.push(alphabet[((acc >> len) & ((1 << bits) - 1)) as usize] as char);
+        }
+    }
+    if len > 0 {
+        out.push(alphabet[((acc << (bits - len)) & ((1 << bits) - 1)) as usize] as char);
+    }
+
+    out
+}
+
+/// Decode data encoded with `bits` bits per character, rejecting non-zero padding bits
+fn decode_bits(s: &str, bits: u32, alphabet: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * bits as usize / 8);
+    let mut acc = 0u32;
+    let mut len = 0;
+    for c in s.bytes() {
+        let value = alphabet.iter().position(|a| *a == c)? as u32;
+        acc = (acc << bits) | value;
+        len += bits;
+        if len >= 8 {
+            len -= 8;
+            out.push((acc >> len) as u8);
+        }
+        acc &= (1 << len) - 1;
+    }
+    if len >= bits || acc != 0 {
+        return None;
+    }
+
+    Some(out)
+}
+
+/// Encode `data` as padded base64
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut out = encode_bits(data, 6, BASE64_ALPHABET);
+    while out.len() % 4 != 0 {
+        out.push('=');
+    }
+
+    out
+}
+
+/// Decode base64, with or without padding
+pub(crate) fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let trimmed = s.trim_end_matches('=');
+    if s.len() - trimmed.len() > 2 || (trimmed.len() != s.len() && s.len() % 4 != 0) {
+        return None;
+    }
+
+    decode_bits(trimmed, 6, BASE64_ALPHABET)
+}
+
+/// Encode `data` as unpadded lowercase base32, as used for onion addresses and client
+/// authorization keys
+pub(crate) fn base32_encode(data: &[u8]) -> String {
+    encode_bits(data, 5, BASE32_ALPHABET)
+}
+
+/// Decode unpadded base32, ignoring the case
+pub(crate) fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    decode_bits(&s.to_ascii_lowercase(), 5, BASE32_ALPHABET)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode(&[0x00, 0xab, 0x10]), "00AB10");
+        assert_eq!(hex_decode("00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(hex_decode("00AB1"), None);
+        assert_eq!(hex_decode("0g"), None);
+        assert_eq!(hex_decode("+1"), None);
+    }
+
+    #[test]
+    fn test_base64() {
+        for (data, encoded) in &[
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"\xff\xfe\xfd", "//79"),
+        ] {
+            assert_eq!(base64_encode(data), *encoded);
+            assert_eq!(base64_decode(encoded).as_deref(), Some(*data));
+        }
+        assert_eq!(base64_decode("Zm9vYg").as_deref(), Some(&b"foob"[..]));
+        assert_eq!(base64_decode("Zm9vYg="), None);
+        assert_eq!(base64_decode("Zh=="), None);
+        assert_eq!(base64_decode("Z==="), None);
+        assert_eq!(base64_decode("Zm9v!"), None);
+    }
+
+    #[test]
+    fn test_base32() {
+        for (data, encoded) in &[
+            (&b""[..], ""),
+            (b"f", "my"),
+            (b"fo", "mzxq"),
+            (b"foo", "mzxw6"),
+            (b"foobar", "mzxw6ytboi"),
+        ] {
+            assert_eq!(base32_encode(data), *encoded);
+            assert_eq!(base32_decode(encoded).as_deref(), Some(*data));
+        }
+        assert_eq!(base32_decode("MZXW6").as_deref(), Some(&b"foo"[..]));
+        assert_eq!(base32_decode("mz"), None);
+        assert_eq!(base32_decode("m"), None);
+        assert_eq!(base32_decode("mzxw1"), None);
+    }
+}
@@ -0,0 +1,76 @@
+//! Error type for the safe wrappers around `tor_api.h`
+
+use std::error::Error as StdError;
+use std::ffi::NulError;
+use std::fmt;
+use std::io;
+
+use crate::control::ControlError;
+
+/// Errors returned by the safe wrappers around Tor's API
+#[derive(Debug)]
+pub enum Error {
+    /// Tor couldn't allocate a new configuration
+    Allocation,
+    /// A command line argument contains an interior NUL byte
+    Nul(NulError),
+    /// Tor rejected the command line
+    CommandLine,
+    /// The configuration is invalid
+    InvalidConfig(String),
+    /// Tor is already running in this process
+    AlreadyRunning,
+    /// The thread running Tor panicked
+    Panicked,
+    /// I/O error
+    Io(io::Error),
+    /// The operation requires the owning controller, which wasn't enabled or has been taken
+    NoController,
+    /// Error on the control connection
+    Control(ControlError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Allocation => write!(f, "Tor failed to allocate a new configuration"),
+            Error::Nul(e) => write!(f, "Invalid command line argument: {}", e),
+            Error::CommandLine => write!(f, "Tor rejected the command line"),
+            Error::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            Error::AlreadyRunning => write!(f, "Tor is already running in this process"),
+            Error::Panicked => write!(f, "The thread running Tor panicked"),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::NoController => write!(f, "The owning controller isn't available"),
+            Error::Control(e) => write!(f, "Control connection error: {}", e),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Nul(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Control(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for Error {
+    fn from(e: NulError) -> Self {
+        Error::Nul(e)
+    }
+}
+
+impl From<ControlError> for Error {
+    fn from(e: ControlError) -> Self {
+        Error::Control(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,133 @@
+//! Process-wide guard around [`tor_run_main`](crate::tor_run_main)
+//!
+//! Tor keeps global state, so only one instance can run in a process at any given time. Every safe
+//! entry point of this crate goes through this guard, which rejects a second concurrent run with
+//! [`Error::AlreadyRunning`]. Calling the raw `tor_run_main` directly bypasses it.
+//!
+//! Sequential runs are allowed, but `tor_api.h` warns that Tor may crash or behave strangely when
+//! started a second time in the same process (bug 23847): the issues found so far have been
+//! fixed, but more may remain. Applications that can afford it should restart the whole process
+//! instead.
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::error::Error;
+use crate::runner::TorExit;
+
+/// State of the embedded Tor, shared by the whole process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RunState {
+    /// Tor has never been started
+    NotStarted,
+    /// Tor is currently running
+    Running,
+    /// The last run of Tor finished with the given result
+    Finished(TorExit),
+}
+
+static STATE: Mutex<RunState> = Mutex::new(RunState::NotStarted);
+
+fn lock_state() -> MutexGuard<'static, RunState> {
+    // The state is always valid, even if a thread panicked while holding the lock
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the current state of the embedded Tor
+pub fn run_state() -> RunState {
+    *lock_state()
+}
+
+/// Exclusive right to call `tor_run_main`, released when dropped
+#[derive(Debug)]
+pub(crate) struct RunGuard {
+    previous: RunState,
+    exit: Option<TorExit>,
+}
+
+impl RunGuard {
+    /// Acquire the guard, failing if Tor is already running
+    pub(crate) fn acquire() -> Result<Self, Error> {
+        let mut state = lock_state();
+        if *state == RunState::Running {
+            return Err(Error::AlreadyRunning);
+        }
+        let previous = std::mem::replace(&mut *state, RunState::Running);
+
+        Ok(RunGuard {
+            previous,
+            exit: None,
+        })
+    }
+
+    /// Record the result of the run
+    pub(crate) fn finish(mut self, exit: TorExit) {
+        self.exit = Some(exit);
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        // A guard dropped without a result means that Tor was never started
+        *lock_state() = match self.exit {
+            Some(exit) => RunState::Finished(exit),
+            None => self.previous,
+        };
+    }
+}
+
+/// Serializes the tests that run Tor, since they would otherwise fail with `AlreadyRunning`
+#[cfg(test)]
+pub(crate) fn test_lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::{Port, TorConfigBuilder};
+    use crate::runner::TorRunner;
+
+    #[test]
+    fn test_concurrent_run() {
+        let _lock = test_lock();
+
+        let guard = RunGuard::acquire().unwrap();
+        assert_eq!(run_state(), RunState::Running);
+        match TorRunner::spawn(vec!["--version"]) {
+            Err(Error::AlreadyRunning) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+
+        guard.finish(TorExit::Success);
+        assert_eq!(run_state(), RunState::Finished(TorExit::Success));
+    }
+
+    #[test]
+    fn test_sequential_runs() {
+        let _lock = test_lock();
+
+        let dir = crate::test_utils::test_dir("guard-restart");
+        let args = TorConfigBuilder::new()
+            .data_directory(&dir)
+            .socks_port(Port::Disabled)
+            .disable_network(true)
+            .build()
+            .unwrap();
+
+        // Start the daemon, shut it down through the owning controller and start it again
+        for _ in 0..2 {
+            let mut handle = TorRunner::new(args.clone())
+                .owning_controller(true)
+                .start()
+                .unwrap();
+            assert_eq!(run_state(), RunState::Running);
+            let controller = handle.controller().unwrap();
+            assert!(!controller.bootstrap_status().unwrap().is_done());
+            assert_eq!(handle.shutdown().unwrap(), TorExit::Success);
+            assert_eq!(run_state(), RunState::Finished(TorExit::Success));
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
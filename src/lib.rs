@@ -8,7 +8,13 @@
 //! respective dependencies, and the `vendored-lzma` and `vendored-zstd` features can be used
 //! to compile and like those libraries statically.
 //!
-//! The interface simply re-exports Tor's functions defined in its tor_api.h header.
+//! The low-level interface simply re-exports Tor's functions defined in its tor_api.h header, while
+//! [`TorConfiguration`] and [`TorRunner`] wrap them in a safe API. The optional `bindgen` feature
+//! generates the bindings from the header at build time and makes the tests check them against the
+//! ones defined here, so that changes to the API surface don't go unnoticed when upgrading Tor.
+//!
+//! The optional `tokio` feature adds async versions of the [`socks`] connector and of the
+//! [`control`] client.
 //!
 //! # Example
 //!
@@ -21,8 +27,8 @@
 //!         CString::new("tor").unwrap(),
 //!         CString::new("--version").unwrap(),
 //!     ];
-//!     let argv: Vec<_> = argv.iter().map(|s| s.as_ptr()).collect();
-//!     tor_main_configuration_set_command_line(config, argv.len() as i32, argv.as_ptr());
+//!     let mut argv: Vec<_> = argv.iter().map(|s| s.as_ptr() as *mut _).collect();
+//!     tor_main_configuration_set_command_line(config, argv.len() as i32, argv.as_mut_ptr());
 //!
 //!     assert_eq!(tor_run_main(config), 0);
 //!
@@ -30,19 +36,98 @@
 //! }
 //! ```
 
-use std::os::raw::{c_char, c_int, c_void};
+extern crate curve25519_dalek;
+extern crate getrandom;
+extern crate hmac;
+extern crate sha1;
+extern crate sha2;
+extern crate sha3;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+use std::marker::{PhantomData, PhantomPinned};
+use std::os::raw::{c_char, c_int};
+
+pub mod control;
+pub mod onion;
+pub mod socks;
+
+mod builder;
+mod configuration;
+mod control_socket;
+mod encoding;
+mod error;
+mod guard;
+mod runner;
+mod version;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use builder::{
+    HiddenService, HiddenServiceTarget, Log, LogSeverity, LogTarget, Port, SocksPortFlag,
+    TorConfigBuilder, UnixSocketFlag,
+};
+pub use configuration::TorConfiguration;
+#[cfg(feature = "tokio")]
+pub use control_socket::AsyncOwningController;
+pub use control_socket::{setup_control_socket, ControlStream, OwningController};
+pub use error::Error;
+pub use guard::{run_state, RunState};
+pub use runner::{TorExit, TorHandle, TorRunner, DEFAULT_THREAD_NAME};
+pub use version::{
+    crate_tor_version, provider_version, ParseVersionError, ProviderVersion, TorVersion,
+};
+
+/// Opaque configuration object created by [`tor_main_configuration_new`]
+///
+/// It can only be used behind a pointer, and it's neither `Send` nor `Sync`: see
+/// [`TorConfiguration`] for a safe owner of the object.
+#[repr(C)]
+pub struct tor_main_configuration_t {
+    _data: [u8; 0],
+    _marker: PhantomData<(*mut u8, PhantomPinned)>,
+}
+
+/// Socket type returned by [`tor_main_configuration_setup_control_socket`]: a file descriptor on
+/// UNIX-like systems
+#[cfg(not(windows))]
+pub type tor_control_socket_t = c_int;
+/// Socket type returned by [`tor_main_configuration_setup_control_socket`]: a `SOCKET` handle on
+/// Windows
+#[cfg(windows)]
+pub type tor_control_socket_t = std::os::windows::raw::SOCKET;
 
-type tor_main_configuration_t = c_void;
+/// Value returned by [`tor_main_configuration_setup_control_socket`] on failure
+#[cfg(not(windows))]
+pub const INVALID_TOR_CONTROL_SOCKET: tor_control_socket_t = -1;
+/// Value returned by [`tor_main_configuration_setup_control_socket`] on failure (`INVALID_SOCKET`)
+#[cfg(windows)]
+pub const INVALID_TOR_CONTROL_SOCKET: tor_control_socket_t = !0;
 
 extern "C" {
     pub fn tor_main_configuration_new() -> *mut tor_main_configuration_t;
     pub fn tor_main_configuration_set_command_line(
         config: *mut tor_main_configuration_t,
         argc: c_int,
-        argv: *const *const c_char,
+        argv: *mut *mut c_char,
     ) -> c_int;
+    pub fn tor_main_configuration_setup_control_socket(
+        config: *mut tor_main_configuration_t,
+    ) -> tor_control_socket_t;
     pub fn tor_main_configuration_free(config: *mut tor_main_configuration_t);
+    pub fn tor_api_get_provider_version() -> *const c_char;
     pub fn tor_run_main(configuration: *const tor_main_configuration_t) -> c_int;
+    /// Deprecated entry point kept for compatibility, new code should use [`tor_run_main`]
+    pub fn tor_main(argc: c_int, argv: *mut *mut c_char) -> c_int;
+}
+
+#[cfg(all(test, feature = "bindgen"))]
+#[allow(non_upper_case_globals, dead_code, clippy::all)]
+mod bindgen_generated {
+    use crate::tor_main_configuration_t;
+
+    include!(concat!(env!("OUT_DIR"), "/tor_api.rs"));
 }
 
 #[cfg(test)]
@@ -56,13 +141,73 @@ mod test {
             CString::new("tor").unwrap(),
             CString::new("--version").unwrap(),
         ];
-        let args_ptr = [args[0].as_ptr(), args[1].as_ptr()];
+        let mut args_ptr = [args[0].as_ptr() as *mut _, args[1].as_ptr() as *mut _];
 
+        // Calling `tor_run_main` directly bypasses the guard, serialize with the other tests
+        let _lock = guard::test_lock();
         unsafe {
             let config = tor_main_configuration_new();
-            tor_main_configuration_set_command_line(config, 2, args_ptr.as_ptr());
+            tor_main_configuration_set_command_line(config, 2, args_ptr.as_mut_ptr());
             tor_run_main(config);
             tor_main_configuration_free(config);
         }
     }
+
+    #[test]
+    fn test_setup_control_socket() {
+        use super::*;
+
+        unsafe {
+            let config = tor_main_configuration_new();
+            let stream = setup_control_socket(config);
+            assert!(stream.is_ok());
+            // Only one control socket per configuration
+            assert!(setup_control_socket(config).is_err());
+
+            drop(stream);
+            tor_main_configuration_free(config);
+        }
+    }
+
+    #[cfg(feature = "bindgen")]
+    #[test]
+    fn test_bindgen_bindings() {
+        use super::bindgen_generated as generated;
+        use super::*;
+        use std::mem::{align_of, size_of};
+
+        // Putting both functions in the same array only compiles if their signatures match
+        macro_rules! check_functions {
+            ($($name:ident),*) => {{
+                $( let _ = [generated::$name, super::$name]; )*
+            }};
+        }
+
+        // Every function in the header has a matching hand-written binding...
+        include!(concat!(env!("OUT_DIR"), "/tor_api_functions.rs"));
+        // ...and every hand-written binding is still in the header
+        check_functions!(
+            tor_main_configuration_new,
+            tor_main_configuration_set_command_line,
+            tor_main_configuration_setup_control_socket,
+            tor_main_configuration_free,
+            tor_api_get_provider_version,
+            tor_run_main,
+            tor_main
+        );
+
+        assert_eq!(
+            size_of::<generated::tor_control_socket_t>(),
+            size_of::<tor_control_socket_t>()
+        );
+        assert_eq!(
+            align_of::<generated::tor_control_socket_t>(),
+            align_of::<tor_control_socket_t>()
+        );
+        #[cfg(not(windows))]
+        assert_eq!(
+            generated::INVALID_TOR_CONTROL_SOCKET as tor_control_socket_t,
+            INVALID_TOR_CONTROL_SOCKET
+        );
+    }
 }
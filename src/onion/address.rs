@@ -0,0 +1,185 @@
+//! v3 onion addresses, as defined in section 6 of `rend-spec-v3.txt`
+//!
+//! ```text
+//! onion_address = base32(PUBKEY | CHECKSUM | VERSION) + ".onion"
+//! CHECKSUM = SHA3-256(".onion checksum" | PUBKEY | VERSION)[:2]
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use sha3::{Digest, Sha3_256};
+
+use super::OnionError;
+use crate::encoding::{base32_decode, base32_encode};
+
+/// Version byte of v3 onion addresses
+const VERSION: u8 = 3;
+/// Length of a v3 address, without `.onion`
+const ADDRESS_LEN: usize = 56;
+/// Length of a legacy v2 address, without `.onion`
+const V2_ADDRESS_LEN: usize = 16;
+
+/// Validated v3 onion address
+///
+/// It's parsed from the address with or without the `.onion` suffix, and displayed with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OnionAddress {
+    public_key: [u8; 32],
+}
+
+impl OnionAddress {
+    /// Derive the address of the service identified by an ed25519 public key
+    pub fn from_public_key(public_key: [u8; 32]) -> Result<Self, OnionError> {
+        // Like Tor, reject keys that aren't valid points or have a torsion component, since
+        // they would give the same service multiple addresses
+        match CompressedEdwardsY(public_key).decompress() {
+            Some(point) if point.is_torsion_free() => Ok(OnionAddress { public_key }),
+            _ => Err(OnionError::InvalidAddress(
+                "The public key is not a valid ed25519 point".into(),
+            )),
+        }
+    }
+
+    /// Returns the ed25519 public key of the service
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+
+    /// Returns the address without the `.onion` suffix, also known as service id
+    pub fn service_id(&self) -> String {
+        let mut data = self.public_key.to_vec();
+        data.extend_from_slice(&checksum(&self.public_key));
+        data.push(VERSION);
+
+        base32_encode(&data)
+    }
+}
+
+fn checksum(public_key: &[u8; 32]) -> [u8; 2] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b".onion checksum");
+    hasher.update(public_key);
+    hasher.update([VERSION]);
+    let digest = hasher.finalize();
+
+    [digest[0], digest[1]]
+}
+
+impl fmt::Display for OnionAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.onion", self.service_id())
+    }
+}
+
+impl FromStr for OnionAddress {
+    type Err = OnionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let service_id = s.strip_suffix(".onion").unwrap_or(s);
+        match service_id.len() {
+            ADDRESS_LEN => {}
+            V2_ADDRESS_LEN if base32_decode(service_id).is_some() => {
+                return Err(OnionError::V2Address)
+            }
+            len => {
+                return Err(OnionError::InvalidAddress(format!(
+                    "Expected {} characters, found {}",
+                    ADDRESS_LEN, len
+                )))
+            }
+        }
+
+        let data = base32_decode(service_id)
+            .ok_or_else(|| OnionError::InvalidAddress("Invalid base32".into()))?;
+        if data[34] != VERSION {
+            return Err(OnionError::InvalidAddress(format!(
+                "Unsupported version {}",
+                data[34]
+            )));
+        }
+        let mut public_key = [0; 32];
+        public_key.copy_from_slice(&data[..32]);
+        if data[32..34] != checksum(&public_key) {
+            return Err(OnionError::InvalidAddress("Invalid checksum".into()));
+        }
+
+        OnionAddress::from_public_key(public_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoding::hex_decode;
+
+    const ADDRESS: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad";
+    const PUBLIC_KEY: &str = "1d04a1d04a338c6e6ae970bfabee49049d6702250984ca950c01673f4ec034ad";
+
+    #[test]
+    fn test_parse() {
+        let address = format!("{}.onion", ADDRESS)
+            .parse::<OnionAddress>()
+            .unwrap();
+        assert_eq!(
+            &address.public_key()[..],
+            &hex_decode(PUBLIC_KEY).unwrap()[..]
+        );
+        assert_eq!(address.service_id(), ADDRESS);
+        assert_eq!(address.to_string(), format!("{}.onion", ADDRESS));
+        assert_eq!(ADDRESS.parse::<OnionAddress>().unwrap(), address);
+        assert_eq!(
+            ADDRESS.to_uppercase().parse::<OnionAddress>().unwrap(),
+            address
+        );
+    }
+
+    #[test]
+    fn test_derive() {
+        let mut public_key = [0; 32];
+        public_key.copy_from_slice(&hex_decode(PUBLIC_KEY).unwrap());
+        let address = OnionAddress::from_public_key(public_key).unwrap();
+        assert_eq!(address.service_id(), ADDRESS);
+
+        // Not a point on the curve
+        let mut invalid = [0xff; 32];
+        invalid[31] = 0x7f;
+        assert!(OnionAddress::from_public_key(invalid).is_err());
+        // (0, -1) has order 2
+        let mut small_order = [0xff; 32];
+        small_order[0] = 0xec;
+        small_order[31] = 0x7f;
+        assert!(OnionAddress::from_public_key(small_order).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(
+            "expyuzz4wqqyqhjn.onion".parse::<OnionAddress>(),
+            Err(OnionError::V2Address)
+        );
+
+        // Wrong checksum
+        let mut tampered = ADDRESS.to_string();
+        tampered.replace_range(0..1, "e");
+        // Wrong version
+        let mut data = base32_decode(ADDRESS).unwrap();
+        data[34] = 4;
+        let version = base32_encode(&data);
+
+        for invalid in &[
+            &ADDRESS[1..],
+            &format!("{}.onion.onion", ADDRESS),
+            &ADDRESS.replace('d', "1"),
+            &tampered,
+            &version,
+            "expyuzz4wqqyqhj!",
+        ] {
+            match invalid.parse::<OnionAddress>() {
+                Err(OnionError::InvalidAddress(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", invalid, r),
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Files used for the client authorization of v3 onion services
+//!
+//! Clients store one `<address>.auth_private` file per service in their `ClientOnionAuthDir`,
+//! while services list their authorized clients as `.auth` files in the `authorized_clients`
+//! directory inside their `HiddenServiceDir`.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{OnionAddress, OnionError, X25519PublicKey, X25519SecretKey};
+
+const AUTH_TYPE: &str = "descriptor";
+const KEY_TYPE: &str = "x25519";
+
+/// Content of a client's `.auth_private` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientAuthFile {
+    /// Onion address of the service, without `.onion`
+    pub service_id: String,
+    /// Secret key of the client
+    pub key: X25519SecretKey,
+}
+
+impl ClientAuthFile {
+    /// Credentials for the service `service_id`, which may end with `.onion`
+    ///
+    /// Fails if `service_id` isn't a valid v3 onion address.
+    pub fn new(service_id: &str, key: X25519SecretKey) -> Result<Self, OnionError> {
+        let address = service_id.parse::<OnionAddress>()?;
+        Ok(ClientAuthFile {
+            service_id: address.service_id(),
+            key,
+        })
+    }
+
+    /// Name of the file in `ClientOnionAuthDir`, Tor only looks at the extension
+    pub fn file_name(&self) -> String {
+        format!("{}.auth_private", self.service_id)
+    }
+
+    /// Read a `.auth_private` file
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse().map_err(invalid_data)
+    }
+
+    /// Write the credentials to `path`, readable only by the current user
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(path)?;
+        // The mode only applies to new files, an existing one could be readable by others
+        #[cfg(unix)]
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        writeln!(file, "{}", self)
+    }
+}
+
+impl fmt::Display for ClientAuthFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.service_id,
+            AUTH_TYPE,
+            KEY_TYPE,
+            self.key.to_base32()
+        )
+    }
+}
+
+impl FromStr for ClientAuthFile {
+    type Err = OnionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.trim().split(':').collect::<Vec<_>>();
+        match fields.as_slice() {
+            [service_id, AUTH_TYPE, KEY_TYPE, key] if !service_id.ends_with(".onion") => {
+                ClientAuthFile::new(service_id, X25519SecretKey::from_base32(key)?)
+            }
+            _ => Err(OnionError::InvalidKey(
+                "Expected `<address>:descriptor:x25519:<key>`".into(),
+            )),
+        }
+    }
+}
+
+/// Content of a service's `.auth` file, authorizing one client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AuthorizedClient {
+    /// Public key of the client
+    pub key: X25519PublicKey,
+}
+
+impl AuthorizedClient {
+    /// Authorize the client with the public key `key`
+    pub fn new(key: X25519PublicKey) -> Self {
+        AuthorizedClient { key }
+    }
+
+    /// Read a `.auth` file
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read_to_string(path)?.parse().map_err(invalid_data)
+    }
+
+    /// Write the client to `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, format!("{}\n", self))
+    }
+}
+
+impl fmt::Display for AuthorizedClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", AUTH_TYPE, KEY_TYPE, self.key.to_base32())
+    }
+}
+
+impl FromStr for AuthorizedClient {
+    type Err = OnionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.trim().split(':').collect::<Vec<_>>();
+        match fields.as_slice() {
+            [AUTH_TYPE, KEY_TYPE, key] => {
+                Ok(AuthorizedClient::new(X25519PublicKey::from_base32(key)?))
+            }
+            _ => Err(OnionError::InvalidKey(
+                "Expected `descriptor:x25519:<key>`".into(),
+            )),
+        }
+    }
+}
+
+fn invalid_data(e: OnionError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SERVICE_ID: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+
+    #[test]
+    fn test_client_auth_file() {
+        let key = X25519SecretKey::from_bytes([0x11; 32]);
+        let auth = ClientAuthFile::new(&format!("{}.onion", SERVICE_ID), key.clone()).unwrap();
+        let line = format!(
+            "{}:descriptor:x25519:{}",
+            SERVICE_ID, "ceirceirceirceirceirceirceirceirceirceirceirceirceiq"
+        );
+        assert_eq!(auth.to_string(), line);
+        assert_eq!(
+            format!("{}\n", line).parse::<ClientAuthFile>().unwrap(),
+            auth
+        );
+        assert_eq!(auth.file_name(), format!("{}.auth_private", SERVICE_ID));
+        assert_eq!(
+            ClientAuthFile::new(&SERVICE_ID.to_uppercase(), key.clone()).unwrap(),
+            auth
+        );
+
+        for invalid in &[
+            "",
+            ".onion",
+            "foo.bar",
+            &format!("{}.onion.onion", SERVICE_ID),
+            &SERVICE_ID.replace('v', "a"),
+        ] {
+            match ClientAuthFile::new(invalid, key.clone()) {
+                Err(OnionError::InvalidAddress(_)) => {}
+                r => panic!("Unexpected result for `{}`: {:?}", invalid, r),
+            }
+        }
+
+        for invalid in &[
+            "descriptor:x25519:AAAA",
+            &format!("{}:descriptor:x448:{}", SERVICE_ID, key.to_base32()),
+            &format!("foo.bar:descriptor:x25519:{}", key.to_base32()),
+            &format!("{}:descriptor:x25519:AAAA", SERVICE_ID),
+            &format!("{}.onion:descriptor:x25519:{}", SERVICE_ID, key.to_base32()),
+        ] {
+            assert!(invalid.parse::<ClientAuthFile>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_authorized_client() {
+        let key = X25519SecretKey::from_bytes([0x11; 32]).public_key();
+        let client = AuthorizedClient::new(key);
+        assert_eq!(
+            client.to_string(),
+            format!("descriptor:x25519:{}", key.to_base32())
+        );
+        assert_eq!(
+            client.to_string().parse::<AuthorizedClient>().unwrap(),
+            client
+        );
+        assert!("descriptor:x25519:".parse::<AuthorizedClient>().is_err());
+        assert!(format!("{}:descriptor:x25519:{}", SERVICE_ID, key)
+            .parse::<AuthorizedClient>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = crate::test_utils::test_dir("client-auth");
+        let secret = X25519SecretKey::generate();
+
+        let auth = ClientAuthFile::new(SERVICE_ID, secret.clone()).unwrap();
+        let path = dir.join(auth.file_name());
+        // Overwriting a file readable by others must restrict its permissions too
+        fs::write(&path, "").unwrap();
+        #[cfg(unix)]
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        auth.write(&path).unwrap();
+        assert_eq!(ClientAuthFile::read(&path).unwrap(), auth);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let client = AuthorizedClient::new(secret.public_key());
+        let path = dir.join("alice.auth");
+        client.write(&path).unwrap();
+        assert_eq!(AuthorizedClient::read(&path).unwrap(), client);
+
+        fs::write(&path, "garbage").unwrap();
+        assert_eq!(
+            AuthorizedClient::read(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
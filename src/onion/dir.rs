@@ -0,0 +1,256 @@
+//! Key material stored by Tor in a `HiddenServiceDir`
+//!
+//! The key files start with a 32-byte header, padded with NUL bytes, followed by the raw key:
+//!
+//! ```text
+//! hs_ed25519_secret_key: "== ed25519v1-secret: type0 ==" | expanded secret key (64 bytes)
+//! hs_ed25519_public_key: "== ed25519v1-public: type0 ==" | public key (32 bytes)
+//! hostname:              <address>.onion
+//! ```
+
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use super::{OnionAddress, OnionError, OnionSecretKey};
+
+const HEADER_LEN: usize = 32;
+const SECRET_KEY_HEADER: &str = "== ed25519v1-secret: type0 ==";
+const PUBLIC_KEY_HEADER: &str = "== ed25519v1-public: type0 ==";
+
+const HOSTNAME_FILE: &str = "hostname";
+const SECRET_KEY_FILE: &str = "hs_ed25519_secret_key";
+const PUBLIC_KEY_FILE: &str = "hs_ed25519_public_key";
+
+/// `HiddenServiceDir` of an onion service configured in the torrc
+///
+/// # Example
+///
+/// ```no_run
+/// # use tor_sys::control::AddOnion;
+/// # use tor_sys::onion::HiddenServiceDir;
+/// // Turn a service configured in the torrc into an ephemeral one
+/// let key = HiddenServiceDir::new("/var/lib/tor/my_service").read_secret_key()?;
+/// let request = AddOnion::with_key(key);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HiddenServiceDir {
+    path: PathBuf,
+}
+
+impl HiddenServiceDir {
+    /// Use the directory at `path`, which doesn't need to exist yet
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        HiddenServiceDir { path: path.into() }
+    }
+
+    /// Returns the path of the directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the address from the `hostname` file
+    pub fn read_hostname(&self) -> io::Result<OnionAddress> {
+        fs::read_to_string(self.path.join(HOSTNAME_FILE))?
+            .trim()
+            .parse()
+            .map_err(invalid_data)
+    }
+
+    /// Read the `hs_ed25519_secret_key` file
+    pub fn read_secret_key(&self) -> io::Result<OnionSecretKey> {
+        let key = read_key_file(&self.path.join(SECRET_KEY_FILE), SECRET_KEY_HEADER, 64)?;
+
+        let mut bytes = [0; 64];
+        bytes.copy_from_slice(&key);
+        Ok(OnionSecretKey::from_bytes(bytes))
+    }
+
+    /// Read the `hs_ed25519_public_key` file, returning the corresponding address
+    pub fn read_public_key(&self) -> io::Result<OnionAddress> {
+        let key = read_key_file(&self.path.join(PUBLIC_KEY_FILE), PUBLIC_KEY_HEADER, 32)?;
+
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&key);
+        OnionAddress::from_public_key(bytes).map_err(invalid_data)
+    }
+
+    /// Write the key files and the `hostname` for `key`, returning the address of the service
+    ///
+    /// The directory is created if needed. Like Tor, it's only accessible by the current user,
+    /// and the secret key is only readable by it: the permissions of an existing directory and
+    /// key file are restricted too, since Tor refuses to use a directory readable by others.
+    pub fn write_keys(&self, key: &OnionSecretKey) -> io::Result<OnionAddress> {
+        let mut builder = DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&self.path)?;
+        #[cfg(unix)]
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(0o700))?;
+
+        let address = key.address();
+        write_key_file(
+            &self.path.join(SECRET_KEY_FILE),
+            SECRET_KEY_HEADER,
+            key.as_bytes(),
+        )?;
+        write_key_file(
+            &self.path.join(PUBLIC_KEY_FILE),
+            PUBLIC_KEY_HEADER,
+            address.public_key(),
+        )?;
+        fs::write(self.path.join(HOSTNAME_FILE), format!("{}\n", address))?;
+
+        Ok(address)
+    }
+}
+
+fn read_key_file(path: &Path, header: &str, key_len: usize) -> io::Result<Vec<u8>> {
+    let mut data = fs::read(path)?;
+    if data.len() != HEADER_LEN + key_len {
+        return Err(invalid_data(OnionError::InvalidKey(format!(
+            "Expected {} bytes in {}, found {}",
+            HEADER_LEN + key_len,
+            path.display(),
+            data.len()
+        ))));
+    }
+    if data[..HEADER_LEN] != header_bytes(header) {
+        return Err(invalid_data(OnionError::InvalidKey(format!(
+            "Expected the header `{}` in {}",
+            header,
+            path.display()
+        ))));
+    }
+
+    Ok(data.split_off(HEADER_LEN))
+}
+
+fn write_key_file(path: &Path, header: &str, key: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, an existing one could be readable by others
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(&header_bytes(header))?;
+    file.write_all(key)
+}
+
+fn header_bytes(header: &str) -> [u8; HEADER_LEN] {
+    let mut bytes = [0; HEADER_LEN];
+    bytes[..header.len()].copy_from_slice(header.as_bytes());
+    bytes
+}
+
+fn invalid_data(e: OnionError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let path = crate::test_utils::test_dir("hs-dir").join("service");
+        let dir = HiddenServiceDir::new(&path);
+        let key = OnionSecretKey::generate();
+
+        let address = dir.write_keys(&key).unwrap();
+        assert_eq!(address, key.address());
+        assert_eq!(dir.read_secret_key().unwrap(), key);
+        assert_eq!(dir.read_public_key().unwrap(), address);
+        assert_eq!(dir.read_hostname().unwrap(), address);
+        assert_eq!(
+            fs::read_to_string(path.join(HOSTNAME_FILE)).unwrap(),
+            format!("{}\n", address)
+        );
+
+        // Same layout as the files written by Tor
+        let secret = fs::read(path.join(SECRET_KEY_FILE)).unwrap();
+        assert_eq!(secret.len(), 96);
+        assert_eq!(&secret[..32], b"== ed25519v1-secret: type0 ==\0\0\0");
+        assert_eq!(&secret[32..], &key.as_bytes()[..]);
+        let public = fs::read(path.join(PUBLIC_KEY_FILE)).unwrap();
+        assert_eq!(&public[..32], b"== ed25519v1-public: type0 ==\0\0\0");
+
+        // Round trip through the ADD_ONION blob
+        let blob = dir.read_secret_key().unwrap().to_blob();
+        assert_eq!(OnionSecretKey::from_blob(&blob).unwrap().address(), address);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&path), 0o700);
+            assert_eq!(mode(&path.join(SECRET_KEY_FILE)), 0o600);
+        }
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_existing_permissions() {
+        let path = crate::test_utils::test_dir("hs-dir-existing").join("service");
+        fs::create_dir(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(path.join(SECRET_KEY_FILE), "").unwrap();
+        fs::set_permissions(
+            path.join(SECRET_KEY_FILE),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        HiddenServiceDir::new(&path)
+            .write_keys(&OnionSecretKey::generate())
+            .unwrap();
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o700);
+        assert_eq!(mode(&path.join(SECRET_KEY_FILE)), 0o600);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let path = crate::test_utils::test_dir("hs-dir-invalid");
+        let dir = HiddenServiceDir::new(&path);
+        let key = OnionSecretKey::generate();
+        dir.write_keys(&key).unwrap();
+
+        // Public key header in the secret key file
+        let mut data = fs::read(path.join(SECRET_KEY_FILE)).unwrap();
+        data[..32].copy_from_slice(&header_bytes(PUBLIC_KEY_HEADER));
+        fs::write(path.join(SECRET_KEY_FILE), &data).unwrap();
+        // Truncated public key
+        let data = fs::read(path.join(PUBLIC_KEY_FILE)).unwrap();
+        fs::write(path.join(PUBLIC_KEY_FILE), &data[..48]).unwrap();
+        fs::write(path.join(HOSTNAME_FILE), "expyuzz4wqqyqhjn.onion\n").unwrap();
+
+        for err in &[
+            dir.read_secret_key().unwrap_err(),
+            dir.read_public_key().unwrap_err(),
+            dir.read_hostname().unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        assert_eq!(
+            HiddenServiceDir::new(path.join("missing"))
+                .read_secret_key()
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,356 @@
+//! Keys and identifiers of v3 onion services
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use curve25519_dalek::edwards::EdwardsPoint;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use getrandom::getrandom;
+use sha2::{Digest, Sha512};
+
+use crate::encoding::{base32_decode, base32_encode, base64_decode, base64_encode};
+
+mod address;
+mod client_auth;
+mod dir;
+
+pub use self::address::OnionAddress;
+pub use self::client_auth::{AuthorizedClient, ClientAuthFile};
+pub use self::dir::HiddenServiceDir;
+
+/// Prefix of the key blobs used by `ADD_ONION` for v3 onion services
+pub const ED25519_V3_PREFIX: &str = "ED25519-V3:";
+
+/// Errors related to onion service keys and addresses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnionError {
+    /// The key is malformed
+    InvalidKey(String),
+    /// The onion address is malformed
+    InvalidAddress(String),
+    /// The address is a legacy v2 address, which isn't supported by Tor anymore
+    V2Address,
+}
+
+impl fmt::Display for OnionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OnionError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            OnionError::InvalidAddress(msg) => write!(f, "Invalid onion address: {}", msg),
+            OnionError::V2Address => write!(f, "v2 onion addresses are not supported anymore"),
+        }
+    }
+}
+
+impl StdError for OnionError {}
+
+/// Expanded ed25519 secret key of a v3 onion service, in the format used by Tor.
+///
+/// The first 32 bytes are the secret scalar and the last 32 the nonce prefix used when signing,
+/// which is how Tor stores the key both in `hs_ed25519_secret_key` and in the `ED25519-V3:` blobs
+/// exchanged with `ADD_ONION`. The `Debug` implementation doesn't print the key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct OnionSecretKey([u8; 64]);
+
+impl OnionSecretKey {
+    /// Generate a new random key
+    pub fn generate() -> Self {
+        let mut seed = [0; 32];
+        getrandom(&mut seed).expect("The system RNG is unavailable");
+
+        OnionSecretKey::from_seed(&seed)
+    }
+
+    /// Expand the 32-byte seed of a standard ed25519 secret key
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        let mut bytes = [0; 64];
+        bytes.copy_from_slice(&Sha512::digest(seed));
+        bytes[0] &= 248;
+        bytes[31] &= 63;
+        bytes[31] |= 64;
+
+        OnionSecretKey(bytes)
+    }
+
+    /// Wrap the 64 bytes of an expanded secret key
+    pub fn from_bytes(bytes: [u8; 64]) -> Self {
+        OnionSecretKey(bytes)
+    }
+
+    /// Returns the 64 bytes of the expanded secret key
+    pub fn as_bytes(&self) -> &[u8; 64] {
+        &self.0
+    }
+
+    /// Parse an `ED25519-V3:<base64>` key blob
+    pub fn from_blob(blob: &str) -> Result<Self, OnionError> {
+        let encoded = blob.strip_prefix(ED25519_V3_PREFIX).ok_or_else(|| {
+            OnionError::InvalidKey(format!(
+                "Key blob doesn't start with `{}`",
+                ED25519_V3_PREFIX
+            ))
+        })?;
+        let bytes = base64_decode(encoded)
+            .ok_or_else(|| OnionError::InvalidKey("Key blob is not valid base64".into()))?;
+        if bytes.len() != 64 {
+            return Err(OnionError::InvalidKey(format!(
+                "Expected 64 bytes, found {}",
+                bytes.len()
+            )));
+        }
+
+        let mut key = [0; 64];
+        key.copy_from_slice(&bytes);
+        Ok(OnionSecretKey(key))
+    }
+
+    /// Encode the key as an `ED25519-V3:<base64>` blob
+    pub fn to_blob(&self) -> String {
+        format!("{}{}", ED25519_V3_PREFIX, base64_encode(&self.0))
+    }
+
+    /// Compute the ed25519 public key
+    pub fn public_key(&self) -> [u8; 32] {
+        let mut scalar = [0; 32];
+        scalar.copy_from_slice(&self.0[..32]);
+
+        EdwardsPoint::mul_base(&Scalar::from_bytes_mod_order(scalar))
+            .compress()
+            .to_bytes()
+    }
+
+    /// Compute the onion address of the service
+    pub fn address(&self) -> OnionAddress {
+        OnionAddress::from_public_key(self.public_key())
+            .expect("Public keys derived from a secret key are always valid")
+    }
+}
+
+impl fmt::Debug for OnionSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("OnionSecretKey(..)")
+    }
+}
+
+impl FromStr for OnionSecretKey {
+    type Err = OnionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OnionSecretKey::from_blob(s)
+    }
+}
+
+/// Public x25519 key of a client authorized to access a v3 onion service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct X25519PublicKey([u8; 32]);
+
+impl X25519PublicKey {
+    /// Wrap the 32 bytes of a public key
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        X25519PublicKey(bytes)
+    }
+
+    /// Returns the 32 bytes of the public key
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Parse the base32 encoding used by Tor, without padding
+    pub fn from_base32(s: &str) -> Result<Self, OnionError> {
+        decode_x25519(s).map(X25519PublicKey)
+    }
+
+    /// Encode the key in base32, without padding
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+}
+
+impl fmt::Display for X25519PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_base32())
+    }
+}
+
+impl FromStr for X25519PublicKey {
+    type Err = OnionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        X25519PublicKey::from_base32(s)
+    }
+}
+
+/// Secret x25519 key used by a client to access a v3 onion service that requires authorization.
+///
+/// The `Debug` implementation doesn't print the key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct X25519SecretKey([u8; 32]);
+
+impl X25519SecretKey {
+    /// Generate a new random key
+    pub fn generate() -> Self {
+        let mut bytes = [0; 32];
+        getrandom(&mut bytes).expect("The system RNG is unavailable");
+        // Clamp the key like Tor does, even if it's clamped again when used
+        bytes[0] &= 248;
+        bytes[31] &= 127;
+        bytes[31] |= 64;
+
+        X25519SecretKey(bytes)
+    }
+
+    /// Wrap the 32 bytes of a secret key
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        X25519SecretKey(bytes)
+    }
+
+    /// Returns the 32 bytes of the secret key
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Compute the public key to give to the onion service
+    pub fn public_key(&self) -> X25519PublicKey {
+        X25519PublicKey(MontgomeryPoint::mul_base_clamped(self.0).to_bytes())
+    }
+
+    /// Parse the base32 encoding used in `.auth_private` files
+    pub fn from_base32(s: &str) -> Result<Self, OnionError> {
+        decode_x25519(s).map(X25519SecretKey)
+    }
+
+    /// Encode the key in base32, as in `.auth_private` files
+    pub fn to_base32(&self) -> String {
+        base32_encode(&self.0)
+    }
+
+    /// Parse the base64 encoding used by `ONION_CLIENT_AUTH_ADD`
+    pub fn from_base64(s: &str) -> Result<Self, OnionError> {
+        match base64_decode(s) {
+            Some(bytes) if bytes.len() == 32 => {
+                let mut key = [0; 32];
+                key.copy_from_slice(&bytes);
+                Ok(X25519SecretKey(key))
+            }
+            _ => Err(OnionError::InvalidKey(
+                "Not a base64 x25519 key".to_string(),
+            )),
+        }
+    }
+
+    /// Encode the key in base64, as used by `ONION_CLIENT_AUTH_ADD`
+    pub fn to_base64(&self) -> String {
+        base64_encode(&self.0)
+    }
+}
+
+impl fmt::Debug for X25519SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("X25519SecretKey(..)")
+    }
+}
+
+fn decode_x25519(s: &str) -> Result<[u8; 32], OnionError> {
+    match base32_decode(s) {
+        Some(bytes) if bytes.len() == 32 => {
+            let mut key = [0; 32];
+            key.copy_from_slice(&bytes);
+            Ok(key)
+        }
+        _ => Err(OnionError::InvalidKey(format!(
+            "`{}` is not a base32 x25519 key",
+            s
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::encoding::{hex_decode, hex_encode};
+
+    #[test]
+    fn test_secret_key_blob() {
+        let mut bytes = [0; 64];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let key = OnionSecretKey::from_bytes(bytes);
+        let blob = key.to_blob();
+        assert!(blob.starts_with("ED25519-V3:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8g"));
+        assert!(blob.ends_with("=="));
+        assert_eq!(blob.parse::<OnionSecretKey>().unwrap(), key);
+        // Tor also accepts blobs without padding
+        assert_eq!(
+            OnionSecretKey::from_blob(blob.trim_end_matches('=')).unwrap(),
+            key
+        );
+        assert_eq!(format!("{:?}", key), "OnionSecretKey(..)");
+
+        for invalid in &["RSA1024:AAAA", "ED25519-V3:AAAA", "ED25519-V3:!!!!"] {
+            match OnionSecretKey::from_blob(invalid) {
+                Err(OnionError::InvalidKey(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", invalid, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_secret_key_derivation() {
+        // Test vector 1 from RFC 8032, section 7.1
+        let seed =
+            hex_decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&seed);
+        let key = OnionSecretKey::from_seed(&bytes);
+        assert_eq!(
+            hex_encode(&key.public_key()),
+            "D75A980182B10AB7D54BFED3C964073A0EE172F3DAA62325AF021A68F707511A"
+        );
+        assert_eq!(
+            key.address().service_id(),
+            "25njqamcweflpvkl73j4szahhihoc4xt3ktcgjnpaingr5yhkenl5sid"
+        );
+
+        let generated = OnionSecretKey::generate();
+        assert_ne!(generated, OnionSecretKey::generate());
+        assert_eq!(generated.address().public_key(), &generated.public_key());
+    }
+
+    #[test]
+    fn test_x25519_public_key() {
+        let key = X25519PublicKey::from_bytes([0xff; 32]);
+        let encoded = key.to_base32();
+        assert_eq!(encoded.len(), 52);
+        assert_eq!(encoded.parse::<X25519PublicKey>().unwrap(), key);
+        assert_eq!(
+            X25519PublicKey::from_base32(&encoded.to_uppercase()).unwrap(),
+            key
+        );
+        assert!(X25519PublicKey::from_base32(&encoded[1..]).is_err());
+    }
+
+    #[test]
+    fn test_x25519_secret_key() {
+        // Test vector from RFC 7748, section 6.1
+        let secret =
+            X25519SecretKey::from_base64("dwdtCnMYpX08FsFyUbJmRd9ML4frwJkqsXf7pR25LCo=").unwrap();
+        assert_eq!(
+            hex_encode(secret.public_key().as_bytes()),
+            "8520F0098930A754748B7DDCB43EF75A0DBF3A0D26381AF4EBA4A98EAA9B4E6A"
+        );
+        assert_eq!(
+            X25519SecretKey::from_base32(&secret.to_base32()).unwrap(),
+            secret
+        );
+        assert_eq!(format!("{:?}", secret), "X25519SecretKey(..)");
+        assert!(X25519SecretKey::from_base64("AAAA").is_err());
+
+        let generated = X25519SecretKey::generate();
+        assert_ne!(generated, X25519SecretKey::generate());
+        assert_eq!(generated.as_bytes()[0] & 7, 0);
+        assert_eq!(generated.as_bytes()[31] & 0xc0, 0x40);
+    }
+}
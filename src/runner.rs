@@ -0,0 +1,330 @@
+//! Run Tor on a dedicated thread
+
+use std::os::raw::c_int;
+use std::thread::{self, JoinHandle};
+
+use crate::builder::TorConfigBuilder;
+use crate::configuration::TorConfiguration;
+use crate::control::{BoundListeners, ControlConnection};
+use crate::control_socket::OwningController;
+use crate::error::Error;
+use crate::guard::RunGuard;
+
+/// Name of the thread running Tor, unless changed with [`TorRunner::thread_name`]
+pub const DEFAULT_THREAD_NAME: &str = "tor";
+
+/// Outcome of a Tor run, as returned by [`tor_run_main`](crate::tor_run_main)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TorExit {
+    /// Tor exited cleanly
+    Success,
+    /// Tor exited with a nonzero code
+    Failure(c_int),
+}
+
+impl TorExit {
+    /// Returns `true` if Tor exited cleanly
+    pub fn success(&self) -> bool {
+        *self == TorExit::Success
+    }
+
+    /// Returns the value returned by `tor_run_main`
+    pub fn code(&self) -> c_int {
+        match self {
+            TorExit::Success => 0,
+            TorExit::Failure(code) => *code,
+        }
+    }
+}
+
+impl From<c_int> for TorExit {
+    fn from(code: c_int) -> Self {
+        match code {
+            0 => TorExit::Success,
+            code => TorExit::Failure(code),
+        }
+    }
+}
+
+/// Runs Tor on its own thread, owning the configuration and the command line until it exits
+///
+/// # Example
+///
+/// ```no_run
+/// # use tor_sys::TorRunner;
+/// let handle = TorRunner::spawn(vec!["--SocksPort", "9050"])?;
+/// let exit = handle.join()?;
+/// assert!(exit.success());
+/// # Ok::<(), tor_sys::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TorRunner {
+    args: Vec<String>,
+    thread_name: String,
+    owning_controller: bool,
+}
+
+impl TorRunner {
+    /// Create a runner for the given command line arguments, without the program name
+    pub fn new<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        TorRunner {
+            args: args.into_iter().map(Into::into).collect(),
+            thread_name: DEFAULT_THREAD_NAME.to_string(),
+            owning_controller: false,
+        }
+    }
+
+    /// Create a runner for the configuration of `builder`, preparing the directories of its
+    /// unix socket listeners with [`TorConfigBuilder::prepare_socket_dirs`]
+    pub fn from_config(builder: &TorConfigBuilder) -> Result<Self, Error> {
+        let args = builder.build()?;
+        builder.prepare_socket_dirs()?;
+
+        Ok(TorRunner::new(args))
+    }
+
+    /// Shorthand for `TorRunner::new(args).start()`
+    pub fn spawn<I, S>(args: I) -> Result<TorHandle, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        TorRunner::new(args).start()
+    }
+
+    /// Set the name of the thread running Tor
+    pub fn thread_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// Tie the lifetime of Tor to an [`OwningController`] kept in the returned [`TorHandle`],
+    /// which also makes it available as an authenticated control connection.
+    ///
+    /// Tor shuts down as soon as the controller is dropped, either on its own after being taken
+    /// with [`TorHandle::take_controller`] or together with the handle.
+    pub fn owning_controller(mut self, enabled: bool) -> Self {
+        self.owning_controller = enabled;
+        self
+    }
+
+    /// Start Tor on a new thread.
+    ///
+    /// Fails with [`Error::AlreadyRunning`] if Tor is already running in this process.
+    pub fn start(self) -> Result<TorHandle, Error> {
+        let guard = RunGuard::acquire()?;
+
+        let mut config = TorConfiguration::new()?;
+        config.set_command_line(Some("tor".to_string()).into_iter().chain(self.args))?;
+        let controller = if self.owning_controller {
+            Some(ControlConnection::new(OwningController::new(
+                config.setup_control_socket()?,
+            )))
+        } else {
+            None
+        };
+
+        let thread = thread::Builder::new()
+            .name(self.thread_name)
+            .spawn(move || config.run_with_guard(guard))?;
+
+        Ok(TorHandle { thread, controller })
+    }
+}
+
+/// Handle to a Tor instance started by [`TorRunner`]
+///
+/// When the runner was configured with [`TorRunner::owning_controller`], dropping the handle
+/// while it still holds the controller makes Tor shut down.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tor_sys::TorRunner;
+/// let mut handle = TorRunner::new(vec!["--SocksPort", "9050"])
+///     .owning_controller(true)
+///     .start()?;
+/// let controller = handle.controller().unwrap();
+/// controller.wait_until_bootstrapped_with(Duration::from_secs(120), |status| {
+///     println!("Bootstrapping: {}", status);
+/// })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct TorHandle {
+    thread: JoinHandle<TorExit>,
+    controller: Option<ControlConnection<OwningController>>,
+}
+
+impl TorHandle {
+    /// Returns `true` once Tor has exited
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Returns the connection over the owning controller, if it hasn't been taken yet.
+    ///
+    /// The connection is already authenticated.
+    pub fn controller(&mut self) -> Option<&mut ControlConnection<OwningController>> {
+        self.controller.as_mut()
+    }
+
+    /// Query the addresses Tor is listening on through the owning controller, which is how the
+    /// ports picked for [`Port::Auto`](crate::Port::Auto) can be found.
+    ///
+    /// Fails with [`Error::NoController`] if the runner wasn't configured with
+    /// [`TorRunner::owning_controller`] or the controller has been taken.
+    pub fn bound_listeners(&mut self) -> Result<BoundListeners, Error> {
+        let controller = self.controller.as_mut().ok_or(Error::NoController)?;
+        Ok(controller.bound_listeners()?)
+    }
+
+    /// Enable or disable the network through the owning controller, which is what a platform
+    /// should do when it loses connectivity
+    ///
+    /// Fails with [`Error::NoController`] if there's no owning controller, like
+    /// [`TorHandle::bound_listeners`].
+    pub fn set_network_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        let controller = self.controller.as_mut().ok_or(Error::NoController)?;
+        Ok(controller.set_network_enabled(enabled)?)
+    }
+
+    /// Put Tor in dormant mode, or wake it up, through the owning controller
+    ///
+    /// Fails with [`Error::NoController`] if there's no owning controller, like
+    /// [`TorHandle::bound_listeners`].
+    pub fn set_dormant(&mut self, dormant: bool) -> Result<(), Error> {
+        let controller = self.controller.as_mut().ok_or(Error::NoController)?;
+        Ok(controller.set_dormant(dormant)?)
+    }
+
+    /// Take the owning controller out of the handle, so that Tor keeps running until the
+    /// controller itself is dropped
+    pub fn take_controller(&mut self) -> Option<ControlConnection<OwningController>> {
+        self.controller.take()
+    }
+
+    /// Wait for Tor to exit, keeping the owning controller (if any) alive in the meantime
+    pub fn join(self) -> Result<TorExit, Error> {
+        let TorHandle { thread, controller } = self;
+        let exit = thread.join().map_err(|_| Error::Panicked);
+        drop(controller);
+
+        exit
+    }
+
+    /// Drop the owning controller, making Tor shut down, and wait for it to exit
+    pub fn shutdown(mut self) -> Result<TorExit, Error> {
+        self.controller = None;
+        self.join()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::builder::Port;
+    use crate::control::ControlError;
+    use std::time::Duration;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(TorExit::from(0), TorExit::Success);
+        assert_eq!(TorExit::from(1), TorExit::Failure(1));
+        assert!(TorExit::Success.success());
+        assert!(!TorExit::Failure(-1).success());
+        assert_eq!(TorExit::Failure(-1).code(), -1);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let _lock = crate::guard::test_lock();
+
+        let mut handle = TorRunner::new(vec!["--version"])
+            .thread_name("tor-test")
+            .start()
+            .unwrap();
+        match handle.bound_listeners() {
+            Err(Error::NoController) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        match handle.set_network_enabled(true) {
+            Err(Error::NoController) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        assert_eq!(handle.join().unwrap(), TorExit::Success);
+    }
+
+    #[test]
+    fn test_owning_controller() {
+        let _lock = crate::guard::test_lock();
+
+        let dir = crate::test_utils::test_dir("runner-owning");
+        let args = TorConfigBuilder::new()
+            .data_directory(&dir)
+            .socks_port(Port::Disabled)
+            .disable_network(true)
+            .build()
+            .unwrap();
+
+        let mut handle = TorRunner::new(args)
+            .owning_controller(true)
+            .start()
+            .unwrap();
+        let controller = handle.controller().unwrap();
+        let status = controller.bootstrap_status().unwrap();
+        assert!(!status.is_done());
+        match controller.wait_until_bootstrapped(Duration::from_millis(200)) {
+            Err(ControlError::Timeout) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        // Dropping the controller makes Tor exit
+        assert_eq!(handle.shutdown().unwrap(), TorExit::Success);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_owning_controller() {
+        let _lock = crate::guard::test_lock();
+
+        let dir = crate::test_utils::test_dir("runner-async-owning");
+        let args = TorConfigBuilder::new()
+            .data_directory(&dir)
+            .socks_port(Port::Disabled)
+            .disable_network(true)
+            .build()
+            .unwrap();
+
+        let mut handle = TorRunner::new(args)
+            .owning_controller(true)
+            .start()
+            .unwrap();
+        let controller = handle.take_controller().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut controller = controller.into_async().unwrap();
+            assert!(!controller.bootstrap_status().await.unwrap().is_done());
+            match controller
+                .wait_until_bootstrapped(Duration::from_millis(200))
+                .await
+            {
+                Err(ControlError::Timeout) => {}
+                r => panic!("Unexpected result: {:?}", r),
+            }
+        });
+        // The async controller has been dropped, which makes Tor exit
+        assert_eq!(handle.join().unwrap(), TorExit::Success);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
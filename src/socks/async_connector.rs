@@ -0,0 +1,168 @@
+//! Async version of [`SocksConnector::connect`], available with the `tokio` feature
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use super::{
+    auth_method, check_auth, check_method, check_reply, timed_out, SocksConnector, SocksError,
+    SocksProxy, TargetAddr, VERSION,
+};
+
+impl SocksConnector {
+    /// Open a stream to `target` through the proxy without blocking the runtime
+    pub async fn connect_async<T: Into<TargetAddr>>(
+        &self,
+        target: T,
+    ) -> Result<AsyncSocksStream, SocksError> {
+        let (auth, request) = self.requests(&target.into())?;
+        let connect = async {
+            let mut stream = match &self.proxy {
+                SocksProxy::Tcp(addr) => AsyncSocksStream::Tcp(TcpStream::connect(addr).await?),
+                #[cfg(unix)]
+                SocksProxy::Unix(path) => AsyncSocksStream::Unix(UnixStream::connect(path).await?),
+            };
+            handshake(&mut stream, auth.as_deref(), &request).await?;
+
+            Ok(stream)
+        };
+
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .unwrap_or_else(|_| Err(timed_out())),
+            None => connect.await,
+        }
+    }
+}
+
+/// Stream connected to a target through the proxy, implementing [`AsyncRead`] and
+/// [`AsyncWrite`]
+#[derive(Debug)]
+pub enum AsyncSocksStream {
+    /// Stream opened through a TCP `SocksPort`
+    Tcp(TcpStream),
+    /// Stream opened through a unix socket `SocksPort`
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for AsyncSocksStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncSocksStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            AsyncSocksStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AsyncSocksStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            AsyncSocksStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            AsyncSocksStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncSocksStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            AsyncSocksStream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncSocksStream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            AsyncSocksStream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    auth: Option<&[u8]>,
+    request: &[u8],
+) -> Result<(), SocksError> {
+    let method = auth_method(auth);
+    stream.write_all(&[VERSION, 1, method]).await?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    check_method(reply, method)?;
+
+    if let Some(auth) = auth {
+        stream.write_all(auth).await?;
+        stream.read_exact(&mut reply).await?;
+        check_auth(reply)?;
+    }
+
+    stream.write_all(request).await?;
+    stream.flush().await?;
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let addr_len = match check_reply(header)? {
+        Some(len) => len,
+        None => stream.read_u8().await? as usize,
+    };
+    let mut bound = vec![0; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socks::test::tcp_server;
+    use crate::socks::ConnectError;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_connect_async() {
+        let (connector, server) = tcp_server(0x00);
+        let mut stream = connector
+            .credentials("alice", "circuit-1")
+            .connect_async(("example.onion", 80))
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(
+            server.join().unwrap(),
+            (
+                Some(("alice".into(), "circuit-1".into())),
+                TargetAddr::Domain("example.onion".into(), 80)
+            )
+        );
+
+        let (connector, server) = tcp_server(0xF4);
+        match connector
+            .timeout(Duration::from_secs(10))
+            .connect_async(("example.onion", 80))
+            .await
+        {
+            Err(SocksError::Connect(ConnectError::OnionServiceMissingClientAuth)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Credentials used to isolate streams from each other
+//!
+//! Tor puts streams opened with different SOCKS credentials on different circuits as long as the
+//! `SocksPort` has the `IsolateSOCKSAuth` flag, which is enabled by default. Extra isolation
+//! rules can be enabled with [`TorConfigBuilder::socks_port_flag`], for example
+//! [`SocksPortFlag::IsolateDestAddr`] to also isolate streams to different hosts.
+//!
+//! [`TorConfigBuilder::socks_port_flag`]: crate::TorConfigBuilder::socks_port_flag
+//! [`SocksPortFlag::IsolateDestAddr`]: crate::SocksPortFlag::IsolateDestAddr
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use getrandom::getrandom;
+use sha2::{Digest, Sha256};
+
+use crate::encoding::hex_encode;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Random prefix shared by all the credentials created by this process, so that they don't
+/// collide with the ones of other processes using the same Tor instance
+fn session_id() -> &'static str {
+    static SESSION_ID: OnceLock<String> = OnceLock::new();
+
+    SESSION_ID.get_or_init(|| {
+        let mut bytes = [0u8; 8];
+        getrandom(&mut bytes).expect("The system RNG is unavailable");
+        hex_encode(&bytes).to_lowercase()
+    })
+}
+
+fn next_id() -> u64 {
+    COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// SOCKS credentials identifying an isolated set of streams
+///
+/// Streams opened with the same token can share circuits, streams opened with different tokens
+/// never do. Use [`SocksConnector::isolation`](super::SocksConnector::isolation) to connect with
+/// a token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IsolationToken {
+    username: String,
+    password: String,
+}
+
+impl IsolationToken {
+    /// Create a token different from every other one created by this process
+    pub fn new() -> Self {
+        IsolationToken {
+            username: session_id().to_string(),
+            password: format!("t{}", next_id()),
+        }
+    }
+
+    /// Returns the SOCKS username
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Returns the SOCKS password
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl Default for IsolationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Group of isolation tokens sharing the same SOCKS username
+///
+/// Tokens of a group are still isolated from each other, but are easy to tell apart from the
+/// ones of other groups in Tor's logs. A group can also map keys, like the name of an account,
+/// to stable tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IsolationGroup {
+    id: String,
+}
+
+impl IsolationGroup {
+    /// Create a group different from every other one created by this process
+    pub fn new() -> Self {
+        IsolationGroup {
+            id: format!("{}-g{}", session_id(), next_id()),
+        }
+    }
+
+    /// Returns the SOCKS username shared by the tokens of this group
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Create a token different from every other one created by this process
+    pub fn new_token(&self) -> IsolationToken {
+        IsolationToken {
+            username: self.id.clone(),
+            password: format!("t{}", next_id()),
+        }
+    }
+
+    /// Returns the token for `key`, which is always the same for the same group and key
+    pub fn token(&self, key: &str) -> IsolationToken {
+        IsolationToken {
+            username: self.id.clone(),
+            password: format!(
+                "k{}",
+                hex_encode(&Sha256::digest(key.as_bytes())).to_lowercase()
+            ),
+        }
+    }
+}
+
+impl Default for IsolationGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socks::test::tcp_server;
+    use crate::socks::TargetAddr;
+    use std::collections::HashSet;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_tokens() {
+        let tokens = (0..100)
+            .map(|_| IsolationToken::new())
+            .collect::<HashSet<_>>();
+        assert_eq!(tokens.len(), 100);
+        let passwords = tokens.iter().map(|t| t.password()).collect::<HashSet<_>>();
+        assert_eq!(passwords.len(), 100);
+        assert!(tokens.iter().all(|t| t.username() == session_id()));
+        // Both fields have to fit in the RFC 1929 request
+        assert!(tokens
+            .iter()
+            .all(|t| t.username().len() <= 255 && t.password().len() <= 255));
+    }
+
+    #[test]
+    fn test_groups() {
+        let group = IsolationGroup::new();
+        let other = IsolationGroup::new();
+        assert_ne!(group, other);
+
+        let token = group.new_token();
+        assert_eq!(token.username(), group.id());
+        assert_ne!(token, group.new_token());
+
+        assert_eq!(group.token("alice"), group.token("alice"));
+        assert_ne!(group.token("alice"), group.token("bob"));
+        assert_ne!(group.token("alice"), other.token("alice"));
+        assert_eq!(group.token("alice").username(), group.id());
+    }
+
+    #[test]
+    fn test_connect_isolated() {
+        let token = IsolationToken::new();
+        let (connector, server) = tcp_server(0x00);
+        let mut stream = connector
+            .isolation(&token)
+            .connect(("example.com", 80))
+            .unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            (
+                Some((token.username().into(), token.password().into())),
+                TargetAddr::Domain("example.com".into(), 80)
+            )
+        );
+    }
+}
@@ -0,0 +1,786 @@
+//! SOCKS5 client for Tor's `SocksPort`, as described in RFC 1928 and `socks-extensions.txt`
+//!
+//! Hostnames, including onion addresses, are always sent to Tor unresolved, so that DNS
+//! resolution happens over the Tor network. The username and password, which Tor accepts without
+//! checking them, are used to isolate streams from each other: with the default `SocksPort`
+//! flags, streams opened with different credentials never share a circuit. [`IsolationToken`]
+//! and [`IsolationGroup`] hand out unique credentials for this purpose.
+//!
+//! Failures are returned as a [`ConnectError`], including the detailed errors for onion services
+//! like a missing client authorization when the `SocksPort` has the
+//! [`SocksPortFlag::ExtendedErrors`](crate::SocksPortFlag::ExtendedErrors) flag, which
+//! [`TorConfigBuilder`](crate::TorConfigBuilder) sets by default.
+//!
+//! With the `tokio` feature, [`SocksConnector::connect_async`] returns an [`AsyncSocksStream`]
+//! instead.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use std::io::{Read, Write};
+//! # use tor_sys::socks::SocksConnector;
+//! let connector = SocksConnector::tcp("127.0.0.1:9050".parse().unwrap());
+//! let mut stream = connector.connect(("example.com", 80))?;
+//! stream.write_all(b"GET / HTTP/1.0\r\nHost: example.com\r\n\r\n")?;
+//! let mut response = String::new();
+//! stream.read_to_string(&mut response)?;
+//! # Ok::<(), tor_sys::socks::SocksError>(())
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::control::Listener;
+use crate::onion::OnionAddress;
+use crate::Port;
+
+#[cfg(feature = "tokio")]
+mod async_connector;
+mod isolation;
+mod reply;
+
+#[cfg(feature = "tokio")]
+pub use self::async_connector::AsyncSocksStream;
+pub use self::isolation::{IsolationGroup, IsolationToken};
+pub use self::reply::ConnectError;
+
+const VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xFF;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// Errors returned by the SOCKS connector
+#[derive(Debug)]
+pub enum SocksError {
+    /// I/O error on the underlying stream
+    Io(io::Error),
+    /// The proxy sent something that doesn't follow the SOCKS5 protocol
+    Protocol(String),
+    /// The target or the credentials can't be encoded in a SOCKS5 request
+    InvalidRequest(String),
+    /// The proxy didn't accept the authentication method or the credentials
+    Auth(String),
+    /// The proxy failed to connect to the target
+    Connect(ConnectError),
+}
+
+impl fmt::Display for SocksError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SocksError::Io(e) => write!(f, "I/O error: {}", e),
+            SocksError::Protocol(msg) => write!(f, "SOCKS protocol error: {}", msg),
+            SocksError::InvalidRequest(msg) => write!(f, "Invalid SOCKS request: {}", msg),
+            SocksError::Auth(msg) => write!(f, "SOCKS authentication error: {}", msg),
+            SocksError::Connect(e) => write!(f, "The proxy failed to connect: {}", e),
+        }
+    }
+}
+
+impl StdError for SocksError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            SocksError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SocksError {
+    fn from(e: io::Error) -> Self {
+        SocksError::Io(e)
+    }
+}
+
+/// Destination of a connection made through the proxy
+///
+/// Strings and `(host, port)` tuples are converted to [`TargetAddr::Ip`] when the host is an IP
+/// address, and to [`TargetAddr::Domain`] otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TargetAddr {
+    /// IP address and port
+    Ip(SocketAddr),
+    /// Hostname or onion address, resolved by Tor, and port
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    /// Port `port` of an onion service
+    pub fn onion(address: &OnionAddress, port: u16) -> Self {
+        TargetAddr::Domain(address.to_string(), port)
+    }
+
+    fn host(host: &str, port: u16) -> Self {
+        // IPv6 addresses may be written in brackets, like in URLs
+        let ip = host.trim_start_matches('[').trim_end_matches(']');
+        match ip.parse::<IpAddr>() {
+            Ok(ip) => TargetAddr::Ip(SocketAddr::new(ip, port)),
+            Err(_) => TargetAddr::Domain(host.to_string(), port),
+        }
+    }
+}
+
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetAddr::Ip(addr) => write!(f, "{}", addr),
+            TargetAddr::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl FromStr for TargetAddr {
+    type Err = SocksError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() => match port.parse() {
+                Ok(port) => Ok(TargetAddr::host(host, port)),
+                Err(_) => Err(SocksError::InvalidRequest(format!(
+                    "Invalid port in `{}`",
+                    s
+                ))),
+            },
+            _ => Err(SocksError::InvalidRequest(format!(
+                "Expected `<host>:<port>`, found `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+impl From<SocketAddr> for TargetAddr {
+    fn from(addr: SocketAddr) -> Self {
+        TargetAddr::Ip(addr)
+    }
+}
+
+impl<'a> From<(&'a str, u16)> for TargetAddr {
+    fn from((host, port): (&'a str, u16)) -> Self {
+        TargetAddr::host(host, port)
+    }
+}
+
+impl From<(String, u16)> for TargetAddr {
+    fn from((host, port): (String, u16)) -> Self {
+        TargetAddr::host(&host, port)
+    }
+}
+
+impl From<(OnionAddress, u16)> for TargetAddr {
+    fn from((address, port): (OnionAddress, u16)) -> Self {
+        TargetAddr::onion(&address, port)
+    }
+}
+
+/// Address of Tor's `SocksPort`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SocksProxy {
+    /// TCP listener
+    Tcp(SocketAddr),
+    /// Unix domain socket listener (`SocksPort unix:<path>`)
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Connector opening streams through Tor's `SocksPort`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocksConnector {
+    proxy: SocksProxy,
+    credentials: Option<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl SocksConnector {
+    /// Connect through the proxy at `proxy`
+    pub fn new(proxy: SocksProxy) -> Self {
+        SocksConnector {
+            proxy,
+            credentials: None,
+            timeout: None,
+        }
+    }
+
+    /// Connect through a TCP `SocksPort`
+    pub fn tcp(addr: SocketAddr) -> Self {
+        SocksConnector::new(SocksProxy::Tcp(addr))
+    }
+
+    /// Connect through a `SocksPort unix:<path>` listener
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P) -> Self {
+        SocksConnector::new(SocksProxy::Unix(path.into()))
+    }
+
+    /// Connect through the `SocksPort` configured with
+    /// [`TorConfigBuilder::socks_port`](crate::TorConfigBuilder::socks_port)
+    ///
+    /// Returns `None` for [`Port::Disabled`] and [`Port::Auto`], since the port picked by Tor
+    /// isn't known in advance: use [`SocksConnector::from_listener`] with the address reported
+    /// by Tor instead.
+    pub fn from_port(port: &Port) -> Option<Self> {
+        match port {
+            Port::Disabled | Port::Auto => None,
+            Port::Local(port) => Some(SocksConnector::tcp(SocketAddr::from((
+                [127, 0, 0, 1],
+                *port,
+            )))),
+            Port::Addr(addr) => Some(SocksConnector::tcp(*addr)),
+            #[cfg(unix)]
+            Port::Unix(path) => Some(SocksConnector::unix(path.clone())),
+            #[cfg(not(unix))]
+            Port::Unix(_) => None,
+        }
+    }
+
+    /// Create a connector for a listener reported by Tor, like the ones returned by
+    /// [`ControlConnection::bound_listeners`](crate::control::ControlConnection::bound_listeners)
+    ///
+    /// Returns `None` for unix sockets on platforms that don't support them.
+    pub fn from_listener(listener: &Listener) -> Option<Self> {
+        match listener {
+            Listener::Tcp(addr) => Some(SocksConnector::tcp(*addr)),
+            #[cfg(unix)]
+            Listener::Unix(path) => Some(SocksConnector::unix(path.clone())),
+            #[cfg(not(unix))]
+            Listener::Unix(_) => None,
+        }
+    }
+
+    /// Authenticate with `username` and `password`, isolating the streams from those opened
+    /// with different credentials
+    pub fn credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Authenticate with the credentials of `token`, see [`IsolationToken`]
+    pub fn isolation(self, token: &IsolationToken) -> Self {
+        self.credentials(token.username(), token.password())
+    }
+
+    /// Give up if connecting to the proxy or the handshake take longer than `timeout`
+    ///
+    /// Tor only replies once the connection to the target is established, which can take a while
+    /// for onion services.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the address of the proxy
+    pub fn proxy(&self) -> &SocksProxy {
+        &self.proxy
+    }
+
+    /// Open a stream to `target` through the proxy
+    pub fn connect<T: Into<TargetAddr>>(&self, target: T) -> Result<SocksStream, SocksError> {
+        let (auth, request) = self.requests(&target.into())?;
+
+        let mut stream = match &self.proxy {
+            SocksProxy::Tcp(addr) => SocksStream::Tcp(match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(addr, timeout)?,
+                None => TcpStream::connect(addr)?,
+            }),
+            #[cfg(unix)]
+            SocksProxy::Unix(path) => SocksStream::Unix(UnixStream::connect(path)?),
+        };
+
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        handshake(&mut stream, auth.as_deref(), &request).map_err(|e| match e {
+            SocksError::Io(ref io) if io.kind() == io::ErrorKind::WouldBlock => timed_out(),
+            e => e,
+        })?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+
+        Ok(stream)
+    }
+
+    /// Encode the authentication, if any, and the `CONNECT` request
+    fn requests(&self, target: &TargetAddr) -> Result<(Option<Vec<u8>>, Vec<u8>), SocksError> {
+        let auth = match &self.credentials {
+            Some((username, password)) => Some(auth_request(username, password)?),
+            None => None,
+        };
+
+        Ok((auth, connect_request(target)?))
+    }
+}
+
+/// Stream connected to a target through the proxy
+#[derive(Debug)]
+pub enum SocksStream {
+    /// Stream opened through a TCP `SocksPort`
+    Tcp(TcpStream),
+    /// Stream opened through a unix socket `SocksPort`
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl SocksStream {
+    /// Shut down the read, write, or both halves of the stream
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            SocksStream::Tcp(s) => s.shutdown(how),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.shutdown(how),
+        }
+    }
+
+    /// Set the read timeout, `None` meaning that reads block indefinitely
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            SocksStream::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    /// Set the write timeout, `None` meaning that writes block indefinitely
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            SocksStream::Tcp(s) => s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.set_write_timeout(timeout),
+        }
+    }
+
+    /// Move the stream in or out of nonblocking mode
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            SocksStream::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Create another handle to the same stream
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            SocksStream::Tcp(s) => SocksStream::Tcp(s.try_clone()?),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => SocksStream::Unix(s.try_clone()?),
+        })
+    }
+}
+
+impl Read for SocksStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SocksStream::Tcp(s) => s.read(buf),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for SocksStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SocksStream::Tcp(s) => s.write(buf),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SocksStream::Tcp(s) => s.flush(),
+            #[cfg(unix)]
+            SocksStream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+fn timed_out() -> SocksError {
+    SocksError::Io(io::Error::new(io::ErrorKind::TimedOut, "SOCKS handshake"))
+}
+
+/// Encode the username/password sub-negotiation of RFC 1929
+fn auth_request(username: &str, password: &str) -> Result<Vec<u8>, SocksError> {
+    // Tor doesn't care about the credentials, but the protocol can't encode empty ones
+    for field in &[username, password] {
+        if field.is_empty() || field.len() > 255 {
+            return Err(SocksError::InvalidRequest(
+                "The username and password must be 1 to 255 bytes long".into(),
+            ));
+        }
+    }
+
+    let mut buf = vec![USERNAME_PASSWORD_VERSION, username.len() as u8];
+    buf.extend_from_slice(username.as_bytes());
+    buf.push(password.len() as u8);
+    buf.extend_from_slice(password.as_bytes());
+    Ok(buf)
+}
+
+fn connect_request(target: &TargetAddr) -> Result<Vec<u8>, SocksError> {
+    let mut buf = vec![VERSION, CMD_CONNECT, 0x00];
+    let port = match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        TargetAddr::Ip(SocketAddr::V6(addr)) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        TargetAddr::Domain(host, port) => {
+            if host.is_empty() || host.len() > 255 {
+                return Err(SocksError::InvalidRequest(format!(
+                    "Invalid hostname length {}",
+                    host.len()
+                )));
+            }
+            buf.push(ATYP_DOMAIN);
+            buf.push(host.len() as u8);
+            buf.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+    buf.extend_from_slice(&port.to_be_bytes());
+
+    Ok(buf)
+}
+
+fn handshake<S: Read + Write>(
+    stream: &mut S,
+    auth: Option<&[u8]>,
+    request: &[u8],
+) -> Result<(), SocksError> {
+    let method = auth_method(auth);
+    stream.write_all(&[VERSION, 1, method])?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    check_method(reply, method)?;
+
+    if let Some(auth) = auth {
+        stream.write_all(auth)?;
+        stream.read_exact(&mut reply)?;
+        check_auth(reply)?;
+    }
+
+    stream.write_all(request)?;
+    stream.flush()?;
+    let mut header = [0; 4];
+    stream.read_exact(&mut header)?;
+    let addr_len = match check_reply(header)? {
+        Some(len) => len,
+        None => {
+            let mut len = [0];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+    };
+    // Skip the bound address, which Tor always sets to 0.0.0.0:0
+    let mut bound = vec![0; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(())
+}
+
+fn auth_method(auth: Option<&[u8]>) -> u8 {
+    // Only offer username/password when we have credentials, so that Tor can't pick the other
+    // method and silently drop the isolation
+    match auth {
+        Some(_) => METHOD_USERNAME_PASSWORD,
+        None => METHOD_NO_AUTH,
+    }
+}
+
+fn check_method(reply: [u8; 2], method: u8) -> Result<(), SocksError> {
+    match reply {
+        [VERSION, m] if m == method => Ok(()),
+        [VERSION, METHOD_NOT_ACCEPTABLE] => Err(SocksError::Auth(
+            "The proxy doesn't support the authentication method".into(),
+        )),
+        _ => Err(SocksError::Protocol(format!(
+            "Unexpected method selection {:02x?}",
+            reply
+        ))),
+    }
+}
+
+fn check_auth(reply: [u8; 2]) -> Result<(), SocksError> {
+    match reply {
+        [USERNAME_PASSWORD_VERSION, 0x00] => Ok(()),
+        [USERNAME_PASSWORD_VERSION, _] => Err(SocksError::Auth(
+            "The proxy rejected the credentials".into(),
+        )),
+        _ => Err(SocksError::Protocol(format!(
+            "Unexpected authentication reply {:02x?}",
+            reply
+        ))),
+    }
+}
+
+/// Check the header of the reply to `CONNECT`, returning the length of the bound address that
+/// follows it, or `None` if it's a domain prefixed by its length
+fn check_reply(header: [u8; 4]) -> Result<Option<usize>, SocksError> {
+    if header[0] != VERSION {
+        return Err(SocksError::Protocol(format!(
+            "Unexpected version {:#04x} in reply",
+            header[0]
+        )));
+    }
+    if header[1] != REPLY_SUCCEEDED {
+        return Err(SocksError::Connect(ConnectError::from_code(header[1])));
+    }
+
+    match header[3] {
+        ATYP_IPV4 => Ok(Some(4)),
+        ATYP_IPV6 => Ok(Some(16)),
+        ATYP_DOMAIN => Ok(None),
+        atyp => Err(SocksError::Protocol(format!(
+            "Unexpected address type {:#04x} in reply",
+            atyp
+        ))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Credentials and target received by the test server
+    pub(crate) type Request = (Option<(String, String)>, TargetAddr);
+
+    /// Minimal SOCKS5 server that checks the handshake, replies with `reply` and then echoes
+    /// the data it receives
+    fn serve<S: Read + Write>(mut stream: S, reply: u8) -> Request {
+        let mut buf = [0; 3];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..2], [VERSION, 1]);
+
+        stream.write_all(&[VERSION, buf[2]]).unwrap();
+        let credentials = if buf[2] == METHOD_USERNAME_PASSWORD {
+            let read_field = |stream: &mut S| {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len).unwrap();
+                let mut field = vec![0; len[0] as usize];
+                stream.read_exact(&mut field).unwrap();
+                String::from_utf8(field).unwrap()
+            };
+            let mut version = [0; 1];
+            stream.read_exact(&mut version).unwrap();
+            assert_eq!(version[0], USERNAME_PASSWORD_VERSION);
+            let username = read_field(&mut stream);
+            let password = read_field(&mut stream);
+            stream
+                .write_all(&[USERNAME_PASSWORD_VERSION, 0x00])
+                .unwrap();
+            Some((username, password))
+        } else {
+            None
+        };
+
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[..3], [VERSION, CMD_CONNECT, 0x00]);
+        let target = match header[3] {
+            ATYP_IPV4 => {
+                let mut buf = [0; 6];
+                stream.read_exact(&mut buf).unwrap();
+                let ip = [buf[0], buf[1], buf[2], buf[3]];
+                TargetAddr::Ip(SocketAddr::from((ip, u16::from_be_bytes([buf[4], buf[5]]))))
+            }
+            ATYP_DOMAIN => {
+                let mut len = [0; 1];
+                stream.read_exact(&mut len).unwrap();
+                let mut buf = vec![0; len[0] as usize + 2];
+                stream.read_exact(&mut buf).unwrap();
+                let port = u16::from_be_bytes([buf[buf.len() - 2], buf[buf.len() - 1]]);
+                buf.truncate(len[0] as usize);
+                TargetAddr::Domain(String::from_utf8(buf).unwrap(), port)
+            }
+            atyp => panic!("Unexpected address type {}", atyp),
+        };
+
+        stream
+            .write_all(&[VERSION, reply, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+        if reply == REPLY_SUCCEEDED {
+            let mut buf = [0; 5];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+        }
+
+        (credentials, target)
+    }
+
+    pub(crate) fn tcp_server(reply: u8) -> (SocksConnector, thread::JoinHandle<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connector = SocksConnector::tcp(listener.local_addr().unwrap());
+        let server = thread::spawn(move || serve(listener.accept().unwrap().0, reply));
+        (connector, server)
+    }
+
+    #[test]
+    fn test_target_addr() {
+        assert_eq!(
+            "example.com:80".parse::<TargetAddr>().unwrap(),
+            TargetAddr::Domain("example.com".into(), 80)
+        );
+        assert_eq!(
+            "[::1]:443".parse::<TargetAddr>().unwrap(),
+            TargetAddr::Ip("[::1]:443".parse().unwrap())
+        );
+        assert_eq!(
+            TargetAddr::from(("127.0.0.1", 80)),
+            TargetAddr::Ip("127.0.0.1:80".parse().unwrap())
+        );
+        for invalid in &[
+            "example.com",
+            ":80",
+            "example.com:http",
+            "example.com:65536",
+        ] {
+            assert!(invalid.parse::<TargetAddr>().is_err(), "{}", invalid);
+        }
+
+        let address = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd"
+            .parse::<OnionAddress>()
+            .unwrap();
+        assert_eq!(
+            TargetAddr::from((address, 80)).to_string(),
+            "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:80"
+        );
+    }
+
+    #[test]
+    fn test_connect() {
+        let (connector, server) = tcp_server(REPLY_SUCCEEDED);
+        let mut stream = connector.connect(("example.com", 443)).unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(
+            server.join().unwrap(),
+            (None, TargetAddr::Domain("example.com".into(), 443))
+        );
+
+        let (connector, server) = tcp_server(REPLY_SUCCEEDED);
+        let mut stream = connector
+            .credentials("alice", "circuit-1")
+            .timeout(Duration::from_secs(10))
+            .connect("10.0.0.1:8333".parse::<SocketAddr>().unwrap())
+            .unwrap();
+        stream.write_all(b"world").unwrap();
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            (
+                Some(("alice".into(), "circuit-1".into())),
+                TargetAddr::Ip("10.0.0.1:8333".parse().unwrap())
+            )
+        );
+    }
+
+    #[test]
+    fn test_connect_failed() {
+        let (connector, server) = tcp_server(0x05);
+        match connector.connect(("example.com", 80)) {
+            Err(SocksError::Connect(ConnectError::ConnectionRefused)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        server.join().unwrap();
+
+        let (connector, server) = tcp_server(0xF4);
+        match connector.connect(("example.onion", 80)) {
+            Err(SocksError::Connect(e)) => assert!(e.requires_client_auth()),
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        server.join().unwrap();
+
+        let connector = SocksConnector::tcp("127.0.0.1:9".parse().unwrap());
+        for target in &[
+            TargetAddr::Domain(String::new(), 80),
+            TargetAddr::Domain("a".repeat(256), 80),
+        ] {
+            match connector.connect(target.clone()) {
+                Err(SocksError::InvalidRequest(_)) => {}
+                r => panic!("Unexpected result for {:?}: {:?}", target, r),
+            }
+        }
+        match connector
+            .clone()
+            .credentials("", "")
+            .connect(("example.com", 80))
+        {
+            Err(SocksError::InvalidRequest(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_rejected_method() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connector = SocksConnector::tcp(listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let mut buf = [0; 3];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&[VERSION, METHOD_NOT_ACCEPTABLE]).unwrap();
+        });
+        match connector.connect(("example.com", 80)) {
+            Err(SocksError::Auth(_)) => {}
+            r => panic!("Unexpected result: {:?}", r),
+        }
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_connect_unix() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = crate::test_utils::test_dir("socks-unix");
+        let path = dir.join("socks.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || serve(listener.accept().unwrap().0, REPLY_SUCCEEDED));
+
+        let connector = SocksConnector::from_port(&Port::Unix(path)).unwrap();
+        let mut stream = connector.connect(("example.onion", 80)).unwrap();
+        stream.write_all(b"hello").unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            server.join().unwrap().1,
+            TargetAddr::Domain("example.onion".into(), 80)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_from_port() {
+        assert_eq!(SocksConnector::from_port(&Port::Disabled), None);
+        assert_eq!(SocksConnector::from_port(&Port::Auto), None);
+        assert_eq!(
+            SocksConnector::from_port(&Port::Local(9050))
+                .unwrap()
+                .proxy(),
+            &SocksProxy::Tcp("127.0.0.1:9050".parse().unwrap())
+        );
+    }
+}
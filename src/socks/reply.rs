@@ -0,0 +1,166 @@
+//! Reply codes of failed `CONNECT` requests, including Tor's extended errors
+//!
+//! The codes `0xF0`-`0xF7` are only sent by `SocksPort` listeners with the `ExtendedErrors` flag,
+//! see [`SocksPortFlag::ExtendedErrors`](crate::SocksPortFlag::ExtendedErrors), which
+//! [`TorConfigBuilder`](crate::TorConfigBuilder) sets by default. Without it,
+//! onion service failures are reported as [`ConnectError::TtlExpired`] or
+//! [`ConnectError::GeneralFailure`].
+
+use std::fmt;
+
+/// Reason why the proxy failed to connect to the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectError {
+    /// General SOCKS server failure (`0x01`)
+    GeneralFailure,
+    /// Connection not allowed by ruleset (`0x02`), for example a non-onion address on a
+    /// `SocksPort` with `OnionTrafficOnly`
+    NotAllowed,
+    /// Network unreachable (`0x03`)
+    NetworkUnreachable,
+    /// Host unreachable (`0x04`), which includes DNS failures
+    HostUnreachable,
+    /// Connection refused by the target (`0x05`)
+    ConnectionRefused,
+    /// TTL expired (`0x06`), which Tor uses for timeouts
+    TtlExpired,
+    /// Command not supported (`0x07`)
+    CommandNotSupported,
+    /// Address type not supported (`0x08`)
+    AddressTypeNotSupported,
+    /// The descriptor of the onion service couldn't be found (`0xF0`)
+    OnionServiceNotFound,
+    /// The descriptor of the onion service is invalid (`0xF1`)
+    OnionServiceInvalidDescriptor,
+    /// Every introduction point failed (`0xF2`)
+    OnionServiceIntroFailed,
+    /// The rendezvous failed (`0xF3`)
+    OnionServiceRendezvousFailed,
+    /// The onion service requires client authorization, and Tor has no credentials for it
+    /// (`0xF4`)
+    OnionServiceMissingClientAuth,
+    /// The credentials known to Tor were rejected by the onion service (`0xF5`)
+    OnionServiceBadClientAuth,
+    /// The onion address is invalid (`0xF6`), for example because of a wrong checksum
+    OnionServiceBadAddress,
+    /// Timed out while connecting to the introduction points (`0xF7`)
+    OnionServiceIntroTimedOut,
+    /// Any other code
+    Unknown(u8),
+}
+
+impl ConnectError {
+    /// Decode the reply code sent by the proxy, which must not be `0x00` (succeeded)
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => ConnectError::GeneralFailure,
+            0x02 => ConnectError::NotAllowed,
+            0x03 => ConnectError::NetworkUnreachable,
+            0x04 => ConnectError::HostUnreachable,
+            0x05 => ConnectError::ConnectionRefused,
+            0x06 => ConnectError::TtlExpired,
+            0x07 => ConnectError::CommandNotSupported,
+            0x08 => ConnectError::AddressTypeNotSupported,
+            0xF0 => ConnectError::OnionServiceNotFound,
+            0xF1 => ConnectError::OnionServiceInvalidDescriptor,
+            0xF2 => ConnectError::OnionServiceIntroFailed,
+            0xF3 => ConnectError::OnionServiceRendezvousFailed,
+            0xF4 => ConnectError::OnionServiceMissingClientAuth,
+            0xF5 => ConnectError::OnionServiceBadClientAuth,
+            0xF6 => ConnectError::OnionServiceBadAddress,
+            0xF7 => ConnectError::OnionServiceIntroTimedOut,
+            code => ConnectError::Unknown(code),
+        }
+    }
+
+    /// Returns the reply code
+    pub fn code(&self) -> u8 {
+        match self {
+            ConnectError::GeneralFailure => 0x01,
+            ConnectError::NotAllowed => 0x02,
+            ConnectError::NetworkUnreachable => 0x03,
+            ConnectError::HostUnreachable => 0x04,
+            ConnectError::ConnectionRefused => 0x05,
+            ConnectError::TtlExpired => 0x06,
+            ConnectError::CommandNotSupported => 0x07,
+            ConnectError::AddressTypeNotSupported => 0x08,
+            ConnectError::OnionServiceNotFound => 0xF0,
+            ConnectError::OnionServiceInvalidDescriptor => 0xF1,
+            ConnectError::OnionServiceIntroFailed => 0xF2,
+            ConnectError::OnionServiceRendezvousFailed => 0xF3,
+            ConnectError::OnionServiceMissingClientAuth => 0xF4,
+            ConnectError::OnionServiceBadClientAuth => 0xF5,
+            ConnectError::OnionServiceBadAddress => 0xF6,
+            ConnectError::OnionServiceIntroTimedOut => 0xF7,
+            ConnectError::Unknown(code) => *code,
+        }
+    }
+
+    /// Returns `true` for the extended errors specific to onion services
+    pub fn is_onion_service_error(&self) -> bool {
+        (0xF0..=0xF7).contains(&self.code())
+    }
+
+    /// Returns `true` if the onion service needs client authorization credentials, either
+    /// because Tor has none or because they were rejected
+    pub fn requires_client_auth(&self) -> bool {
+        matches!(
+            self,
+            ConnectError::OnionServiceMissingClientAuth | ConnectError::OnionServiceBadClientAuth
+        )
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            ConnectError::GeneralFailure => "general SOCKS server failure",
+            ConnectError::NotAllowed => "connection not allowed by ruleset",
+            ConnectError::NetworkUnreachable => "network unreachable",
+            ConnectError::HostUnreachable => "host unreachable",
+            ConnectError::ConnectionRefused => "connection refused",
+            ConnectError::TtlExpired => "TTL expired",
+            ConnectError::CommandNotSupported => "command not supported",
+            ConnectError::AddressTypeNotSupported => "address type not supported",
+            ConnectError::OnionServiceNotFound => "onion service descriptor not found",
+            ConnectError::OnionServiceInvalidDescriptor => "invalid onion service descriptor",
+            ConnectError::OnionServiceIntroFailed => "onion service introduction failed",
+            ConnectError::OnionServiceRendezvousFailed => "onion service rendezvous failed",
+            ConnectError::OnionServiceMissingClientAuth => {
+                "onion service requires client authorization"
+            }
+            ConnectError::OnionServiceBadClientAuth => {
+                "onion service rejected the client authorization"
+            }
+            ConnectError::OnionServiceBadAddress => "invalid onion address",
+            ConnectError::OnionServiceIntroTimedOut => "onion service introduction timed out",
+            ConnectError::Unknown(_) => "unknown error",
+        };
+        write!(f, "{} ({:#04x})", msg, self.code())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codes() {
+        for code in 1..=255 {
+            assert_eq!(ConnectError::from_code(code).code(), code);
+        }
+        assert_eq!(ConnectError::from_code(0x09), ConnectError::Unknown(0x09));
+
+        let missing = ConnectError::from_code(0xF4);
+        assert_eq!(missing, ConnectError::OnionServiceMissingClientAuth);
+        assert!(missing.is_onion_service_error());
+        assert!(missing.requires_client_auth());
+        assert_eq!(
+            missing.to_string(),
+            "onion service requires client authorization (0xf4)"
+        );
+        assert!(ConnectError::from_code(0xF5).requires_client_auth());
+        assert!(!ConnectError::from_code(0xF7).requires_client_auth());
+        assert!(!ConnectError::ConnectionRefused.is_onion_service_error());
+    }
+}
@@ -0,0 +1,20 @@
+//! Helpers shared by the tests
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Create an empty private directory for a test, unique to this process
+pub(crate) fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("libtor-sys-{}-{}", std::process::id(), name));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+    }
+
+    dir
+}
@@ -0,0 +1,228 @@
+//! Parsing of the version string returned by
+//! [`tor_api_get_provider_version`](crate::tor_api_get_provider_version)
+
+use std::error::Error;
+use std::ffi::CStr;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::tor_api_get_provider_version;
+
+/// Version of Tor, in the `MAJOR.MINOR.MICRO[.PATCHLEVEL][-STATUS_TAG]` format described in
+/// `version-spec.txt`
+///
+/// Returned by [`provider_version`] as part of a [`ProviderVersion`], and by the `version`
+/// GETINFO key with [`ControlConnection::version`](crate::control::ControlConnection::version).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TorVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub micro: u32,
+    /// `0` when the version doesn't have a patch level
+    pub patch: u32,
+    /// Optional status tag, like `alpha` or `alpha-dev`
+    pub status: Option<String>,
+    /// Extra information in parentheses, like the git commit
+    pub extra: Option<String>,
+}
+
+/// Name and version of the software implementing `tor_api.h`, as reported by
+/// [`tor_api_get_provider_version`](crate::tor_api_get_provider_version).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProviderVersion {
+    /// Name of the implementation, normally `tor`
+    pub product: String,
+    pub version: TorVersion,
+}
+
+/// Error returned when a version string doesn't follow the expected format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseVersionError(String);
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid version string `{}`", self.0)
+    }
+}
+
+impl Error for ParseVersionError {}
+
+impl TorVersion {
+    /// Returns `true` if this version is `major.minor.micro.patch` or newer, ignoring the
+    /// status tag
+    pub fn at_least(&self, major: u32, minor: u32, micro: u32, patch: u32) -> bool {
+        (self.major, self.minor, self.micro, self.patch) >= (major, minor, micro, patch)
+    }
+
+    /// Returns `true` if this version matches the Tor version recorded in the crate's build
+    /// metadata (see [`crate_tor_version`]).
+    ///
+    /// Components set to `x` in the build metadata match any value.
+    pub fn matches_crate_version(&self) -> bool {
+        let expected = crate_tor_version().split('.');
+        let actual = [self.major, self.minor, self.micro, self.patch];
+
+        expected
+            .zip(actual.iter())
+            .all(|(expected, actual)| expected == "x" || expected == actual.to_string())
+    }
+}
+
+impl FromStr for TorVersion {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseVersionError(s.to_string());
+
+        let (version, extra) = match s.find(" (") {
+            Some(i) => {
+                let extra = s[i + 2..].strip_suffix(')').ok_or_else(err)?;
+                (&s[..i], Some(extra.to_string()))
+            }
+            None => (s, None),
+        };
+
+        let mut version_parts = version.splitn(2, '-');
+        let numbers = version_parts.next().ok_or_else(err)?;
+        let status = version_parts.next().map(str::to_string);
+
+        let numbers = numbers
+            .split('.')
+            .map(|n| n.parse::<u32>().map_err(|_| err()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (major, minor, micro, patch) = match numbers[..] {
+            [major, minor, micro] => (major, minor, micro, 0),
+            [major, minor, micro, patch] => (major, minor, micro, patch),
+            _ => return Err(err()),
+        };
+
+        Ok(TorVersion {
+            major,
+            minor,
+            micro,
+            patch,
+            status,
+            extra,
+        })
+    }
+}
+
+impl fmt::Display for TorVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.micro, self.patch
+        )?;
+        if let Some(status) = &self.status {
+            write!(f, "-{}", status)?;
+        }
+        if let Some(extra) = &self.extra {
+            write!(f, " ({})", extra)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ProviderVersion {
+    /// Shorthand for [`TorVersion::matches_crate_version`]
+    pub fn matches_crate_version(&self) -> bool {
+        self.version.matches_crate_version()
+    }
+}
+
+impl FromStr for ProviderVersion {
+    type Err = ParseVersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseVersionError(s.to_string());
+
+        let mut parts = s.trim().splitn(2, ' ');
+        let product = parts.next().filter(|p| !p.is_empty()).ok_or_else(err)?;
+        let version = parts.next().ok_or_else(err)?.parse().map_err(|_| err())?;
+
+        Ok(ProviderVersion {
+            product: product.to_string(),
+            version,
+        })
+    }
+}
+
+impl fmt::Display for ProviderVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.product, self.version)
+    }
+}
+
+/// Returns the Tor version this crate was built for, taken from the build metadata of the crate
+/// version (i.e. `0.4.7.x` for `47.13.0+0.4.7.x`)
+pub fn crate_tor_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+        .splitn(2, '+')
+        .nth(1)
+        .unwrap_or_default()
+}
+
+/// Returns the parsed name and version of the linked Tor
+pub fn provider_version() -> Result<ProviderVersion, ParseVersionError> {
+    let version = unsafe { CStr::from_ptr(tor_api_get_provider_version()) };
+    version.to_string_lossy().parse()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let version: ProviderVersion = "tor 0.4.7.13".parse().unwrap();
+        assert_eq!(version.product, "tor");
+        assert_eq!(
+            version.version,
+            TorVersion {
+                major: 0,
+                minor: 4,
+                micro: 7,
+                patch: 13,
+                status: None,
+                extra: None,
+            }
+        );
+        assert!(version.matches_crate_version());
+        assert_eq!(version.to_string(), "tor 0.4.7.13");
+
+        let version: ProviderVersion = "libtorrunner 0.3.5.1-alpha".parse().unwrap();
+        assert_eq!(version.product, "libtorrunner");
+        assert_eq!(version.version.status.as_deref(), Some("alpha"));
+        assert!(!version.matches_crate_version());
+
+        let version: ProviderVersion = "tor 0.4.8.0-alpha-dev (git-1234abcd)".parse().unwrap();
+        assert_eq!(version.version.micro, 8);
+        assert_eq!(version.version.status.as_deref(), Some("alpha-dev"));
+        assert_eq!(version.version.extra.as_deref(), Some("git-1234abcd"));
+        assert_eq!(version.to_string(), "tor 0.4.8.0-alpha-dev (git-1234abcd)");
+
+        assert!("tor".parse::<ProviderVersion>().is_err());
+        assert!("tor 0.4".parse::<ProviderVersion>().is_err());
+        assert!("tor 0.4.seven.13".parse::<ProviderVersion>().is_err());
+        assert!("tor 0.4.7.13 (git".parse::<ProviderVersion>().is_err());
+    }
+
+    #[test]
+    fn test_tor_version() {
+        let version = "0.4.7.13".parse::<TorVersion>().unwrap();
+        assert!(version.at_least(0, 4, 7, 0));
+        assert!(!version.at_least(0, 4, 8, 0));
+        assert!(version.matches_crate_version());
+        assert_eq!("0.4.8".parse::<TorVersion>().unwrap().patch, 0);
+        assert!("0.4".parse::<TorVersion>().is_err());
+    }
+
+    #[test]
+    fn test_provider_version() {
+        let version = provider_version().unwrap();
+        assert_eq!(version.product, "tor");
+        assert!(version.matches_crate_version());
+    }
+}